@@ -5,10 +5,13 @@ use glob::glob;
 fn main() {
     let mut compiler = shaderc::Compiler::new().expect("Failed to create shader compiler");
 
-    let patterns: Result<Vec<glob::Paths>, _> =
-        vec![glob("./src/shaders/*.vert"), glob("./src/shaders/*.frag")]
-            .into_iter()
-            .collect();
+    let patterns: Result<Vec<glob::Paths>, _> = vec![
+        glob("./src/shaders/*.vert"),
+        glob("./src/shaders/*.frag"),
+        glob("./src/shaders/*.comp"),
+    ]
+    .into_iter()
+    .collect();
 
     let patterns = patterns.expect("Failed to create glob pattern");
     let entries = patterns.into_iter().flatten();
@@ -31,6 +34,7 @@ fn main() {
             let shader_kind = match extension {
                 "vert" => shaderc::ShaderKind::Vertex,
                 "frag" => shaderc::ShaderKind::Fragment,
+                "comp" => shaderc::ShaderKind::Compute,
                 other => panic!("Failed to guess shader kind with extension {}", other),
             };
 
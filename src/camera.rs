@@ -63,6 +63,15 @@ impl CameraController {
         }
     }
 
+    /// Sets yaw and pitch so that the controller looks along `front`.
+    pub fn look_along(&mut self, front: Vec3) {
+        use cgmath::InnerSpace;
+
+        let front = front.normalize();
+        self.yaw = front.z.atan2(front.x);
+        self.pitch = front.y.asin();
+    }
+
     pub fn process_window_event(&mut self, event: &WindowEvent) -> bool {
         match event {
             WindowEvent::KeyboardInput {
@@ -0,0 +1,169 @@
+use std::path::Path;
+
+use crate::normals::{self, NormalMode};
+use crate::present_mode;
+
+/// Optional file read before the command line, with one `key = value` per line.
+const CONFIG_PATH: &str = "wgpu_learning.cfg";
+
+/// How the adapter and the swap chain are chosen, and which model is drawn.
+///
+/// Values come from `wgpu_learning.cfg` if it exists, then from the command line, using the
+/// same keys as flags: `--backend`, `--power`, `--adapter`, `--present-mode`, `--model` and
+/// `--normals`.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub backends: wgpu::BackendBit,
+    pub power_preference: wgpu::PowerPreference,
+    /// Case insensitive part of the adapter name, overriding the power preference.
+    pub adapter_name: Option<String>,
+    /// Preferred present mode, Fifo is used if the surface does not support it.
+    pub present_mode: wgpu::PresentMode,
+    /// OBJ, glTF or GLB file, the format is chosen from the extension.
+    pub model_path: String,
+    /// How normals are generated for OBJ meshes without them.
+    pub normal_mode: NormalMode,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            backends: wgpu::BackendBit::PRIMARY,
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            adapter_name: None,
+            present_mode: wgpu::PresentMode::Mailbox,
+            model_path: "res/cube/cube.obj".to_string(),
+            normal_mode: NormalMode::Smooth,
+        }
+    }
+}
+
+impl Config {
+    /// Reads the config file and the command line, panicking on invalid values.
+    pub fn load() -> Self {
+        let mut config = Config::default();
+
+        if Path::new(CONFIG_PATH).exists() {
+            let content = std::fs::read_to_string(CONFIG_PATH).expect("Failed to read config");
+            for (number, line) in content.lines().enumerate() {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                let (key, value) = match line.find('=') {
+                    Some(index) => (line[..index].trim(), line[index + 1..].trim()),
+                    None => panic!("{}:{}: expected `key = value`", CONFIG_PATH, number + 1),
+                };
+                if let Err(e) = config.set(key, value) {
+                    panic!("{}:{}: {}", CONFIG_PATH, number + 1, e);
+                }
+            }
+        }
+
+        let args: Vec<String> = std::env::args().collect();
+        for pair in args.windows(2) {
+            let key = match pair[0].strip_prefix("--") {
+                Some(key) => key,
+                None => continue,
+            };
+            if matches!(
+                key,
+                "backend" | "power" | "adapter" | "present-mode" | "model" | "normals"
+            ) {
+                if let Err(e) = config.set(key, &pair[1]) {
+                    panic!("--{}: {}", key, e);
+                }
+            }
+        }
+
+        config
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "backend" => {
+                self.backends =
+                    parse_backends(value).ok_or_else(|| format!("unknown backend {}", value))?
+            }
+            "power" => {
+                self.power_preference = parse_power_preference(value)
+                    .ok_or_else(|| format!("unknown power preference {}", value))?
+            }
+            "adapter" => self.adapter_name = Some(value.to_string()),
+            "present-mode" | "present_mode" => {
+                self.present_mode = present_mode::parse(value)
+                    .ok_or_else(|| format!("unknown present mode {}", value))?
+            }
+            "model" => self.model_path = value.to_string(),
+            "normals" => {
+                self.normal_mode =
+                    normals::parse(value).ok_or_else(|| format!("unknown normal mode {}", value))?
+            }
+            _ => return Err(format!("unknown key {}", key)),
+        }
+        Ok(())
+    }
+
+    /// Finds the adapter to use, either by name or by power preference.
+    pub async fn request_adapter(
+        &self,
+        instance: &wgpu::Instance,
+        compatible_surface: Option<&wgpu::Surface>,
+    ) -> Option<wgpu::Adapter> {
+        match &self.adapter_name {
+            Some(name) => {
+                let name = name.to_lowercase();
+                instance
+                    .enumerate_adapters(self.backends)
+                    .find(|adapter| adapter.get_info().name.to_lowercase().contains(&name))
+            }
+            None => {
+                instance
+                    .request_adapter(&wgpu::RequestAdapterOptions {
+                        power_preference: self.power_preference,
+                        compatible_surface,
+                    })
+                    .await
+            }
+        }
+    }
+}
+
+pub fn parse_backends(name: &str) -> Option<wgpu::BackendBit> {
+    match name.to_ascii_lowercase().as_str() {
+        "vulkan" => Some(wgpu::BackendBit::VULKAN),
+        "metal" => Some(wgpu::BackendBit::METAL),
+        "dx12" => Some(wgpu::BackendBit::DX12),
+        "dx11" => Some(wgpu::BackendBit::DX11),
+        "gl" => Some(wgpu::BackendBit::GL),
+        "primary" => Some(wgpu::BackendBit::PRIMARY),
+        "secondary" => Some(wgpu::BackendBit::SECONDARY),
+        "all" => Some(wgpu::BackendBit::all()),
+        _ => None,
+    }
+}
+
+pub fn parse_power_preference(name: &str) -> Option<wgpu::PowerPreference> {
+    match name.to_ascii_lowercase().as_str() {
+        "low" | "low-power" => Some(wgpu::PowerPreference::LowPower),
+        "high" | "high-performance" => Some(wgpu::PowerPreference::HighPerformance),
+        _ => None,
+    }
+}
+
+/// Prints every adapter of the configured backends, for `--list-adapters`.
+pub fn list_adapters(config: &Config) {
+    let instance = wgpu::Instance::new(config.backends);
+    let mut count = 0;
+    for adapter in instance.enumerate_adapters(config.backends) {
+        let info = adapter.get_info();
+        println!(
+            "{} ({:?}, {:?}, vendor {:#06x}, device {:#06x})",
+            info.name, info.backend, info.device_type, info.vendor, info.device
+        );
+        count += 1;
+    }
+    if count == 0 {
+        println!("No adapter found for {:?}", config.backends);
+    }
+}
@@ -0,0 +1,197 @@
+use cgmath::{EuclideanSpace, InnerSpace, Matrix};
+
+type Point3 = cgmath::Point3<f32>;
+type Vec4 = cgmath::Vector4<f32>;
+
+/// Where instances outside of the view frustum are discarded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CullingMode {
+    Disabled,
+    Cpu,
+    /// Compute shader culling feeding indirect draws, see [`crate::gpu_culling`].
+    Gpu,
+}
+
+/// Axis-aligned bounding box in model space.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Point3,
+    pub max: Point3,
+}
+
+impl Aabb {
+    /// Smallest box containing all the points, a degenerate box at the origin if empty.
+    pub fn from_points(points: impl Iterator<Item = Point3>) -> Self {
+        let mut bounds: Option<Aabb> = None;
+        for point in points {
+            bounds = Some(match bounds {
+                Some(b) => Aabb {
+                    min: Point3::new(
+                        b.min.x.min(point.x),
+                        b.min.y.min(point.y),
+                        b.min.z.min(point.z),
+                    ),
+                    max: Point3::new(
+                        b.max.x.max(point.x),
+                        b.max.y.max(point.y),
+                        b.max.z.max(point.z),
+                    ),
+                },
+                None => Aabb {
+                    min: point,
+                    max: point,
+                },
+            });
+        }
+
+        bounds.unwrap_or(Aabb {
+            min: Point3::new(0.0, 0.0, 0.0),
+            max: Point3::new(0.0, 0.0, 0.0),
+        })
+    }
+
+    pub fn center(&self) -> Point3 {
+        self.min.midpoint(self.max)
+    }
+
+    /// The eight corners, bits 0, 1 and 2 of the index picking the max X, Y and Z.
+    pub fn corners(&self) -> [Point3; 8] {
+        let (min, max) = (self.min, self.max);
+        let mut corners = [min; 8];
+        for (index, corner) in corners.iter_mut().enumerate() {
+            *corner = Point3::new(
+                if index & 1 == 0 { min.x } else { max.x },
+                if index & 2 == 0 { min.y } else { max.y },
+                if index & 4 == 0 { min.z } else { max.z },
+            );
+        }
+        corners
+    }
+
+    /// Radius of the sphere centered on the box and containing it.
+    pub fn radius(&self) -> f32 {
+        (self.max - self.min).magnitude() * 0.5
+    }
+}
+
+/// The six planes of a view frustum, with normals pointing inside.
+#[derive(Debug, Clone, Copy)]
+pub struct Frustum {
+    planes: [Vec4; 6],
+}
+
+impl Frustum {
+    /// Extracts the planes of a wgpu view-projection matrix (depth in `[0, 1]`).
+    pub fn from_matrix(view_proj: cgmath::Matrix4<f32>) -> Self {
+        let row = |i: usize| view_proj.row(i);
+        let mut planes = [
+            row(3) + row(0), // left
+            row(3) - row(0), // right
+            row(3) + row(1), // bottom
+            row(3) - row(1), // top
+            row(2),          // near
+            row(3) - row(2), // far
+        ];
+        for plane in planes.iter_mut() {
+            *plane /= plane.truncate().magnitude();
+        }
+
+        Frustum { planes }
+    }
+
+    /// Planes as `(normal, distance)` vectors, for upload to the GPU.
+    pub fn planes(&self) -> [[f32; 4]; 6] {
+        let mut planes = [[0.0; 4]; 6];
+        for (raw, plane) in planes.iter_mut().zip(self.planes.iter()) {
+            *raw = (*plane).into();
+        }
+        planes
+    }
+
+    pub fn intersects_sphere(&self, center: Point3, radius: f32) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.truncate().dot(center.to_vec()) + plane.w >= -radius)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::OPENGL_TO_WGPU_MATRIX;
+
+    type Vec3 = cgmath::Vector3<f32>;
+
+    /// Looking down -Z with a 90° field of view, so that the side planes are at 45°.
+    fn frustum() -> Frustum {
+        let view = cgmath::Matrix4::look_at_rh(
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(0.0, 0.0, -1.0),
+            Vec3::unit_y(),
+        );
+        let proj = cgmath::perspective(cgmath::Deg(90.0), 1.0, 1.0, 10.0);
+        Frustum::from_matrix(OPENGL_TO_WGPU_MATRIX * proj * view)
+    }
+
+    /// A point on each plane and the normal pointing outside of the frustum there.
+    fn plane_points() -> [(&'static str, Point3, Vec3); 6] {
+        let diagonal = |x, y, z| Vec3::new(x, y, z).normalize();
+        [
+            (
+                "left",
+                Point3::new(-5.0, 0.0, -5.0),
+                diagonal(-1.0, 0.0, 1.0),
+            ),
+            (
+                "right",
+                Point3::new(5.0, 0.0, -5.0),
+                diagonal(1.0, 0.0, 1.0),
+            ),
+            (
+                "bottom",
+                Point3::new(0.0, -5.0, -5.0),
+                diagonal(0.0, -1.0, 1.0),
+            ),
+            ("top", Point3::new(0.0, 5.0, -5.0), diagonal(0.0, 1.0, 1.0)),
+            ("near", Point3::new(0.0, 0.0, -1.0), Vec3::unit_z()),
+            ("far", Point3::new(0.0, 0.0, -10.0), -Vec3::unit_z()),
+        ]
+    }
+
+    #[test]
+    fn sphere_inside_each_plane_intersects() {
+        let frustum = frustum();
+        for (name, point, outward) in plane_points().iter() {
+            assert!(
+                frustum.intersects_sphere(point - outward * 2.0, 1.0),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn sphere_outside_each_plane_is_culled() {
+        let frustum = frustum();
+        for (name, point, outward) in plane_points().iter() {
+            assert!(
+                !frustum.intersects_sphere(point + outward * 2.0, 1.0),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn sphere_straddling_each_plane_intersects() {
+        let frustum = frustum();
+        for (name, point, outward) in plane_points().iter() {
+            // Center outside the plane, but closer to it than the radius
+            assert!(
+                frustum.intersects_sphere(point + outward * 0.5, 1.0),
+                "{}",
+                name
+            );
+        }
+    }
+}
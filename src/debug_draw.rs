@@ -0,0 +1,524 @@
+use cgmath::{EuclideanSpace, InnerSpace, SquareMatrix};
+use memoffset::offset_of;
+use once_cell::sync::Lazy;
+
+use crate::{culling::Aabb, texture::Texture};
+
+type Point3 = cgmath::Point3<f32>;
+type Matrix4 = cgmath::Matrix4<f32>;
+
+pub type Color = [f32; 4];
+
+pub const RED: Color = [1.0, 0.0, 0.0, 1.0];
+pub const GREEN: Color = [0.0, 1.0, 0.0, 1.0];
+pub const BLUE: Color = [0.0, 0.0, 1.0, 1.0];
+
+const SPHERE_SEGMENTS: usize = 32;
+const INITIAL_CAPACITY: usize = 1024;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct LineVertex {
+    position: [f32; 3],
+    color: Color,
+}
+
+impl LineVertex {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        static ATTRIBUTES: Lazy<[wgpu::VertexAttribute; 2]> = Lazy::new(|| {
+            [
+                wgpu::VertexAttribute {
+                    format: wgpu::VertexFormat::Float3,
+                    shader_location: 0,
+                    offset: offset_of!(LineVertex, position) as _,
+                },
+                wgpu::VertexAttribute {
+                    format: wgpu::VertexFormat::Float4,
+                    shader_location: 1,
+                    offset: offset_of!(LineVertex, color) as _,
+                },
+            ]
+        });
+
+        wgpu::VertexBufferLayout {
+            step_mode: wgpu::InputStepMode::Vertex,
+            array_stride: std::mem::size_of::<Self>() as wgpu::BufferAddress,
+            attributes: &*ATTRIBUTES,
+        }
+    }
+}
+
+/// Built-in visualizations toggled from the UI.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DebugOverlays {
+    pub axes: bool,
+    pub grid: bool,
+    pub bounds: bool,
+    pub light: bool,
+    /// View-projection of a camera pose kept on screen, for looking at it from elsewhere.
+    pub frozen_frustum: Option<Matrix4>,
+}
+
+/// Immediate mode line drawing, for visualizing things that are not part of the scene.
+///
+/// Lines accumulate until [`DebugDraw::clear`], usually once per frame. Each call picks
+/// whether its lines are hidden behind the scene or drawn on top of it.
+pub struct DebugDraw {
+    depth_tested: Vec<LineVertex>,
+    overlay: Vec<LineVertex>,
+    dirty: bool,
+
+    // Depth tested lines come first, followed by the overlay lines
+    vertex_buffer: wgpu::Buffer,
+    vertex_capacity: usize,
+    uploaded_depth_tested: u32,
+    uploaded_overlay: u32,
+
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+
+    pipeline_layout: wgpu::PipelineLayout,
+    vs_module: wgpu::ShaderModule,
+    fs_module: wgpu::ShaderModule,
+    sample_count: u32,
+    depth_tested_pipeline: wgpu::RenderPipeline,
+    overlay_pipeline: wgpu::RenderPipeline,
+}
+
+impl DebugDraw {
+    pub fn new(device: &wgpu::Device, sample_count: u32) -> Self {
+        let vertex_buffer = Self::create_vertex_buffer(device, INITIAL_CAPACITY);
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Debug draw uniform buffer"),
+            size: std::mem::size_of::<[[f32; 4]; 4]>() as _,
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStage::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("Debug draw bind group layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer {
+                    buffer: &uniform_buffer,
+                    offset: 0,
+                    size: None,
+                },
+            }],
+            label: Some("Debug draw bind group"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Debug draw pipeline layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let vs_module = device.create_shader_module(&wgpu::include_spirv!(concat!(
+            env!("OUT_DIR"),
+            "/debug_lines.vert.spv"
+        )));
+        let fs_module = device.create_shader_module(&wgpu::include_spirv!(concat!(
+            env!("OUT_DIR"),
+            "/debug_lines.frag.spv"
+        )));
+
+        let depth_tested_pipeline = Self::create_pipeline(
+            device,
+            &pipeline_layout,
+            &vs_module,
+            &fs_module,
+            true,
+            sample_count,
+        );
+        let overlay_pipeline = Self::create_pipeline(
+            device,
+            &pipeline_layout,
+            &vs_module,
+            &fs_module,
+            false,
+            sample_count,
+        );
+
+        DebugDraw {
+            depth_tested: Vec::new(),
+            overlay: Vec::new(),
+            dirty: false,
+
+            vertex_buffer,
+            vertex_capacity: INITIAL_CAPACITY,
+            uploaded_depth_tested: 0,
+            uploaded_overlay: 0,
+
+            uniform_buffer,
+            bind_group,
+
+            pipeline_layout,
+            vs_module,
+            fs_module,
+            sample_count,
+            depth_tested_pipeline,
+            overlay_pipeline,
+        }
+    }
+
+    fn create_vertex_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Debug line vertex buffer"),
+            size: (capacity * std::mem::size_of::<LineVertex>()) as _,
+            usage: wgpu::BufferUsage::VERTEX | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        vs_module: &wgpu::ShaderModule,
+        fs_module: &wgpu::ShaderModule,
+        depth_test: bool,
+        sample_count: u32,
+    ) -> wgpu::RenderPipeline {
+        let label = if depth_test {
+            "Debug line render pipeline"
+        } else {
+            "Debug overlay line render pipeline"
+        };
+        let depth_compare = if depth_test {
+            wgpu::CompareFunction::LessEqual
+        } else {
+            wgpu::CompareFunction::Always
+        };
+
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(label),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: vs_module,
+                entry_point: "main",
+                buffers: &[LineVertex::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: fs_module,
+                entry_point: "main",
+                targets: &[wgpu::ColorTargetState {
+                    format: Texture::HDR_FORMAT,
+                    color_blend: wgpu::BlendState {
+                        src_factor: wgpu::BlendFactor::SrcAlpha,
+                        dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                    alpha_blend: wgpu::BlendState::REPLACE,
+                    write_mask: wgpu::ColorWrite::ALL,
+                }],
+            }),
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Texture::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+                clamp_depth: false,
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::LineList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: wgpu::CullMode::None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+            },
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+        })
+    }
+
+    /// Recreates the pipelines when the scene render targets change their sample count.
+    pub fn set_sample_count(&mut self, device: &wgpu::Device, sample_count: u32) {
+        if sample_count == self.sample_count {
+            return;
+        }
+
+        self.sample_count = sample_count;
+        self.depth_tested_pipeline = Self::create_pipeline(
+            device,
+            &self.pipeline_layout,
+            &self.vs_module,
+            &self.fs_module,
+            true,
+            sample_count,
+        );
+        self.overlay_pipeline = Self::create_pipeline(
+            device,
+            &self.pipeline_layout,
+            &self.vs_module,
+            &self.fs_module,
+            false,
+            sample_count,
+        );
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.uploaded_depth_tested == 0 && self.uploaded_overlay == 0
+    }
+
+    /// Forgets the lines of the previous frame.
+    pub fn clear(&mut self) {
+        self.depth_tested.clear();
+        self.overlay.clear();
+        self.dirty = true;
+    }
+
+    pub fn line(&mut self, from: Point3, to: Point3, color: Color, depth_test: bool) {
+        let vertices = if depth_test {
+            &mut self.depth_tested
+        } else {
+            &mut self.overlay
+        };
+        vertices.push(LineVertex {
+            position: from.into(),
+            color,
+        });
+        vertices.push(LineVertex {
+            position: to.into(),
+            color,
+        });
+        self.dirty = true;
+    }
+
+    /// Draws the twelve edges of a box, corners being indexed by the bits of their offsets.
+    ///
+    /// Bits 0 and 1 walk around one face and bit 2 moves to the opposite face.
+    fn box_edges(&mut self, corners: &[Point3; 8], color: Color, depth_test: bool) {
+        const EDGES: [(usize, usize); 12] = [
+            (0, 1),
+            (1, 3),
+            (3, 2),
+            (2, 0),
+            (4, 5),
+            (5, 7),
+            (7, 6),
+            (6, 4),
+            (0, 4),
+            (1, 5),
+            (2, 6),
+            (3, 7),
+        ];
+
+        for &(a, b) in EDGES.iter() {
+            self.line(corners[a], corners[b], color, depth_test);
+        }
+    }
+
+    pub fn aabb(&mut self, bounds: &Aabb, color: Color, depth_test: bool) {
+        self.box_edges(&bounds.corners(), color, depth_test);
+    }
+
+    /// Draws a sphere as three circles, one around each axis.
+    pub fn sphere(&mut self, center: Point3, radius: f32, color: Color, depth_test: bool) {
+        let point = |angle: f32, axis: usize| {
+            let (sin, cos) = angle.sin_cos();
+            let offset = match axis {
+                0 => cgmath::Vector3::new(0.0, cos, sin),
+                1 => cgmath::Vector3::new(cos, 0.0, sin),
+                _ => cgmath::Vector3::new(cos, sin, 0.0),
+            };
+            center + offset * radius
+        };
+
+        let step = std::f32::consts::PI * 2.0 / SPHERE_SEGMENTS as f32;
+        for axis in 0..3 {
+            for segment in 0..SPHERE_SEGMENTS {
+                let from = point(segment as f32 * step, axis);
+                let to = point((segment + 1) as f32 * step, axis);
+                self.line(from, to, color, depth_test);
+            }
+        }
+    }
+
+    /// Draws the volume seen through a wgpu view-projection matrix (depth in `[0, 1]`).
+    pub fn frustum(&mut self, view_proj: Matrix4, color: Color, depth_test: bool) {
+        let inverse = match view_proj.invert() {
+            Some(inverse) => inverse,
+            None => return,
+        };
+
+        let mut corners = [Point3::origin(); 8];
+        for (index, corner) in corners.iter_mut().enumerate() {
+            let x = if index & 1 == 0 { -1.0 } else { 1.0 };
+            let y = if index & 2 == 0 { -1.0 } else { 1.0 };
+            let z = if index & 4 == 0 { 0.0 } else { 1.0 };
+            let world = inverse * cgmath::Vector4::new(x, y, z, 1.0);
+            *corner = Point3::from_homogeneous(world);
+        }
+        self.box_edges(&corners, color, depth_test);
+    }
+
+    /// Draws the X, Y and Z axes in red, green and blue.
+    pub fn axes(&mut self, origin: Point3, size: f32, depth_test: bool) {
+        self.line(
+            origin,
+            origin + cgmath::Vector3::unit_x() * size,
+            RED,
+            depth_test,
+        );
+        self.line(
+            origin,
+            origin + cgmath::Vector3::unit_y() * size,
+            GREEN,
+            depth_test,
+        );
+        self.line(
+            origin,
+            origin + cgmath::Vector3::unit_z() * size,
+            BLUE,
+            depth_test,
+        );
+    }
+
+    /// Draws a square grid on the horizontal plane going through `center`.
+    pub fn grid(
+        &mut self,
+        center: Point3,
+        size: f32,
+        divisions: u32,
+        color: Color,
+        depth_test: bool,
+    ) {
+        let divisions = divisions.max(1);
+        let half_size = size * 0.5;
+        let step = size / divisions as f32;
+        for line in 0..=divisions {
+            let offset = -half_size + line as f32 * step;
+            self.line(
+                center + cgmath::Vector3::new(offset, 0.0, -half_size),
+                center + cgmath::Vector3::new(offset, 0.0, half_size),
+                color,
+                depth_test,
+            );
+            self.line(
+                center + cgmath::Vector3::new(-half_size, 0.0, offset),
+                center + cgmath::Vector3::new(half_size, 0.0, offset),
+                color,
+                depth_test,
+            );
+        }
+    }
+
+    /// Draws an arrow from `from` to `to`, with a small head at `to`.
+    pub fn arrow(&mut self, from: Point3, to: Point3, color: Color, depth_test: bool) {
+        self.line(from, to, color, depth_test);
+
+        let direction = to - from;
+        let length = direction.magnitude();
+        if length == 0.0 {
+            return;
+        }
+
+        let direction = direction / length;
+        let reference = if direction.y.abs() < 0.99 {
+            cgmath::Vector3::unit_y()
+        } else {
+            cgmath::Vector3::unit_x()
+        };
+        let side = direction.cross(reference).normalize();
+        let up = side.cross(direction);
+        let head = length * 0.1;
+        for &offset in [side, -side, up, -up].iter() {
+            self.line(
+                to,
+                to - direction * head + offset * head * 0.5,
+                color,
+                depth_test,
+            );
+        }
+    }
+
+    /// Sends this frame's lines and camera to the GPU, growing the vertex buffer if needed.
+    pub fn upload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, view_proj: Matrix4) {
+        let view_proj: [[f32; 4]; 4] = view_proj.into();
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[view_proj]));
+
+        if !self.dirty {
+            return;
+        }
+        self.dirty = false;
+
+        let vertex_count = self.depth_tested.len() + self.overlay.len();
+        if vertex_count > self.vertex_capacity {
+            self.vertex_capacity = vertex_count.next_power_of_two();
+            self.vertex_buffer = Self::create_vertex_buffer(device, self.vertex_capacity);
+        }
+
+        let vertex_size = std::mem::size_of::<LineVertex>() as wgpu::BufferAddress;
+        queue.write_buffer(
+            &self.vertex_buffer,
+            0,
+            bytemuck::cast_slice(&self.depth_tested),
+        );
+        queue.write_buffer(
+            &self.vertex_buffer,
+            self.depth_tested.len() as wgpu::BufferAddress * vertex_size,
+            bytemuck::cast_slice(&self.overlay),
+        );
+        self.uploaded_depth_tested = self.depth_tested.len() as u32;
+        self.uploaded_overlay = self.overlay.len() as u32;
+    }
+
+    /// Draws the uploaded lines over the scene color and depth targets.
+    pub fn encode(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        attachment: &wgpu::TextureView,
+        resolve_target: Option<&wgpu::TextureView>,
+        depth: &wgpu::TextureView,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Debug line render pass"),
+            color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
+                attachment,
+                resolve_target,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachmentDescriptor {
+                attachment: depth,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+
+        let overlay_start = self.uploaded_depth_tested;
+        if overlay_start > 0 {
+            render_pass.set_pipeline(&self.depth_tested_pipeline);
+            render_pass.draw(0..overlay_start, 0..1);
+        }
+        if self.uploaded_overlay > 0 {
+            render_pass.set_pipeline(&self.overlay_pipeline);
+            render_pass.draw(overlay_start..overlay_start + self.uploaded_overlay, 0..1);
+        }
+    }
+}
@@ -0,0 +1,454 @@
+use std::{
+    error::Error,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use cgmath::{InnerSpace, Matrix, Matrix3, Matrix4, SquareMatrix, Vector3};
+use image::DynamicImage;
+
+use crate::model::{self, white_image, LoadWarning, LoadedMesh, MaterialData, MeshData, ModelData};
+use crate::normals::{self, NormalMode};
+use crate::vertex::ModelVertex;
+
+/// Why a glTF or GLB file could not be loaded.
+///
+/// Textures that cannot be read are only warnings, see [`load`].
+#[derive(Debug)]
+pub enum GltfError {
+    /// The file or one of its buffers could not be read or parsed.
+    Gltf(gltf::Error),
+    /// The file has no scene to draw.
+    NoScene,
+    /// A triangle primitive of the mesh has no positions.
+    NoPositions { mesh: String },
+    /// A triangle primitive of the mesh points past its vertices.
+    IndexOutOfRange { mesh: String, index: u32 },
+    /// The scene has no triangle to draw, only points, lines or nothing.
+    NoGeometry,
+}
+
+impl fmt::Display for GltfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GltfError::Gltf(e) => write!(f, "invalid glTF file: {}", e),
+            GltfError::NoScene => write!(f, "glTF file has no scene"),
+            GltfError::NoPositions { mesh } => {
+                write!(f, "{} has a primitive without positions", mesh)
+            }
+            GltfError::IndexOutOfRange { mesh, index } => {
+                write!(f, "{} has an out of range index {}", mesh, index)
+            }
+            GltfError::NoGeometry => write!(f, "glTF scene has no triangles"),
+        }
+    }
+}
+
+impl Error for GltfError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            GltfError::Gltf(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Reads a glTF or GLB file, with its external or embedded buffers and textures.
+///
+/// Node transforms are applied to the vertices, so every triangle primitive of the
+/// default scene becomes one mesh. Texture coordinates are read from the set used by
+/// the base color texture, the only texture the shaders sample. A texture that cannot
+/// be read is replaced by a white one and returned as a warning next to the model.
+pub fn load(path: &Path) -> Result<(ModelData, Vec<LoadWarning>), GltfError> {
+    let gltf::Gltf { document, blob } = gltf::Gltf::open(path).map_err(GltfError::Gltf)?;
+    let base = path.parent().unwrap_or_else(|| Path::new(""));
+    let buffers = load_buffers(&document, base, blob).map_err(GltfError::Gltf)?;
+
+    let mut warnings = Vec::new();
+    // Decoded on first use, as only base color textures are sampled
+    let mut images = vec![None; document.images().len()];
+    let mut materials: Vec<_> = document
+        .materials()
+        .map(|material| load_material(&material, base, &buffers, &mut images, &mut warnings))
+        .collect();
+    // Used by primitives without a material
+    let default_material = materials.len();
+    let uses_default_material = document
+        .meshes()
+        .flat_map(|mesh| mesh.primitives())
+        .any(|primitive| primitive.material().index().is_none());
+    // Dielectric and rough, rather than the fully metallic default of the glTF
+    // specification which the Blinn-Phong shader would render almost black
+    if uses_default_material {
+        materials.push(MaterialData::default_material(
+            model::shininess_from_roughness(1.0),
+        ));
+    }
+
+    let scene = document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+        .ok_or(GltfError::NoScene)?;
+
+    let mut meshes = Vec::new();
+    for node in scene.nodes() {
+        load_node(
+            &node,
+            Matrix4::identity(),
+            &buffers,
+            default_material,
+            &mut meshes,
+        )?;
+    }
+    if meshes.is_empty() {
+        return Err(GltfError::NoGeometry);
+    }
+
+    let model = ModelData::from_meshes(format!("{:?}", path), meshes, materials);
+    Ok((model, warnings))
+}
+
+fn load_node(
+    node: &gltf::Node,
+    parent_transform: Matrix4<f32>,
+    buffers: &[gltf::buffer::Data],
+    default_material: usize,
+    meshes: &mut Vec<LoadedMesh>,
+) -> Result<(), GltfError> {
+    let transform = parent_transform * Matrix4::from(node.transform().matrix());
+
+    if let Some(mesh) = node.mesh() {
+        let name = mesh
+            .name()
+            .or_else(|| node.name())
+            .map_or_else(|| format!("Mesh {}", mesh.index()), str::to_string);
+
+        for primitive in mesh.primitives() {
+            // Points and lines cannot be drawn by the triangle pipelines
+            if primitive.mode() != gltf::mesh::Mode::Triangles {
+                continue;
+            }
+
+            let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()].0[..]));
+            let positions: Vec<[f32; 3]> = reader
+                .read_positions()
+                .ok_or_else(|| GltfError::NoPositions { mesh: name.clone() })?
+                .collect();
+            // Attributes shorter than the positions are ignored rather than misread
+            let vertex_normals: Option<Vec<[f32; 3]>> = reader
+                .read_normals()
+                .map(Iterator::collect)
+                .filter(|normals: &Vec<_>| normals.len() == positions.len());
+            let tex_coord_set = primitive
+                .material()
+                .pbr_metallic_roughness()
+                .base_color_texture()
+                .map_or(0, |info| info.tex_coord());
+            let tex_coords: Vec<[f32; 2]> = reader
+                .read_tex_coords(tex_coord_set)
+                .map(|tex_coords| tex_coords.into_f32().collect())
+                .filter(|tex_coords: &Vec<_>| tex_coords.len() == positions.len())
+                .unwrap_or_else(|| vec![[0.0; 2]; positions.len()]);
+            let mut indices: Vec<u32> = match reader.read_indices() {
+                Some(indices) => indices.into_u32().collect(),
+                None => (0..positions.len() as u32).collect(),
+            };
+            if let Some(&index) = indices.iter().find(|&&i| i as usize >= positions.len()) {
+                return Err(GltfError::IndexOutOfRange {
+                    mesh: name.clone(),
+                    index,
+                });
+            }
+            indices.truncate(indices.len() / 3 * 3);
+
+            let normal_transform = normal_matrix(&transform);
+            let mut vertices: Vec<ModelVertex> = positions
+                .iter()
+                .zip(&tex_coords)
+                .enumerate()
+                .map(|(i, (&position, &tex_coords))| {
+                    let position = transform * Vector3::from(position).extend(1.0);
+                    let normal = vertex_normals.as_ref().map_or([0.0; 3], |vertex_normals| {
+                        (normal_transform * Vector3::from(vertex_normals[i]))
+                            .normalize()
+                            .into()
+                    });
+                    ModelVertex {
+                        position: position.truncate().into(),
+                        tex_coords,
+                        normal,
+                    }
+                })
+                .collect();
+
+            // Mirroring transforms turn the triangles inside out
+            if transform.determinant() < 0.0 {
+                for triangle in indices.chunks_exact_mut(3) {
+                    triangle.swap(1, 2);
+                }
+            }
+
+            // glTF asks for flat normals when a primitive has none
+            if vertex_normals.is_none() {
+                normals::generate(NormalMode::Flat, &mut vertices, &mut indices);
+            }
+
+            meshes.push((
+                MeshData {
+                    name: name.clone(),
+                    vertices,
+                    lods: Vec::new(),
+                    material_index: primitive.material().index().unwrap_or(default_material),
+                },
+                indices,
+            ));
+        }
+    }
+
+    for child in node.children() {
+        load_node(&child, transform, buffers, default_material, meshes)?;
+    }
+    Ok(())
+}
+
+/// Inverse transpose of the upper 3x3 of `transform`, keeping normals perpendicular
+/// to scaled surfaces.
+fn normal_matrix(transform: &Matrix4<f32>) -> Matrix3<f32> {
+    let linear = Matrix3::from_cols(
+        transform.x.truncate(),
+        transform.y.truncate(),
+        transform.z.truncate(),
+    );
+    linear
+        .invert()
+        .map_or(linear, |inverse| inverse.transpose())
+}
+
+/// Converts a metallic-roughness material to the parameters of the Blinn-Phong shader.
+fn load_material(
+    material: &gltf::Material,
+    base: &Path,
+    buffers: &[gltf::buffer::Data],
+    images: &mut [Option<DynamicImage>],
+    warnings: &mut Vec<LoadWarning>,
+) -> MaterialData {
+    let pbr = material.pbr_metallic_roughness();
+    let diffuse_image = match pbr.base_color_texture() {
+        Some(info) => {
+            let source = info.texture().source();
+            images[source.index()]
+                .get_or_insert_with(|| {
+                    load_image(&source, base, buffers).unwrap_or_else(|error| {
+                        let name = match source.source() {
+                            gltf::image::Source::Uri { uri, .. } => uri.to_string(),
+                            gltf::image::Source::View { .. } => {
+                                format!("image {}", source.index())
+                            }
+                        };
+                        warnings.push(LoadWarning::Texture { name, error });
+                        white_image()
+                    })
+                })
+                .clone()
+        }
+        None => white_image(),
+    };
+
+    let mut base_color = pbr.base_color_factor();
+    // Masked materials are drawn opaque, with fragments below the cutoff discarded
+    let (transparent, alpha_cutoff) = match material.alpha_mode() {
+        gltf::material::AlphaMode::Opaque => {
+            base_color[3] = 1.0;
+            (false, 0.0)
+        }
+        gltf::material::AlphaMode::Mask => (false, material.alpha_cutoff()),
+        gltf::material::AlphaMode::Blend => (true, 0.0),
+    };
+
+    MaterialData {
+        name: material.name().map_or_else(
+            || format!("Material {}", material.index().unwrap_or(0)),
+            str::to_string,
+        ),
+        diffuse_image,
+        opacity: 1.0,
+        transparent,
+        base_color,
+        emissive: material.emissive_factor(),
+        metallic: pbr.metallic_factor(),
+        shininess: model::shininess_from_roughness(pbr.roughness_factor()),
+        alpha_cutoff,
+    }
+}
+
+/// Reads the buffers of the document, from their files, data URIs or the GLB blob.
+fn load_buffers(
+    document: &gltf::Document,
+    base: &Path,
+    mut blob: Option<Vec<u8>>,
+) -> Result<Vec<gltf::buffer::Data>, gltf::Error> {
+    document
+        .buffers()
+        .map(|buffer| {
+            let data = match buffer.source() {
+                gltf::buffer::Source::Uri(uri) => read_uri(base, uri)?,
+                gltf::buffer::Source::Bin => blob.take().ok_or(gltf::Error::MissingBlob)?,
+            };
+            if data.len() < buffer.length() {
+                return Err(gltf::Error::BufferLength {
+                    buffer: buffer.index(),
+                    expected: buffer.length(),
+                    actual: data.len(),
+                });
+            }
+            Ok(gltf::buffer::Data(data))
+        })
+        .collect()
+}
+
+/// Decodes an image from its file, data URI or buffer view, guessing its format from
+/// its content.
+fn load_image(
+    gltf_image: &gltf::Image,
+    base: &Path,
+    buffers: &[gltf::buffer::Data],
+) -> Result<DynamicImage, Box<dyn Error>> {
+    let image = match gltf_image.source() {
+        gltf::image::Source::Uri { uri, .. } => image::load_from_memory(&read_uri(base, uri)?)?,
+        gltf::image::Source::View { view, .. } => {
+            let start = view.offset();
+            let bytes = buffers[view.buffer().index()]
+                .get(start..start + view.length())
+                .ok_or("image buffer view is out of range")?;
+            image::load_from_memory(bytes)?
+        }
+    };
+    Ok(image)
+}
+
+/// Reads a base64 data URI, or a file relative to `base` or with a `file:` scheme.
+fn read_uri(base: &Path, uri: &str) -> Result<Vec<u8>, gltf::Error> {
+    if let Some(data) = uri.strip_prefix("data:") {
+        let (_, encoded) = data
+            .split_once(";base64,")
+            .ok_or(gltf::Error::UnsupportedScheme)?;
+        return base64::decode(encoded).map_err(gltf::Error::Base64);
+    }
+    let path = match uri
+        .strip_prefix("file://")
+        .or_else(|| uri.strip_prefix("file:"))
+    {
+        Some(path) => PathBuf::from(path),
+        None if uri.contains(':') => return Err(gltf::Error::UnsupportedScheme),
+        None => base.join(uri),
+    };
+    std::fs::read(path).map_err(gltf::Error::Io)
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::Point3;
+
+    use super::*;
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/gltf")
+            .join(name)
+    }
+
+    fn load_fixture(name: &str) -> ModelData {
+        load(&fixture(name)).unwrap().0
+    }
+
+    fn positions(mesh: &MeshData) -> Vec<[f32; 3]> {
+        mesh.vertices.iter().map(|v| v.position).collect()
+    }
+
+    /// Normal of the first triangle from its winding, counter-clockwise being the front.
+    fn face_normal(mesh: &MeshData) -> Vector3<f32> {
+        let corner = |i: usize| Point3::from(mesh.vertices[mesh.lods[0][i] as usize].position);
+        (corner(1) - corner(0))
+            .cross(corner(2) - corner(0))
+            .normalize()
+    }
+
+    #[test]
+    fn node_transforms_are_combined_down_the_hierarchy() {
+        let data = load_fixture("hierarchy.gltf");
+        assert_eq!(data.meshes.len(), 1);
+        let mesh = &data.meshes[0];
+        assert_eq!(mesh.name, "Triangle");
+        assert_eq!(
+            positions(mesh),
+            vec![[1.0, 2.0, 6.0], [3.0, 2.0, 6.0], [1.0, 4.0, 6.0]]
+        );
+        for vertex in &mesh.vertices {
+            assert_eq!(vertex.normal, [0.0, 0.0, 1.0]);
+        }
+    }
+
+    #[test]
+    fn mirrored_transform_keeps_triangles_facing_their_normals() {
+        let data = load_fixture("mirrored.gltf");
+        let mesh = &data.meshes[0];
+        assert_eq!(mesh.lods[0], vec![0, 2, 1]);
+        for vertex in &mesh.vertices {
+            assert_eq!(vertex.normal, [0.0, 0.0, -1.0]);
+        }
+        assert_eq!(face_normal(mesh), Vector3::new(0.0, 0.0, -1.0));
+    }
+
+    #[test]
+    fn missing_normals_are_flat() {
+        let data = load_fixture("missing_normals.gltf");
+        let mesh = &data.meshes[0];
+        assert_eq!(mesh.vertices.len(), 3);
+        let normal: [f32; 3] = face_normal(mesh).into();
+        for vertex in &mesh.vertices {
+            assert_eq!(vertex.normal, normal);
+        }
+        assert_eq!(face_normal(mesh), Vector3::new(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn masked_material_is_opaque_with_a_cutoff() {
+        let data = load_fixture("mask_tex_coord_1.gltf");
+        let material = &data.materials[data.meshes[0].material_index];
+        assert_eq!(material.name, "Cutout");
+        assert!(!material.transparent);
+        assert_eq!(material.alpha_cutoff, 0.3);
+    }
+
+    #[test]
+    fn tex_coords_come_from_the_set_of_the_base_color_texture() {
+        let data = load_fixture("mask_tex_coord_1.gltf");
+        let tex_coords: Vec<_> = data.meshes[0]
+            .vertices
+            .iter()
+            .map(|v| v.tex_coords)
+            .collect();
+        assert_eq!(tex_coords, vec![[0.25, 0.5], [0.75, 0.5], [0.25, 1.0]]);
+    }
+
+    #[test]
+    fn missing_texture_is_replaced_by_white() {
+        let (data, warnings) = load(&fixture("missing_texture.gltf")).unwrap();
+        let material = &data.materials[data.meshes[0].material_index];
+        assert_eq!(material.name, "Textured");
+        assert_eq!(
+            material.diffuse_image.to_rgba8().into_raw(),
+            vec![u8::MAX; 4]
+        );
+        match &warnings[..] {
+            [LoadWarning::Texture { name, .. }] => assert_eq!(name, "missing.png"),
+            warnings => panic!("unexpected warnings {:?}", warnings),
+        }
+    }
+
+    #[test]
+    fn missing_file_is_an_error() {
+        let result = load(&fixture("does_not_exist.gltf"));
+        assert!(matches!(result, Err(GltfError::Gltf(gltf::Error::Io(_)))));
+    }
+}
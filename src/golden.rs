@@ -0,0 +1,307 @@
+use std::path::PathBuf;
+
+use image::{Rgba, RgbaImage};
+use winit::dpi::PhysicalSize;
+
+use crate::{
+    camera::Camera,
+    config::Config,
+    culling::Aabb,
+    debug_draw::{DebugDraw, GREEN, RED},
+    state::State,
+};
+
+const GOLDEN_SIZE: PhysicalSize<u32> = PhysicalSize {
+    width: 320,
+    height: 240,
+};
+
+struct GoldenScene {
+    name: &'static str,
+    eye: [f32; 3],
+    front: [f32; 3],
+    /// Draws [`add_debug_lines`] over the scene.
+    debug_lines: bool,
+}
+
+const SCENES: &[GoldenScene] = &[
+    GoldenScene {
+        name: "default",
+        eye: [0.0, 1.0, 2.0],
+        front: [0.0, 0.0, -1.0],
+        debug_lines: false,
+    },
+    GoldenScene {
+        name: "overview",
+        eye: [0.0, 20.0, 25.0],
+        front: [0.0, -0.6, -0.8],
+        debug_lines: false,
+    },
+    GoldenScene {
+        name: "grid_corner",
+        eye: [-8.0, 2.0, -8.0],
+        front: [0.7, -0.1, 0.7],
+        debug_lines: false,
+    },
+    GoldenScene {
+        name: "debug_lines",
+        eye: [3.0, 3.0, 5.0],
+        front: [-0.45, -0.45, -0.77],
+        debug_lines: true,
+    },
+];
+
+pub struct GoldenOptions {
+    /// Folder containing the checked-in reference images.
+    pub reference_dir: PathBuf,
+    /// Folder receiving the rendered and diff images of failing scenes.
+    pub output_dir: PathBuf,
+    /// Maximum allowed difference per color channel before a pixel is considered different.
+    pub tolerance: u8,
+    /// Overwrite the reference images with the current rendering instead of comparing.
+    pub update: bool,
+    /// Adapter to render with, a software one gives the most stable results.
+    pub config: Config,
+}
+
+impl Default for GoldenOptions {
+    fn default() -> Self {
+        GoldenOptions {
+            reference_dir: PathBuf::from("res/golden"),
+            output_dir: PathBuf::from("target/golden"),
+            tolerance: 2,
+            update: false,
+            config: Config::default(),
+        }
+    }
+}
+
+struct Comparison {
+    mismatched_pixels: usize,
+    diff: RgbaImage,
+}
+
+/// Renders every golden scene offscreen and compares it with its reference image.
+///
+/// Returns `true` if all scenes match their reference.
+pub fn run(options: &GoldenOptions) -> bool {
+    let mut state = futures::executor::block_on(State::new_headless(GOLDEN_SIZE, &options.config));
+    let mut success = true;
+
+    for scene in SCENES {
+        state.set_camera(Camera {
+            eye: scene.eye.into(),
+            front: scene.front.into(),
+            up: cgmath::Vector3::unit_y(),
+            aspect: GOLDEN_SIZE.width as f32 / GOLDEN_SIZE.height as f32,
+            fovy: 45.0,
+            znear: 0.1,
+            zfar: 100.0,
+        });
+        if scene.debug_lines {
+            add_debug_lines(state.debug_draw());
+        }
+        state.render_offscreen();
+        let actual = state.read_frame().expect("Failed to read golden frame");
+        state.debug_draw().clear();
+
+        let reference_path = options.reference_dir.join(format!("{}.png", scene.name));
+
+        if options.update {
+            std::fs::create_dir_all(&options.reference_dir)
+                .expect("Failed to create reference folder");
+            actual
+                .save(&reference_path)
+                .expect("Failed to save reference image");
+            println!("{}: reference updated", scene.name);
+            continue;
+        }
+
+        let reference = match image::open(&reference_path) {
+            Ok(reference) => reference.to_rgba8(),
+            Err(e) => {
+                println!("{}: cannot open {:?}: {}", scene.name, reference_path, e);
+                success = false;
+                continue;
+            }
+        };
+
+        let comparison = match compare(&reference, &actual, options.tolerance) {
+            Some(comparison) => comparison,
+            None => {
+                println!(
+                    "{}: size mismatch, expected {:?} got {:?}",
+                    scene.name,
+                    reference.dimensions(),
+                    actual.dimensions()
+                );
+                success = false;
+                continue;
+            }
+        };
+
+        if comparison.mismatched_pixels == 0 {
+            println!("{}: ok", scene.name);
+            continue;
+        }
+
+        success = false;
+        println!(
+            "{}: {} pixels differ by more than {}",
+            scene.name, comparison.mismatched_pixels, options.tolerance
+        );
+
+        std::fs::create_dir_all(&options.output_dir).expect("Failed to create output folder");
+        actual
+            .save(options.output_dir.join(format!("{}.png", scene.name)))
+            .expect("Failed to save rendered image");
+        comparison
+            .diff
+            .save(options.output_dir.join(format!("{}.diff.png", scene.name)))
+            .expect("Failed to save diff image");
+    }
+
+    if !options.update {
+        success &= check_recovery(&mut state, options.tolerance);
+    }
+
+    success
+}
+
+/// A few [`DebugDraw`] shapes, hidden behind the scene or drawn on top of it.
+fn add_debug_lines(debug_draw: &mut DebugDraw) {
+    let origin = cgmath::Point3::new(0.0, 0.0, 0.0);
+    debug_draw.grid(origin, 4.0, 4, [0.5, 0.5, 0.5, 1.0], true);
+    debug_draw.aabb(
+        &Aabb {
+            min: cgmath::Point3::new(-1.0, 0.0, -1.0),
+            max: cgmath::Point3::new(1.0, 2.0, 1.0),
+        },
+        [1.0, 1.0, 0.0, 1.0],
+        true,
+    );
+    debug_draw.sphere(cgmath::Point3::new(0.0, 1.0, 0.0), 0.5, GREEN, true);
+    debug_draw.arrow(
+        cgmath::Point3::new(1.5, 2.0, 0.0),
+        cgmath::Point3::new(0.0, 1.0, 0.0),
+        RED,
+        false,
+    );
+    debug_draw.axes(origin, 1.5, false);
+}
+
+/// Simulates a device loss and checks that the recovered state renders the same frame,
+/// which covers the settings carried over by [`State::recover`].
+fn check_recovery(state: &mut State, tolerance: u8) -> bool {
+    state.render_offscreen();
+    let before = state.read_frame().expect("Failed to read golden frame");
+
+    state.mark_device_lost();
+    if let Err(e) = futures::executor::block_on(state.recover(None)) {
+        println!("recovery: failed to recreate the device: {}", e);
+        return false;
+    }
+    state.render_offscreen();
+    let after = state.read_frame().expect("Failed to read golden frame");
+
+    match compare(&before, &after, tolerance) {
+        Some(comparison) if comparison.mismatched_pixels == 0 => {
+            println!("recovery: ok");
+            true
+        }
+        Some(comparison) => {
+            println!(
+                "recovery: {} pixels differ after recovering",
+                comparison.mismatched_pixels
+            );
+            false
+        }
+        None => {
+            println!("recovery: frame size changed after recovering");
+            false
+        }
+    }
+}
+
+/// Compares two images channel by channel.
+///
+/// The diff image shows mismatched pixels in red over a darkened copy of the reference.
+fn compare(reference: &RgbaImage, actual: &RgbaImage, tolerance: u8) -> Option<Comparison> {
+    if reference.dimensions() != actual.dimensions() {
+        return None;
+    }
+
+    let mut mismatched_pixels = 0;
+    let mut diff = RgbaImage::new(reference.width(), reference.height());
+
+    for ((expected, got), out) in reference
+        .pixels()
+        .zip(actual.pixels())
+        .zip(diff.pixels_mut())
+    {
+        let matches = expected
+            .0
+            .iter()
+            .zip(got.0.iter())
+            .all(|(e, g)| (*e as i16 - *g as i16).abs() <= tolerance as i16);
+
+        *out = if matches {
+            let luma = (expected[0] as u16 + expected[1] as u16 + expected[2] as u16) / 3;
+            let dimmed = (luma / 4) as u8;
+            Rgba([dimmed, dimmed, dimmed, 255])
+        } else {
+            mismatched_pixels += 1;
+            Rgba([255, 0, 0, 255])
+        };
+    }
+
+    Some(Comparison {
+        mismatched_pixels,
+        diff,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(color: [u8; 4]) -> RgbaImage {
+        RgbaImage::from_pixel(4, 3, Rgba(color))
+    }
+
+    #[test]
+    fn identical_images_match() {
+        let reference = image([10, 20, 30, 255]);
+        let comparison = compare(&reference, &reference.clone(), 0).unwrap();
+        assert_eq!(comparison.mismatched_pixels, 0);
+    }
+
+    #[test]
+    fn differences_within_tolerance_match() {
+        let reference = image([10, 20, 30, 255]);
+        let actual = image([12, 18, 30, 255]);
+        assert_eq!(
+            compare(&reference, &actual, 2).unwrap().mismatched_pixels,
+            0
+        );
+    }
+
+    #[test]
+    fn differences_over_tolerance_are_counted_and_shown_in_red() {
+        let reference = image([10, 20, 30, 255]);
+        let mut actual = reference.clone();
+        actual.put_pixel(1, 2, Rgba([10, 23, 30, 255]));
+
+        let comparison = compare(&reference, &actual, 2).unwrap();
+        assert_eq!(comparison.mismatched_pixels, 1);
+        assert_eq!(*comparison.diff.get_pixel(1, 2), Rgba([255, 0, 0, 255]));
+        assert_ne!(*comparison.diff.get_pixel(0, 0), Rgba([255, 0, 0, 255]));
+    }
+
+    #[test]
+    fn different_sizes_do_not_compare() {
+        let reference = image([0; 4]);
+        let actual = RgbaImage::new(3, 4);
+        assert!(compare(&reference, &actual, 255).is_none());
+    }
+}
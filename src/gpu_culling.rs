@@ -0,0 +1,344 @@
+use crate::{
+    camera::Camera, culling::Frustum, instance::InstanceRaw, lod, model::Model,
+    resource_stats::ResourceStats,
+};
+
+const WORKGROUP_SIZE: u32 = 64;
+
+// Hardcoded by cull.comp, as `uint data[26]` and `lod < 4u`
+const _: () = assert!(std::mem::size_of::<InstanceRaw>() == 26 * 4);
+const _: () = assert!(lod::MAX_LODS == 4);
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct CullingUniforms {
+    planes: [[f32; 4]; 6],
+    bounds: [f32; 4],
+    /// xyz: eye position, w: tangent of half the vertical field of view.
+    eye: [f32; 4],
+    lod_thresholds: [f32; lod::MAX_LODS],
+    instance_count: u32,
+    mesh_count: u32,
+    lod_count: u32,
+    _padding: u32,
+}
+
+/// Arguments of `draw_indexed_indirect`, one per mesh and level of detail of the model.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct DrawIndexedIndirect {
+    index_count: u32,
+    instance_count: u32,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+}
+
+const DRAW_ARGS_SIZE: wgpu::BufferAddress = std::mem::size_of::<DrawIndexedIndirect>() as _;
+
+/// Compute pass testing every instance against the view frustum.
+///
+/// Surviving instances pick their level of detail like [`lod::select_lod`] and are
+/// compacted into the region of that level in the visible instance buffer, counting
+/// them with one atomic per level. A second single workgroup dispatch copies the counts
+/// into the indirect arguments of each mesh, so the CPU never sees the result.
+pub struct GpuCulling {
+    instance_count: u32,
+    mesh_count: u32,
+    lod_count: u32,
+
+    uniform_buffer: wgpu::Buffer,
+    // Only read through the bind group, kept alive with it
+    #[allow(dead_code)]
+    instance_buffer: wgpu::Buffer,
+    // Zeroed counters, copied over the live ones every frame
+    initial_lod_count_buffer: wgpu::Buffer,
+    lod_count_buffer: wgpu::Buffer,
+    draw_buffer: wgpu::Buffer,
+    visible_instance_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    pipeline: wgpu::ComputePipeline,
+    draws_bind_group: wgpu::BindGroup,
+    draws_pipeline: wgpu::ComputePipeline,
+}
+
+impl GpuCulling {
+    pub fn new(
+        device: &wgpu::Device,
+        stats: &mut ResourceStats,
+        model: &Model,
+        instances: &[InstanceRaw],
+    ) -> Self {
+        let instance_count = instances.len() as u32;
+        let mesh_count = model.meshes.len() as u32;
+        let lod_count = model.lod_count() as u32;
+
+        let uniform_buffer = stats.create_buffer(
+            device,
+            &wgpu::BufferDescriptor {
+                label: Some("Culling uniform buffer"),
+                size: std::mem::size_of::<CullingUniforms>() as _,
+                usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+                mapped_at_creation: false,
+            },
+        );
+
+        let instance_buffer = stats.create_buffer_init(
+            device,
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Culling instance buffer"),
+                contents: bytemuck::cast_slice(instances),
+                usage: wgpu::BufferUsage::STORAGE,
+            },
+        );
+
+        // Every level has room for all the instances
+        let visible_instance_buffer = stats.create_buffer(
+            device,
+            &wgpu::BufferDescriptor {
+                label: Some("Visible instance buffer"),
+                size: (lod_count as usize * instances.len() * std::mem::size_of::<InstanceRaw>())
+                    .max(1) as _,
+                usage: wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::VERTEX,
+                mapped_at_creation: false,
+            },
+        );
+
+        // Ordered by level of detail, then by mesh, see draw_args_offset
+        let draws: Vec<_> = (0..model.lod_count())
+            .flat_map(|lod| {
+                model.meshes.iter().map(move |mesh| DrawIndexedIndirect {
+                    index_count: mesh.lod_index_count(lod),
+                    instance_count: 0,
+                    first_index: 0,
+                    base_vertex: 0,
+                    first_instance: 0,
+                })
+            })
+            .collect();
+        let draw_buffer = stats.create_buffer_init(
+            device,
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Indirect draw buffer"),
+                contents: bytemuck::cast_slice(&draws),
+                usage: wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::INDIRECT,
+            },
+        );
+
+        let lod_counts = vec![0u32; lod_count as usize];
+        let initial_lod_count_buffer = stats.create_buffer_init(
+            device,
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Initial LOD count buffer"),
+                contents: bytemuck::cast_slice(&lod_counts),
+                usage: wgpu::BufferUsage::COPY_SRC,
+            },
+        );
+        let lod_count_buffer = stats.create_buffer_init(
+            device,
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("LOD count buffer"),
+                contents: bytemuck::cast_slice(&lod_counts),
+                usage: wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_DST,
+            },
+        );
+
+        let storage_entry = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStage::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let uniform_entry = wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStage::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                uniform_entry.clone(),
+                storage_entry(1, true),
+                storage_entry(2, false),
+                storage_entry(3, false),
+            ],
+            label: Some("Culling bind group layout"),
+        });
+
+        let buffer_entry = |binding, buffer| wgpu::BindGroupEntry {
+            binding,
+            resource: wgpu::BindingResource::Buffer {
+                buffer,
+                offset: 0,
+                size: None,
+            },
+        };
+        let bind_group = stats.create_bind_group(
+            device,
+            &wgpu::BindGroupDescriptor {
+                layout: &bind_group_layout,
+                entries: &[
+                    buffer_entry(0, &uniform_buffer),
+                    buffer_entry(1, &instance_buffer),
+                    buffer_entry(2, &visible_instance_buffer),
+                    buffer_entry(3, &lod_count_buffer),
+                ],
+                label: Some("Culling bind group"),
+            },
+        );
+
+        let draws_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    uniform_entry,
+                    storage_entry(1, true),
+                    storage_entry(2, false),
+                ],
+                label: Some("Culling draws bind group layout"),
+            });
+        let draws_bind_group = stats.create_bind_group(
+            device,
+            &wgpu::BindGroupDescriptor {
+                layout: &draws_bind_group_layout,
+                entries: &[
+                    buffer_entry(0, &uniform_buffer),
+                    buffer_entry(1, &lod_count_buffer),
+                    buffer_entry(2, &draw_buffer),
+                ],
+                label: Some("Culling draws bind group"),
+            },
+        );
+
+        let pipeline = Self::create_pipeline(
+            device,
+            &bind_group_layout,
+            "Culling",
+            wgpu::include_spirv!(concat!(env!("OUT_DIR"), "/cull.comp.spv")),
+        );
+        let draws_pipeline = Self::create_pipeline(
+            device,
+            &draws_bind_group_layout,
+            "Culling draws",
+            wgpu::include_spirv!(concat!(env!("OUT_DIR"), "/cull_draws.comp.spv")),
+        );
+
+        GpuCulling {
+            instance_count,
+            mesh_count,
+            lod_count,
+
+            uniform_buffer,
+            instance_buffer,
+            initial_lod_count_buffer,
+            lod_count_buffer,
+            draw_buffer,
+            visible_instance_buffer,
+            bind_group,
+            pipeline,
+            draws_bind_group,
+            draws_pipeline,
+        }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        label: &str,
+        shader: wgpu::ShaderModuleDescriptor,
+    ) -> wgpu::ComputePipeline {
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(&format!("{} pipeline layout", label)),
+            bind_group_layouts: &[bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let cs_module = device.create_shader_module(&shader);
+        device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some(&format!("{} pipeline", label)),
+            layout: Some(&pipeline_layout),
+            module: &cs_module,
+            entry_point: "main",
+        })
+    }
+
+    pub fn update(
+        &self,
+        queue: &wgpu::Queue,
+        frustum: &Frustum,
+        model: &Model,
+        camera: &Camera,
+        lod_bias: f32,
+    ) {
+        let center = model.bounds.center();
+        let half_fovy: cgmath::Rad<f32> = (cgmath::Deg(camera.fovy) / 2.0).into();
+        let uniforms = CullingUniforms {
+            planes: frustum.planes(),
+            bounds: [center.x, center.y, center.z, model.bounds.radius()],
+            eye: [camera.eye.x, camera.eye.y, camera.eye.z, half_fovy.0.tan()],
+            lod_thresholds: lod::screen_size_thresholds(lod_bias),
+            instance_count: self.instance_count,
+            mesh_count: self.mesh_count,
+            lod_count: self.lod_count,
+            _padding: 0,
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniforms]));
+    }
+
+    pub fn encode(&self, encoder: &mut wgpu::CommandEncoder) {
+        if self.instance_count == 0 || self.mesh_count == 0 {
+            return;
+        }
+
+        encoder.copy_buffer_to_buffer(
+            &self.initial_lod_count_buffer,
+            0,
+            &self.lod_count_buffer,
+            0,
+            (self.lod_count as usize * std::mem::size_of::<u32>()) as wgpu::BufferAddress,
+        );
+
+        let mut culling_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Culling compute pass"),
+        });
+        culling_pass.set_pipeline(&self.pipeline);
+        culling_pass.set_bind_group(0, &self.bind_group, &[]);
+        culling_pass.dispatch(
+            (self.instance_count + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE,
+            1,
+            1,
+        );
+
+        culling_pass.set_pipeline(&self.draws_pipeline);
+        culling_pass.set_bind_group(0, &self.draws_bind_group, &[]);
+        culling_pass.dispatch(1, 1, 1);
+    }
+
+    pub fn lod_count(&self) -> usize {
+        self.lod_count as usize
+    }
+
+    /// Visible instances drawn at a level of detail.
+    pub fn visible_instances(&self, lod: usize) -> wgpu::BufferSlice<'_> {
+        let size = (self.instance_count as usize * std::mem::size_of::<InstanceRaw>()) as u64;
+        let start = lod as wgpu::BufferAddress * size;
+        self.visible_instance_buffer.slice(start..start + size)
+    }
+
+    /// Indirect arguments of every mesh and level of detail, see [`draw_args_offset`].
+    pub fn draw_buffer(&self) -> &wgpu::Buffer {
+        &self.draw_buffer
+    }
+}
+
+/// Byte offset in [`GpuCulling::draw_buffer`] of the indirect arguments of a mesh at a
+/// level of detail.
+pub fn draw_args_offset(model: &Model, lod: usize, mesh_index: usize) -> wgpu::BufferAddress {
+    (lod * model.meshes.len() + mesh_index) as wgpu::BufferAddress * DRAW_ARGS_SIZE
+}
@@ -7,11 +7,24 @@ pub struct Instance {
 }
 
 impl Instance {
-    pub fn to_raw(&self) -> InstanceRaw {
+    /// Moves a point from model space to world space.
+    pub fn transform_point(&self, point: cgmath::Point3<f32>) -> cgmath::Point3<f32> {
+        use cgmath::{EuclideanSpace, Rotation};
+
+        cgmath::Point3::from_vec(self.position + self.rotation.rotate_vector(point.to_vec()))
+    }
+
+    /// Moves the model from model space to world space.
+    pub fn model_matrix(&self) -> cgmath::Matrix4<f32> {
+        cgmath::Matrix4::from_translation(self.position) * cgmath::Matrix4::from(self.rotation)
+    }
+
+    /// `id` ends up in the object-ID buffer, it is the index of the instance in the scene.
+    pub fn to_raw(&self, id: u32) -> InstanceRaw {
         InstanceRaw {
-            model: (cgmath::Matrix4::from_translation(self.position)
-                * cgmath::Matrix4::from(self.rotation))
-            .into(),
+            model: self.model_matrix().into(),
+            normal: cgmath::Matrix3::from(self.rotation).into(),
+            id,
         }
     }
 }
@@ -20,11 +33,13 @@ impl Instance {
 #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct InstanceRaw {
     model: [[f32; 4]; 4],
+    normal: [[f32; 3]; 3],
+    id: u32,
 }
 
 impl InstanceRaw {
     pub fn desc() -> wgpu::VertexBufferLayout<'static> {
-        static ATTRIBUTES: Lazy<[wgpu::VertexAttribute; 4]> = Lazy::new(|| {
+        static ATTRIBUTES: Lazy<[wgpu::VertexAttribute; 8]> = Lazy::new(|| {
             [
                 wgpu::VertexAttribute {
                     format: wgpu::VertexFormat::Float4,
@@ -46,6 +61,29 @@ impl InstanceRaw {
                     shader_location: 8,
                     offset: (std::mem::size_of::<[f32; 4]>() * 3) as _,
                 },
+                wgpu::VertexAttribute {
+                    format: wgpu::VertexFormat::Float3,
+                    shader_location: 9,
+                    offset: (std::mem::size_of::<[f32; 4]>() * 4) as _,
+                },
+                wgpu::VertexAttribute {
+                    format: wgpu::VertexFormat::Float3,
+                    shader_location: 10,
+                    offset: (std::mem::size_of::<[f32; 4]>() * 4 + std::mem::size_of::<[f32; 3]>())
+                        as _,
+                },
+                wgpu::VertexAttribute {
+                    format: wgpu::VertexFormat::Float3,
+                    shader_location: 11,
+                    offset: (std::mem::size_of::<[f32; 4]>() * 4
+                        + std::mem::size_of::<[f32; 3]>() * 2) as _,
+                },
+                wgpu::VertexAttribute {
+                    format: wgpu::VertexFormat::Uint,
+                    shader_location: 12,
+                    offset: (std::mem::size_of::<[f32; 4]>() * 4
+                        + std::mem::size_of::<[f32; 3]>() * 3) as _,
+                },
             ]
         });
 
@@ -0,0 +1,19 @@
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Light {
+    pub position: [f32; 3],
+    pub ambient_strength: f32,
+    pub color: [f32; 3],
+    pub specular_strength: f32,
+}
+
+impl Light {
+    pub fn new(position: [f32; 3], color: [f32; 3]) -> Self {
+        Light {
+            position,
+            ambient_strength: 0.1,
+            color,
+            specular_strength: 0.5,
+        }
+    }
+}
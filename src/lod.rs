@@ -0,0 +1,42 @@
+/// Fraction of the full detail index count targeted by each simplified level.
+pub const LOD_RATIOS: [f32; 3] = [0.5, 0.25, 0.125];
+
+/// Largest surface deviation allowed for each simplified level, relative to the model radius.
+pub const LOD_ERRORS: [f32; 3] = [0.01, 0.03, 0.08];
+
+/// Screen size under which each simplified level is used, see [`screen_size`].
+const LOD_SCREEN_SIZES: [f32; 3] = [0.25, 0.12, 0.05];
+
+/// Full detail and the simplified levels, as many as `cull.comp` has thresholds for.
+pub const MAX_LODS: usize = LOD_RATIOS.len() + 1;
+
+/// Fraction of the viewport height covered by a bounding sphere.
+pub fn screen_size(radius: f32, distance: f32, fovy: cgmath::Deg<f32>) -> f32 {
+    if distance <= radius {
+        return f32::INFINITY;
+    }
+    let half_fovy: cgmath::Rad<f32> = (fovy / 2.0).into();
+    radius / (distance * half_fovy.0.tan())
+}
+
+/// Screen sizes of [`select_lod`] scaled by `bias`, for the culling shader.
+///
+/// Padded with zeros, which no screen size is below.
+pub fn screen_size_thresholds(bias: f32) -> [f32; MAX_LODS] {
+    let mut thresholds = [0.0; MAX_LODS];
+    for (threshold, screen_size) in thresholds.iter_mut().zip(LOD_SCREEN_SIZES.iter()) {
+        *threshold = screen_size * bias;
+    }
+    thresholds
+}
+
+/// Picks the level to draw for an object of the given screen size.
+///
+/// A bias above 1.0 switches to simpler levels earlier.
+pub fn select_lod(screen_size: f32, bias: f32, lod_count: usize) -> usize {
+    let level = LOD_SCREEN_SIZES
+        .iter()
+        .take_while(|&&threshold| screen_size < threshold * bias)
+        .count();
+    level.min(lod_count.saturating_sub(1))
+}
@@ -1,23 +1,67 @@
+use config::Config;
 use futures::executor::block_on;
 use imgui_state::ImguiState;
 use winit::{
+    dpi::PhysicalSize,
     event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
     window::WindowBuilder,
 };
 
 mod camera;
+mod config;
+mod culling;
+mod debug_draw;
+mod gltf_loader;
+mod golden;
+mod gpu_culling;
 mod imgui_state;
 mod instance;
+mod light;
+mod lod;
 mod model;
+mod normals;
+mod obj_loader;
+mod outline;
+mod picking;
+mod pipeline;
+mod post;
+mod present_mode;
+mod profiler;
+mod readback;
+mod recording;
+mod render_graph;
+mod resource_stats;
+mod screenshot;
+mod shadow;
+mod simplify;
 mod state;
 mod texture;
+mod tonemap;
 mod vertex;
 
 use state::State;
 
 fn main() {
     env_logger::init();
+    let config = Config::load();
+
+    if std::env::args().any(|arg| arg == "--list-adapters") {
+        config::list_adapters(&config);
+        return;
+    }
+
+    if let Some(output_path) = std::env::args()
+        .skip_while(|arg| arg != "--headless")
+        .nth(1)
+    {
+        render_headless(&output_path, &config);
+        return;
+    }
+
+    if std::env::args().any(|arg| arg == "--golden" || arg == "--update-golden") {
+        run_golden(config);
+    }
 
     let event_loop = EventLoop::new();
     let window = WindowBuilder::new()
@@ -30,7 +74,8 @@ fn main() {
 
     // IMGUI init
     let mut imgui_state = ImguiState::create(&window);
-    let mut state = block_on(State::new(&window, &mut imgui_state.context));
+    let mut state = block_on(State::new(&window, &mut imgui_state.context, &config));
+    state.set_cursor_grabbed(true);
 
     let mut last_time = std::time::Instant::now();
 
@@ -39,7 +84,10 @@ fn main() {
 
         match event {
             Event::WindowEvent { event, window_id } if window_id == window.id() => {
-                if !state.handle_window_event(&event) {
+                // Clicks on the UI must not pick what is behind it
+                let captured_by_ui = matches!(event, WindowEvent::MouseInput { .. })
+                    && imgui_state.context.io().want_capture_mouse;
+                if !captured_by_ui && !state.handle_window_event(&event) {
                     match event {
                         WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
                         WindowEvent::KeyboardInput { input, .. } => match input {
@@ -48,6 +96,11 @@ fn main() {
                                 virtual_keycode: Some(VirtualKeyCode::Escape),
                                 ..
                             } => *control_flow = ControlFlow::Exit,
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::F12),
+                                ..
+                            } => state.request_screenshot(),
                             _ => {}
                         },
                         WindowEvent::Resized(new_size) => {
@@ -72,16 +125,27 @@ fn main() {
                 match state.render(ui) {
                     Ok(_) => {}
                     Err(wgpu::SwapChainError::Lost) => state.resize(state.window_size),
-                    Err(wgpu::SwapChainError::OutOfMemory) => *control_flow = ControlFlow::Exit,
+                    Err(wgpu::SwapChainError::OutOfMemory) => state.mark_device_lost(),
                     Err(e) => eprintln!("{:?}", e),
                 }
             }
             Event::MainEventsCleared => {
+                if state.is_device_lost() {
+                    match block_on(state.recover(Some(&mut imgui_state.context))) {
+                        Ok(()) => println!("Recovered from a lost device"),
+                        Err(e) => {
+                            eprintln!("Failed to recover from a lost device: {}", e);
+                            *control_flow = ControlFlow::Exit;
+                            return;
+                        }
+                    }
+                }
+
                 let current_time = std::time::Instant::now();
                 let elapsed = current_time.duration_since(last_time);
                 last_time = current_time;
 
-                state.update(elapsed);
+                state.update(state.fixed_dt().unwrap_or(elapsed));
 
                 imgui_state.prepare_frame(elapsed, &window);
 
@@ -91,3 +155,32 @@ fn main() {
         }
     })
 }
+
+fn render_headless(output_path: &str, config: &Config) {
+    let mut state = block_on(State::new_headless(PhysicalSize::new(800, 600), config));
+    state.update(std::time::Duration::default());
+    state.render_offscreen();
+
+    state
+        .read_frame()
+        .expect("Failed to read offscreen frame")
+        .save(output_path)
+        .expect("Failed to save offscreen frame");
+}
+
+fn run_golden(config: Config) -> ! {
+    let mut options = golden::GoldenOptions {
+        config,
+        ..Default::default()
+    };
+    options.update = std::env::args().any(|arg| arg == "--update-golden");
+    if let Some(tolerance) = std::env::args()
+        .skip_while(|arg| arg != "--golden-tolerance")
+        .nth(1)
+    {
+        options.tolerance = tolerance.parse().expect("Invalid golden tolerance");
+    }
+
+    let success = golden::run(&options);
+    std::process::exit(if success { 0 } else { 1 });
+}
@@ -1,180 +1,560 @@
-use std::{ops::Range, path::Path};
+use std::{fmt, ops::Range, path::Path};
 
-use image::ImageError;
-use wgpu::util::DeviceExt;
+use image::{DynamicImage, ImageBuffer};
 
+use crate::culling::Aabb;
+use crate::gltf_loader;
+use crate::gpu_culling::draw_args_offset;
+use crate::lod::{LOD_ERRORS, LOD_RATIOS};
+use crate::normals::NormalMode;
+use crate::obj_loader;
+use crate::resource_stats::ResourceStats;
+use crate::simplify;
 use crate::texture::Texture;
 use crate::vertex::ModelVertex;
 
-pub struct Mesh {
-    #[allow(dead_code)]
-    name: String,
-    vertex_buffer: wgpu::Buffer,
+/// Index buffer of one level of detail, all levels share the mesh vertex buffer.
+pub struct MeshLod {
     index_buffer: wgpu::Buffer,
     index_count: u32,
+}
+
+pub struct Mesh {
+    pub name: String,
+    vertex_buffer: wgpu::Buffer,
+    /// Full detail first, then increasingly simplified levels.
+    lods: Vec<MeshLod>,
+    #[allow(dead_code)]
+    uniform_buffer: wgpu::Buffer,
+    /// Mesh index, written to the object-ID buffer.
+    bind_group: wgpu::BindGroup,
     pub material_index: usize,
 }
 
+impl Mesh {
+    /// Index count of a level of detail, clamped to the simplest level like drawing.
+    pub fn lod_index_count(&self, lod: usize) -> u32 {
+        self.lod(lod).index_count
+    }
+
+    pub fn lod_count(&self) -> usize {
+        self.lods.len()
+    }
+
+    fn lod(&self, lod: usize) -> &MeshLod {
+        &self.lods[lod.min(self.lods.len() - 1)]
+    }
+}
+
+/// Meshes of a model to draw, according to their material.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MeshFilter {
+    All,
+    Opaque,
+    Transparent,
+}
+
 pub struct Material {
     #[allow(dead_code)]
     name: String,
     #[allow(dead_code)]
     diffuse_texture: Texture,
+    #[allow(dead_code)]
+    uniform_buffer: wgpu::Buffer,
     bind_group: wgpu::BindGroup,
+    /// Drawn after opaque geometry with alpha blending.
+    pub transparent: bool,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct MaterialUniforms {
+    base_color: [f32; 4],
+    emissive: [f32; 3],
+    opacity: f32,
+    metallic: f32,
+    shininess: f32,
+    alpha_cutoff: f32,
+    _padding: f32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct MeshUniforms {
+    mesh_index: u32,
+    _padding: [u32; 3],
+}
+
+/// CPU-side description of a model, kept to recreate its GPU resources.
+#[derive(Clone)]
+pub struct ModelData {
+    /// Used to label the GPU resources.
+    pub label: String,
+    pub meshes: Vec<MeshData>,
+    pub materials: Vec<MaterialData>,
+    pub bounds: Aabb,
+}
+
+#[derive(Clone)]
+pub struct MeshData {
+    pub name: String,
+    pub vertices: Vec<ModelVertex>,
+    /// Index lists of each level of detail, full detail first.
+    pub lods: Vec<Vec<u32>>,
+    pub material_index: usize,
+}
+
+#[derive(Clone)]
+pub struct MaterialData {
+    pub name: String,
+    pub diffuse_image: DynamicImage,
+    pub opacity: f32,
+    pub transparent: bool,
+    /// Linear color multiplying the diffuse texture.
+    pub base_color: [f32; 4],
+    pub emissive: [f32; 3],
+    /// Blends the diffuse lighting out, leaving the specular tinted by the base color.
+    pub metallic: f32,
+    /// Blinn-Phong specular exponent.
+    pub shininess: f32,
+    /// Fragments with a lower alpha are discarded, 0 keeps them all.
+    pub alpha_cutoff: f32,
+}
+
+/// Specular exponent of materials without one, as OBJ files are drawn.
+pub const DEFAULT_SHININESS: f32 = 32.0;
+
+impl MaterialData {
+    /// White material for meshes without one, with the loader's default `shininess`.
+    pub fn default_material(shininess: f32) -> Self {
+        MaterialData {
+            name: "Default material".to_string(),
+            diffuse_image: white_image(),
+            opacity: 1.0,
+            transparent: false,
+            base_color: [1.0; 4],
+            emissive: [0.0; 3],
+            metallic: 0.0,
+            shininess,
+            alpha_cutoff: 0.0,
+        }
+    }
+}
+
+/// Single white texel, for materials without a diffuse texture.
+pub fn white_image() -> DynamicImage {
+    DynamicImage::ImageRgba8(ImageBuffer::from_pixel(1, 1, image::Rgba([u8::MAX; 4])))
+}
+
+/// A loaded mesh without levels of detail, and the full detail indices to build them.
+pub type LoadedMesh = (MeshData, Vec<u32>);
+
+/// A problem that did not stop a model from loading, with what was used instead.
+#[derive(Debug)]
+pub enum LoadWarning {
+    /// The material library could not be read, its meshes use the default material.
+    MaterialLibrary(tobj::LoadError),
+    /// The texture could not be read and was replaced by a white one. `name` is its
+    /// path, or its index in the file for embedded textures.
+    Texture {
+        name: String,
+        error: Box<dyn std::error::Error>,
+    },
+}
+
+impl fmt::Display for LoadWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadWarning::MaterialLibrary(e) => {
+                write!(f, "failed to load the material library: {}", e)
+            }
+            LoadWarning::Texture { name, error } => {
+                write!(f, "failed to load texture {}: {}", name, error)
+            }
+        }
+    }
+}
+
+/// Blinn-Phong exponent approximating a GGX highlight of the given roughness.
+pub fn shininess_from_roughness(roughness: f32) -> f32 {
+    let alpha = roughness * roughness;
+    (2.0 / (alpha * alpha).max(1e-4) - 2.0).max(1.0).min(1024.0)
+}
+
+impl ModelData {
+    /// Reads an OBJ, glTF or GLB file according to its extension, and simplifies its
+    /// meshes. OBJ meshes without normals get them generated with `normal_mode`.
+    pub fn load<P: AsRef<Path>>(
+        path: P,
+        normal_mode: NormalMode,
+    ) -> Result<(Self, Vec<LoadWarning>), Box<dyn std::error::Error>> {
+        let path = path.as_ref();
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_ascii_lowercase);
+        match extension.as_deref() {
+            Some("obj") => Ok(obj_loader::load(path, normal_mode)?),
+            Some("gltf") | Some("glb") => Ok(gltf_loader::load(path)?),
+            _ => Err(format!("Unsupported model format {:?}", path).into()),
+        }
+    }
+
+    /// Computes the bounds of the loaded meshes, then builds their levels of detail
+    /// with errors relative to the size of the whole model.
+    pub fn from_meshes(
+        label: String,
+        meshes: Vec<LoadedMesh>,
+        materials: Vec<MaterialData>,
+    ) -> Self {
+        let bounds = Aabb::from_points(meshes.iter().flat_map(|(mesh, _)| {
+            mesh.vertices
+                .iter()
+                .map(|v| cgmath::Point3::from(v.position))
+        }));
+
+        let meshes = meshes
+            .into_iter()
+            .map(|(mut mesh, indices)| {
+                mesh.lods = build_lods(&mesh.vertices, indices, &bounds);
+                mesh
+            })
+            .collect();
+
+        ModelData {
+            label,
+            meshes,
+            materials,
+            bounds,
+        }
+    }
+}
+
+/// The full detail indices followed by the simplified levels worth drawing.
+fn build_lods(vertices: &[ModelVertex], indices: Vec<u32>, bounds: &Aabb) -> Vec<Vec<u32>> {
+    let full_index_count = indices.len();
+    let mut previous_index_count = full_index_count;
+    let mut lods = vec![indices];
+    for (&ratio, &error) in LOD_RATIOS.iter().zip(LOD_ERRORS.iter()) {
+        let target_index_count = (full_index_count as f32 * ratio) as usize / 3 * 3;
+        let indices = simplify::simplify(
+            vertices,
+            &lods[0],
+            target_index_count,
+            error * bounds.radius(),
+        );
+
+        // Not worth a level if the error bound stopped the simplification early
+        if indices.is_empty() || indices.len() * 10 > previous_index_count * 9 {
+            break;
+        }
+        previous_index_count = indices.len();
+        lods.push(indices);
+    }
+    lods
 }
 
 pub struct Model {
     pub meshes: Vec<Mesh>,
     pub materials: Vec<Material>,
+    /// Bounds of all the meshes, in model space.
+    pub bounds: Aabb,
 }
 
 impl Model {
-    pub fn open<P: AsRef<Path>>(
-        path: P,
+    /// Uploads the meshes and textures of `data` to the GPU.
+    pub fn from_data(
+        data: &ModelData,
         device: &wgpu::Device,
         queue: &wgpu::Queue,
+        stats: &mut ResourceStats,
         bind_group_layout: &wgpu::BindGroupLayout,
-    ) -> Result<Self, Box<dyn std::error::Error>> {
-        let path = path.as_ref();
-        let (obj_models, obj_materials) = tobj::load_obj(path, true)?;
+        mesh_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let materials = data
+            .materials
+            .iter()
+            .map(|material| {
+                let diffuse_texture =
+                    Texture::from_image(&material.diffuse_image, device, queue, stats);
 
-        let containing_folder = path
-            .parent()
-            .expect("Failed to extract parent folder while loading model");
+                let uniform_buffer = stats.create_buffer_init(
+                    device,
+                    &wgpu::util::BufferInitDescriptor {
+                        label: Some(&format!("{} material buffer", material.name)),
+                        contents: bytemuck::cast_slice(&[MaterialUniforms {
+                            base_color: material.base_color,
+                            emissive: material.emissive,
+                            opacity: material.opacity,
+                            metallic: material.metallic,
+                            shininess: material.shininess,
+                            alpha_cutoff: material.alpha_cutoff,
+                            _padding: 0.0,
+                        }]),
+                        usage: wgpu::BufferUsage::UNIFORM,
+                    },
+                );
 
-        let materials: Result<Vec<Material>, ImageError> = obj_materials
-            .into_iter()
-            .map(|obj_mat| -> Result<Material, ImageError> {
-                let diffuse_texture = Texture::open(
-                    containing_folder.join(obj_mat.diffuse_texture),
+                let bind_group = stats.create_bind_group(
                     device,
-                    queue,
-                )?;
+                    &wgpu::BindGroupDescriptor {
+                        layout: bind_group_layout,
+                        entries: &[
+                            wgpu::BindGroupEntry {
+                                binding: 0,
+                                resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 1,
+                                resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 2,
+                                resource: wgpu::BindingResource::Buffer {
+                                    buffer: &uniform_buffer,
+                                    offset: 0,
+                                    size: None,
+                                },
+                            },
+                        ],
+                        label: Some(&format!("{} material bind group", material.name)),
+                    },
+                );
 
-                let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-                    layout: bind_group_layout,
-                    entries: &[
-                        wgpu::BindGroupEntry {
-                            binding: 0,
-                            resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
-                        },
-                        wgpu::BindGroupEntry {
-                            binding: 1,
-                            resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
-                        },
-                    ],
-                    label: None,
-                });
-
-                Ok(Material {
-                    name: obj_mat.name,
+                Material {
+                    name: material.name.clone(),
                     diffuse_texture,
+                    uniform_buffer,
                     bind_group,
-                })
+                    transparent: material.transparent,
+                }
             })
             .collect();
 
-        let materials = materials?;
-
-        let meshes = obj_models
-            .into_iter()
-            .map(|model| {
-                let mut vertices = Vec::new();
-                for i in 0..model.mesh.positions.len() / 3 {
-                    vertices.push(ModelVertex {
-                        position: [
-                            model.mesh.positions[i * 3],
-                            model.mesh.positions[i * 3 + 1],
-                            model.mesh.positions[i * 3 + 2],
-                        ],
-                        tex_coords: [model.mesh.texcoords[i * 2], model.mesh.texcoords[i * 2 + 1]],
-                        normal: [
-                            model.mesh.normals[i * 3],
-                            model.mesh.normals[i * 3 + 1],
-                            model.mesh.normals[i * 3 + 2],
-                        ],
-                    });
-                }
+        let meshes = data
+            .meshes
+            .iter()
+            .enumerate()
+            .map(|(mesh_index, mesh)| {
+                let vertex_buffer = stats.create_buffer_init(
+                    device,
+                    &wgpu::util::BufferInitDescriptor {
+                        label: Some(&format!("{} Vertex Buffer", data.label)),
+                        contents: bytemuck::cast_slice(&mesh.vertices),
+                        usage: wgpu::BufferUsage::VERTEX,
+                    },
+                );
+                let lods = mesh
+                    .lods
+                    .iter()
+                    .enumerate()
+                    .map(|(level, indices)| {
+                        let index_buffer = stats.create_buffer_init(
+                            device,
+                            &wgpu::util::BufferInitDescriptor {
+                                label: Some(&format!("{} Index Buffer LOD {}", data.label, level)),
+                                contents: bytemuck::cast_slice(indices),
+                                usage: wgpu::BufferUsage::INDEX,
+                            },
+                        );
+                        MeshLod {
+                            index_buffer,
+                            index_count: indices.len() as u32,
+                        }
+                    })
+                    .collect();
 
-                let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: Some(&format!("{:?} Vertex Buffer", path)),
-                    contents: bytemuck::cast_slice(&vertices),
-                    usage: wgpu::BufferUsage::VERTEX,
-                });
-                let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: Some(&format!("{:?} Index Buffer", path)),
-                    contents: bytemuck::cast_slice(&model.mesh.indices),
-                    usage: wgpu::BufferUsage::INDEX,
-                });
+                let uniform_buffer = stats.create_buffer_init(
+                    device,
+                    &wgpu::util::BufferInitDescriptor {
+                        label: Some(&format!("{} mesh buffer", mesh.name)),
+                        contents: bytemuck::cast_slice(&[MeshUniforms {
+                            mesh_index: mesh_index as u32,
+                            _padding: [0; 3],
+                        }]),
+                        usage: wgpu::BufferUsage::UNIFORM,
+                    },
+                );
+                let bind_group = stats.create_bind_group(
+                    device,
+                    &wgpu::BindGroupDescriptor {
+                        layout: mesh_bind_group_layout,
+                        entries: &[wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: wgpu::BindingResource::Buffer {
+                                buffer: &uniform_buffer,
+                                offset: 0,
+                                size: None,
+                            },
+                        }],
+                        label: Some(&format!("{} mesh bind group", mesh.name)),
+                    },
+                );
 
                 Mesh {
-                    name: model.name,
+                    name: mesh.name.clone(),
                     vertex_buffer,
-                    index_buffer,
-                    index_count: model.mesh.indices.len() as u32,
-                    material_index: model.mesh.material_id.unwrap_or(0),
+                    lods,
+                    uniform_buffer,
+                    bind_group,
+                    material_index: mesh.material_index,
                 }
             })
             .collect();
 
-        Ok(Model { meshes, materials })
+        Model {
+            meshes,
+            materials,
+            bounds: data.bounds,
+        }
+    }
+
+    fn is_mesh_transparent(&self, mesh: &Mesh) -> bool {
+        self.materials
+            .get(mesh.material_index)
+            .map_or(false, |material| material.transparent)
+    }
+
+    fn matches(&self, mesh: &Mesh, filter: MeshFilter) -> bool {
+        match filter {
+            MeshFilter::All => true,
+            MeshFilter::Opaque => !self.is_mesh_transparent(mesh),
+            MeshFilter::Transparent => self.is_mesh_transparent(mesh),
+        }
+    }
+
+    /// Number of levels of the most detailed mesh.
+    pub fn lod_count(&self) -> usize {
+        self.meshes.iter().map(Mesh::lod_count).max().unwrap_or(1)
+    }
+
+    /// Draw calls and triangles of drawing the matching meshes at a level of detail.
+    pub fn draw_stats(&self, filter: MeshFilter, lod: usize, instance_count: u32) -> (u32, u64) {
+        self.meshes
+            .iter()
+            .filter(|mesh| self.matches(mesh, filter))
+            .fold((0, 0), |(draw_calls, triangles), mesh| {
+                let mesh_triangles = (mesh.lod(lod).index_count / 3) as u64;
+                (
+                    draw_calls + 1,
+                    triangles + mesh_triangles * instance_count as u64,
+                )
+            })
+    }
+
+    pub fn has_transparent_meshes(&self) -> bool {
+        self.meshes
+            .iter()
+            .any(|mesh| self.is_mesh_transparent(mesh))
     }
 }
 
 pub trait DrawModel<'b> {
-    fn draw_mesh(&mut self, mesh: &'b Mesh, material: &'b Material, uniforms: &'b wgpu::BindGroup);
-    fn draw_model(&mut self, model: &'b Model, uniforms: &'b wgpu::BindGroup);
-
-    fn draw_mesh_instanced(
+    /// Draws a level of detail, clamped to the simplest level of the mesh.
+    fn draw_mesh_lod_instanced(
         &mut self,
         mesh: &'b Mesh,
         material: &'b Material,
         uniforms: &'b wgpu::BindGroup,
+        lod: usize,
         instances: Range<u32>,
     );
-    fn draw_model_instanced(
+    fn draw_model_lod_instanced(
         &mut self,
         model: &'b Model,
         uniforms: &'b wgpu::BindGroup,
+        lod: usize,
         instances: Range<u32>,
+        filter: MeshFilter,
     );
+
+    /// Draws a level of detail of the meshes with the arguments found in
+    /// `indirect_buffer` at [`draw_args_offset`].
+    fn draw_model_lod_indirect(
+        &mut self,
+        model: &'b Model,
+        uniforms: &'b wgpu::BindGroup,
+        indirect_buffer: &'b wgpu::Buffer,
+        lod: usize,
+        filter: MeshFilter,
+    );
+
+    /// Draws the mesh without binding any material or uniforms, for depth-only passes.
+    fn draw_mesh_geometry_instanced(&mut self, mesh: &'b Mesh, instances: Range<u32>);
+    fn draw_model_geometry_instanced(&mut self, model: &'b Model, instances: Range<u32>);
 }
 
 impl<'a, 'b> DrawModel<'b> for wgpu::RenderPass<'a>
 where
     'b: 'a,
 {
-    fn draw_mesh(&mut self, mesh: &'b Mesh, material: &'b Material, uniforms: &'b wgpu::BindGroup) {
-        self.draw_mesh_instanced(mesh, material, uniforms, 0..1);
-    }
-
-    fn draw_model(&mut self, model: &'b Model, uniforms: &'b wgpu::BindGroup) {
-        self.draw_model_instanced(model, uniforms, 0..1)
-    }
-
-    fn draw_mesh_instanced(
+    fn draw_mesh_lod_instanced(
         &mut self,
         mesh: &'b Mesh,
         material: &'b Material,
         uniforms: &'b wgpu::BindGroup,
+        lod: usize,
         instances: Range<u32>,
     ) {
+        let lod = mesh.lod(lod);
         self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
-        self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        self.set_index_buffer(lod.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
         self.set_bind_group(0, &material.bind_group, &[]);
         self.set_bind_group(1, &uniforms, &[]);
-        self.draw_indexed(0..mesh.index_count, 0, instances);
+        self.set_bind_group(3, &mesh.bind_group, &[]);
+        self.draw_indexed(0..lod.index_count, 0, instances);
     }
 
-    fn draw_model_instanced(
+    fn draw_model_lod_instanced(
         &mut self,
         model: &'b Model,
         uniforms: &'b wgpu::BindGroup,
+        lod: usize,
         instances: Range<u32>,
+        filter: MeshFilter,
     ) {
         for mesh in &model.meshes {
-            let material = &model.materials[mesh.material_index];
-            self.draw_mesh_instanced(mesh, material, uniforms, instances.clone());
+            if model.matches(mesh, filter) {
+                let material = &model.materials[mesh.material_index];
+                self.draw_mesh_lod_instanced(mesh, material, uniforms, lod, instances.clone());
+            }
+        }
+    }
+
+    fn draw_model_lod_indirect(
+        &mut self,
+        model: &'b Model,
+        uniforms: &'b wgpu::BindGroup,
+        indirect_buffer: &'b wgpu::Buffer,
+        lod: usize,
+        filter: MeshFilter,
+    ) {
+        for (index, mesh) in model.meshes.iter().enumerate() {
+            if model.matches(mesh, filter) {
+                let material = &model.materials[mesh.material_index];
+                self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                self.set_index_buffer(
+                    mesh.lod(lod).index_buffer.slice(..),
+                    wgpu::IndexFormat::Uint32,
+                );
+                self.set_bind_group(0, &material.bind_group, &[]);
+                self.set_bind_group(1, &uniforms, &[]);
+                self.set_bind_group(3, &mesh.bind_group, &[]);
+                self.draw_indexed_indirect(indirect_buffer, draw_args_offset(model, lod, index));
+            }
+        }
+    }
+
+    fn draw_mesh_geometry_instanced(&mut self, mesh: &'b Mesh, instances: Range<u32>) {
+        let lod = &mesh.lods[0];
+        self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+        self.set_index_buffer(lod.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        self.draw_indexed(0..lod.index_count, 0, instances);
+    }
+
+    fn draw_model_geometry_instanced(&mut self, model: &'b Model, instances: Range<u32>) {
+        for mesh in &model.meshes {
+            self.draw_mesh_geometry_instanced(mesh, instances.clone());
         }
     }
 }
@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+
+use cgmath::{InnerSpace, Vector3};
+
+use crate::vertex::ModelVertex;
+
+/// Normal of degenerate triangles, which have no direction of their own.
+const FALLBACK_NORMAL: [f32; 3] = [0.0, 1.0, 0.0];
+
+/// How normals are generated for meshes that have none.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NormalMode {
+    /// Averages the triangles around each position, weighted by their area.
+    Smooth,
+    /// Gives each triangle its own vertices, facing the same way as the triangle.
+    Flat,
+}
+
+pub fn parse(name: &str) -> Option<NormalMode> {
+    match name.to_ascii_lowercase().as_str() {
+        "smooth" => Some(NormalMode::Smooth),
+        "flat" => Some(NormalMode::Flat),
+        _ => None,
+    }
+}
+
+/// Replaces the normals of a mesh, flat shading duplicates the shared vertices.
+pub fn generate(mode: NormalMode, vertices: &mut Vec<ModelVertex>, indices: &mut Vec<u32>) {
+    match mode {
+        NormalMode::Smooth => smooth(vertices, indices),
+        NormalMode::Flat => {
+            let (flat_vertices, flat_indices) = flatten(vertices, indices);
+            *vertices = flat_vertices;
+            *indices = flat_indices;
+        }
+    }
+}
+
+/// Generates normals for the vertices flagged in `missing` and keeps the others.
+///
+/// Flat shading still gives every triangle its own vertices, those that had a normal
+/// keeping it.
+pub fn generate_missing(
+    mode: NormalMode,
+    vertices: &mut Vec<ModelVertex>,
+    indices: &mut Vec<u32>,
+    missing: &[bool],
+) {
+    match mode {
+        NormalMode::Smooth => {
+            let mut generated = vertices.clone();
+            smooth(&mut generated, indices);
+            for ((vertex, generated), &missing) in vertices.iter_mut().zip(&generated).zip(missing)
+            {
+                if missing {
+                    vertex.normal = generated.normal;
+                }
+            }
+        }
+        NormalMode::Flat => {
+            let (mut flat_vertices, flat_indices) = flatten(vertices, indices);
+            for (flat_vertex, &index) in flat_vertices.iter_mut().zip(indices.iter()) {
+                if !missing[index as usize] {
+                    flat_vertex.normal = vertices[index as usize].normal;
+                }
+            }
+            *vertices = flat_vertices;
+            *indices = flat_indices;
+        }
+    }
+}
+
+/// Cross product of two edges, its length is twice the area of the triangle.
+fn face_normal(a: &ModelVertex, b: &ModelVertex, c: &ModelVertex) -> Vector3<f32> {
+    let edge_1 = Vector3::from(b.position) - Vector3::from(a.position);
+    let edge_2 = Vector3::from(c.position) - Vector3::from(a.position);
+    edge_1.cross(edge_2)
+}
+
+fn normalize_or_fallback(normal: Vector3<f32>) -> [f32; 3] {
+    if normal.magnitude2() > 0.0 {
+        normal.normalize().into()
+    } else {
+        FALLBACK_NORMAL
+    }
+}
+
+/// Vertices at the same position share their normal, so seams between texture
+/// coordinates are not visible in the lighting.
+pub fn smooth(vertices: &mut [ModelVertex], indices: &[u32]) {
+    let key = |vertex: &ModelVertex| {
+        let [x, y, z] = vertex.position;
+        [x.to_bits(), y.to_bits(), z.to_bits()]
+    };
+
+    let mut sums: HashMap<[u32; 3], Vector3<f32>> = HashMap::new();
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [
+            &vertices[triangle[0] as usize],
+            &vertices[triangle[1] as usize],
+            &vertices[triangle[2] as usize],
+        ];
+        let normal = face_normal(a, b, c);
+        for &vertex in [a, b, c].iter() {
+            *sums
+                .entry(key(vertex))
+                .or_insert_with(|| Vector3::new(0.0, 0.0, 0.0)) += normal;
+        }
+    }
+
+    for vertex in vertices.iter_mut() {
+        let sum = sums
+            .get(&key(vertex))
+            .copied()
+            .unwrap_or_else(|| Vector3::new(0.0, 0.0, 0.0));
+        vertex.normal = normalize_or_fallback(sum);
+    }
+}
+
+/// Gives every triangle its own vertices, with the normal of its face.
+pub fn flatten(vertices: &[ModelVertex], indices: &[u32]) -> (Vec<ModelVertex>, Vec<u32>) {
+    let mut flat_vertices = Vec::with_capacity(indices.len());
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [
+            vertices[triangle[0] as usize],
+            vertices[triangle[1] as usize],
+            vertices[triangle[2] as usize],
+        ];
+        let normal = normalize_or_fallback(face_normal(&a, &b, &c));
+        for &vertex in [a, b, c].iter() {
+            flat_vertices.push(ModelVertex { normal, ..vertex });
+        }
+    }
+    let flat_indices = (0..flat_vertices.len() as u32).collect();
+    (flat_vertices, flat_indices)
+}
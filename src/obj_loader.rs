@@ -0,0 +1,429 @@
+use std::{error::Error, fmt, io::BufReader, path::Path};
+
+use crate::model::{
+    white_image, LoadWarning, MaterialData, MeshData, ModelData, DEFAULT_SHININESS,
+};
+use crate::normals::{self, NormalMode};
+use crate::vertex::ModelVertex;
+
+/// Why an OBJ file could not be loaded.
+///
+/// Broken material libraries and textures are only warnings, see [`load`].
+#[derive(Debug)]
+pub enum ObjError {
+    /// The OBJ file could not be opened or parsed.
+    Obj(tobj::LoadError),
+    /// The file has no triangle to draw, only points, lines or nothing.
+    NoGeometry,
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ObjError::Obj(e) => write!(f, "invalid OBJ file: {}", e),
+            ObjError::NoGeometry => write!(f, "OBJ file has no triangles"),
+        }
+    }
+}
+
+impl Error for ObjError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ObjError::Obj(e) => Some(e),
+            ObjError::NoGeometry => None,
+        }
+    }
+}
+
+/// Reads an OBJ file and its materials.
+///
+/// Faces are triangulated and points and lines dropped. Vertices without normals get
+/// normals generated with `normal_mode`, and vertices without texture coordinates
+/// sample the corner of their texture. A material library that cannot be read leaves
+/// every mesh with the default material, and a texture that cannot be read is replaced
+/// by a white one; both are returned as warnings next to the model.
+pub fn load(
+    path: &Path,
+    normal_mode: NormalMode,
+) -> Result<(ModelData, Vec<LoadWarning>), ObjError> {
+    let options = tobj::LoadOptions {
+        single_index: true,
+        triangulate: true,
+        ignore_points: true,
+        ignore_lines: true,
+    };
+    let source = std::fs::read(path).map_err(|_| ObjError::Obj(tobj::LoadError::OpenFileFailed))?;
+    let source = with_placeholder_attributes(&String::from_utf8_lossy(&source));
+    let containing_folder = path.parent().unwrap_or_else(|| Path::new(""));
+    let (obj_models, obj_materials) = tobj::load_obj_buf(
+        &mut BufReader::new(source.as_bytes()),
+        &options,
+        |mtl_path| tobj::load_mtl(containing_folder.join(mtl_path)),
+    )
+    .map_err(ObjError::Obj)?;
+    let mut warnings = Vec::new();
+    let obj_materials = obj_materials.unwrap_or_else(|e| {
+        warnings.push(LoadWarning::MaterialLibrary(e));
+        Vec::new()
+    });
+
+    let mut materials: Vec<_> = obj_materials
+        .into_iter()
+        .map(|obj_mat| load_material(obj_mat, containing_folder, &mut warnings))
+        .collect();
+
+    // Used by meshes without a material, or with one missing from the libraries
+    let default_material = materials.len();
+    let mut uses_default_material = false;
+
+    let mut meshes = Vec::new();
+    for obj_model in obj_models {
+        let mesh = obj_model.mesh;
+        if mesh.indices.is_empty() {
+            continue;
+        }
+
+        // Every vertex has attributes, the missing ones being the zero placeholders
+        let vertex_count = mesh.positions.len() / 3;
+        let mut vertices: Vec<ModelVertex> = (0..vertex_count)
+            .map(|i| ModelVertex {
+                position: [
+                    mesh.positions[i * 3],
+                    mesh.positions[i * 3 + 1],
+                    mesh.positions[i * 3 + 2],
+                ],
+                tex_coords: [mesh.texcoords[i * 2], mesh.texcoords[i * 2 + 1]],
+                normal: [
+                    mesh.normals[i * 3],
+                    mesh.normals[i * 3 + 1],
+                    mesh.normals[i * 3 + 2],
+                ],
+            })
+            .collect();
+        let mut indices = mesh.indices;
+        let missing_normals: Vec<bool> = vertices
+            .iter()
+            .map(|vertex| vertex.normal == [0.0; 3])
+            .collect();
+        if missing_normals.contains(&true) {
+            normals::generate_missing(normal_mode, &mut vertices, &mut indices, &missing_normals);
+        }
+
+        let material_index = match mesh.material_id {
+            Some(index) if index < default_material => index,
+            _ => {
+                uses_default_material = true;
+                default_material
+            }
+        };
+
+        meshes.push((
+            MeshData {
+                name: obj_model.name,
+                vertices,
+                lods: Vec::new(),
+                material_index,
+            },
+            indices,
+        ));
+    }
+
+    if meshes.is_empty() {
+        return Err(ObjError::NoGeometry);
+    }
+    if uses_default_material {
+        materials.push(MaterialData::default_material(DEFAULT_SHININESS));
+    }
+
+    let model = ModelData::from_meshes(format!("{:?}", path), meshes, materials);
+    Ok((model, warnings))
+}
+
+/// Adds a zero texture coordinate and normal in front of the file and points the face
+/// corners lacking them at these placeholders.
+///
+/// tobj drops missing attributes without telling which vertex lacked them, so every
+/// corner must have both for them to line up with the positions.
+fn with_placeholder_attributes(source: &str) -> String {
+    // Absolute indices move by one, relative ones still count back from the face
+    let shift = |index: Option<&str>| match index {
+        None | Some("") => "1".to_string(),
+        Some(index) => match index.parse::<isize>() {
+            Ok(index) if index > 0 => (index + 1).to_string(),
+            _ => index.to_string(),
+        },
+    };
+
+    let mut output = String::with_capacity(source.len() + 64);
+    output.push_str("vt 0 0\nvn 0 0 0\n");
+    for line in source.lines() {
+        let mut words = line.split_whitespace();
+        if words.next() == Some("f") {
+            output.push('f');
+            for corner in words.take_while(|word| !word.starts_with('#')) {
+                let mut indices = corner.split('/');
+                let position = indices.next().unwrap_or_default();
+                let tex_coords = shift(indices.next());
+                let normal = shift(indices.next());
+                output.push_str(&format!(" {}/{}/{}", position, tex_coords, normal));
+            }
+        } else {
+            output.push_str(line);
+        }
+        output.push('\n');
+    }
+    output
+}
+
+/// Materials without a diffuse texture use their diffuse color instead.
+fn load_material(
+    obj_mat: tobj::Material,
+    folder: &Path,
+    warnings: &mut Vec<LoadWarning>,
+) -> MaterialData {
+    let (diffuse_image, base_color) = if obj_mat.diffuse_texture.is_empty() {
+        let [r, g, b] = obj_mat.diffuse;
+        (white_image(), [r, g, b, 1.0])
+    } else {
+        let path = folder.join(&obj_mat.diffuse_texture);
+        let image = image::open(&path).unwrap_or_else(|e| {
+            warnings.push(LoadWarning::Texture {
+                name: format!("{:?}", path),
+                error: Box::new(e),
+            });
+            white_image()
+        });
+        (image, [1.0; 4])
+    };
+
+    // `d` in the MTL file, 1.0 when not specified
+    let opacity = obj_mat.dissolve;
+    let transparent = opacity < 1.0
+        || (diffuse_image.color().has_alpha()
+            && diffuse_image.to_rgba8().pixels().any(|p| p[3] < u8::MAX));
+
+    MaterialData {
+        name: obj_mat.name,
+        diffuse_image,
+        opacity,
+        transparent,
+        base_color,
+        emissive: [0.0; 3],
+        metallic: 0.0,
+        shininess: DEFAULT_SHININESS,
+        alpha_cutoff: 0.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/obj")
+            .join(name)
+    }
+
+    fn load_fixture(name: &str, normal_mode: NormalMode) -> Result<ModelData, ObjError> {
+        load(&fixture(name), normal_mode).map(|(data, _)| data)
+    }
+
+    fn assert_normal(vertex: &ModelVertex, expected: [f32; 3]) {
+        for (actual, expected) in vertex.normal.iter().zip(expected.iter()) {
+            assert!(
+                (actual - expected).abs() < 1e-5,
+                "normal {:?}, expected {:?}",
+                vertex.normal,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn triangle_without_normals_or_tex_coords() {
+        for &mode in [NormalMode::Smooth, NormalMode::Flat].iter() {
+            let data = load_fixture("triangle.obj", mode).unwrap();
+            assert_eq!(data.meshes.len(), 1);
+            let mesh = &data.meshes[0];
+            assert_eq!(mesh.vertices.len(), 3);
+            assert_eq!(mesh.lods[0].len(), 3);
+            for vertex in &mesh.vertices {
+                assert_normal(vertex, [0.0, 0.0, 1.0]);
+                assert_eq!(vertex.tex_coords, [0.0, 0.0]);
+            }
+        }
+    }
+
+    #[test]
+    fn smooth_normals_average_shared_positions() {
+        let data = load_fixture("fold.obj", NormalMode::Smooth).unwrap();
+        let mesh = &data.meshes[0];
+        assert_eq!(mesh.vertices.len(), 4);
+        let diagonal = std::f32::consts::FRAC_1_SQRT_2;
+        for vertex in &mesh.vertices {
+            match vertex.position {
+                [0.0, 0.0, 0.0] | [0.0, 1.0, 0.0] => {
+                    assert_normal(vertex, [diagonal, 0.0, diagonal])
+                }
+                [1.0, 0.0, 0.0] => assert_normal(vertex, [0.0, 0.0, 1.0]),
+                [0.0, 0.0, 1.0] => assert_normal(vertex, [1.0, 0.0, 0.0]),
+                position => panic!("unexpected vertex {:?}", position),
+            }
+        }
+    }
+
+    #[test]
+    fn flat_normals_split_shared_vertices() {
+        let data = load_fixture("fold.obj", NormalMode::Flat).unwrap();
+        let mesh = &data.meshes[0];
+        assert_eq!(mesh.vertices.len(), 6);
+        assert_eq!(mesh.lods[0], vec![0, 1, 2, 3, 4, 5]);
+        for vertex in &mesh.vertices[..3] {
+            assert_normal(vertex, [0.0, 0.0, 1.0]);
+        }
+        for vertex in &mesh.vertices[3..] {
+            assert_normal(vertex, [1.0, 0.0, 0.0]);
+        }
+    }
+
+    #[test]
+    fn quad_with_tex_coords_is_triangulated() {
+        let data = load_fixture("quad_tex_coords.obj", NormalMode::Smooth).unwrap();
+        let mesh = &data.meshes[0];
+        assert_eq!(mesh.lods[0].len(), 6);
+        assert!(mesh.vertices.iter().any(|v| v.tex_coords == [1.0, 1.0]));
+        for vertex in &mesh.vertices {
+            assert_normal(vertex, [0.0, 0.0, 1.0]);
+        }
+    }
+
+    #[test]
+    fn existing_normals_are_kept() {
+        for &mode in [NormalMode::Smooth, NormalMode::Flat].iter() {
+            let data = load_fixture("normals.obj", mode).unwrap();
+            let mesh = &data.meshes[0];
+            assert_eq!(mesh.vertices.len(), 3);
+            for vertex in &mesh.vertices {
+                assert_normal(vertex, [0.0, 1.0, 0.0]);
+            }
+        }
+    }
+
+    #[test]
+    fn partial_tex_coords_are_defaulted_per_vertex() {
+        let data = load_fixture("partial_tex_coords.obj", NormalMode::Smooth).unwrap();
+        let mesh = &data.meshes[0];
+        let tex_coords: Vec<_> = mesh.lods[0]
+            .iter()
+            .map(|&index| mesh.vertices[index as usize].tex_coords)
+            .collect();
+        assert_eq!(
+            tex_coords,
+            vec![
+                [0.0, 0.0],
+                [1.0, 0.0],
+                [1.0, 1.0],
+                [0.0, 0.0],
+                [0.0, 0.0],
+                [0.0, 0.0]
+            ]
+        );
+    }
+
+    #[test]
+    fn partial_normals_are_generated_per_vertex() {
+        for &mode in [NormalMode::Smooth, NormalMode::Flat].iter() {
+            let data = load_fixture("partial_normals.obj", mode).unwrap();
+            let mesh = &data.meshes[0];
+            let (first, second) = mesh.lods[0].split_at(3);
+            for &index in first {
+                assert_normal(&mesh.vertices[index as usize], [0.0, 1.0, 0.0]);
+            }
+            let far_corner = second
+                .iter()
+                .map(|&index| &mesh.vertices[index as usize])
+                .find(|vertex| vertex.position == [0.0, 0.0, 1.0])
+                .unwrap();
+            assert_normal(far_corner, [1.0, 0.0, 0.0]);
+        }
+    }
+
+    #[test]
+    fn meshes_without_material_use_a_default_one() {
+        let (data, warnings) = load(&fixture("triangle.obj"), NormalMode::Smooth).unwrap();
+        assert!(warnings.is_empty());
+        assert_eq!(data.materials.len(), 1);
+        assert_eq!(data.meshes[0].material_index, 0);
+        assert_eq!(data.materials[0].base_color, [1.0; 4]);
+    }
+
+    #[test]
+    fn untextured_material_uses_diffuse_color() {
+        let data = load_fixture("colored.obj", NormalMode::Smooth).unwrap();
+        assert_eq!(data.materials.len(), 1);
+        assert_eq!(data.materials[0].name, "red");
+        assert_eq!(data.materials[0].base_color, [1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(data.meshes[0].material_index, 0);
+    }
+
+    #[test]
+    fn missing_file_is_an_error() {
+        let result = load_fixture("does_not_exist.obj", NormalMode::Smooth);
+        assert!(matches!(
+            result,
+            Err(ObjError::Obj(tobj::LoadError::OpenFileFailed))
+        ));
+    }
+
+    #[test]
+    fn invalid_position_is_an_error() {
+        let result = load_fixture("invalid_position.obj", NormalMode::Smooth);
+        assert!(matches!(
+            result,
+            Err(ObjError::Obj(tobj::LoadError::PositionParseError))
+        ));
+    }
+
+    #[test]
+    fn out_of_range_face_is_an_error() {
+        let result = load_fixture("out_of_range_face.obj", NormalMode::Smooth);
+        assert!(matches!(
+            result,
+            Err(ObjError::Obj(tobj::LoadError::FaceVertexOutOfBounds))
+        ));
+    }
+
+    #[test]
+    fn missing_material_library_uses_default_material() {
+        let (data, warnings) = load(&fixture("missing_mtl.obj"), NormalMode::Smooth).unwrap();
+        assert!(matches!(warnings[..], [LoadWarning::MaterialLibrary(_)]));
+        assert_eq!(data.materials.len(), 1);
+        assert_eq!(data.materials[0].name, "Default material");
+        assert_eq!(data.meshes[0].material_index, 0);
+    }
+
+    #[test]
+    fn missing_texture_is_replaced_by_white() {
+        let (data, warnings) = load(&fixture("missing_texture.obj"), NormalMode::Smooth).unwrap();
+        match &warnings[..] {
+            [LoadWarning::Texture { name, .. }] => assert!(name.contains("missing.png")),
+            warnings => panic!("unexpected warnings {:?}", warnings),
+        }
+        let material = &data.materials[data.meshes[0].material_index];
+        assert_eq!(material.name, "textured");
+        assert_eq!(
+            material.diffuse_image.to_rgba8().into_raw(),
+            vec![u8::MAX; 4]
+        );
+        assert!(!material.transparent);
+    }
+
+    #[test]
+    fn points_and_lines_only_is_an_error() {
+        let result = load_fixture("points_and_lines.obj", NormalMode::Smooth);
+        assert!(matches!(result, Err(ObjError::NoGeometry)));
+    }
+}
@@ -0,0 +1,262 @@
+use imgui::{im_str, ColorEdit, Slider};
+
+use crate::{
+    instance::Instance,
+    model::{DrawModel, Model},
+    resource_stats::ResourceStats,
+    texture::Texture,
+    vertex::ModelVertex,
+};
+
+/// Copies of the silhouette drawn around it, see `outline.vert`.
+const OUTLINE_COPIES: u32 = 8;
+const STENCIL_REFERENCE: u32 = 1;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct OutlineParams {
+    model_view_proj: [[f32; 4]; 4],
+    color: [f32; 4],
+    // Thickness in clip space units, per axis
+    offset: [f32; 2],
+    _padding: [u32; 2],
+}
+
+/// Pass drawing a silhouette around the selected instance, through a stencil buffer.
+///
+/// The instance is first drawn into the stencil only, then copies of it shifted by the
+/// thickness in every direction are drawn where the stencil is not set. The outline
+/// is drawn over the scene, so the selection stays visible behind other instances.
+pub struct OutlinePass {
+    pub color: [f32; 4],
+    pub thickness: i32,
+
+    params_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    stencil_pipeline: wgpu::RenderPipeline,
+    outline_pipeline: wgpu::RenderPipeline,
+}
+
+impl OutlinePass {
+    pub fn new(
+        device: &wgpu::Device,
+        stats: &mut ResourceStats,
+        output_format: wgpu::TextureFormat,
+    ) -> Self {
+        let params_buffer = stats.create_buffer(
+            device,
+            &wgpu::BufferDescriptor {
+                label: Some("Outline params buffer"),
+                size: std::mem::size_of::<OutlineParams>() as _,
+                usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+                mapped_at_creation: false,
+            },
+        );
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStage::VERTEX | wgpu::ShaderStage::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("Outline bind group layout"),
+        });
+        let bind_group = stats.create_bind_group(
+            device,
+            &wgpu::BindGroupDescriptor {
+                layout: &bind_group_layout,
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Buffer {
+                        buffer: &params_buffer,
+                        offset: 0,
+                        size: None,
+                    },
+                }],
+                label: Some("Outline bind group"),
+            },
+        );
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Outline pipeline layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let vs_module = device.create_shader_module(&wgpu::include_spirv!(concat!(
+            env!("OUT_DIR"),
+            "/outline.vert.spv"
+        )));
+        let fs_module = device.create_shader_module(&wgpu::include_spirv!(concat!(
+            env!("OUT_DIR"),
+            "/outline.frag.spv"
+        )));
+
+        let create_pipeline = |label, stencil_face: wgpu::StencilFaceState, write_mask| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &vs_module,
+                    entry_point: "main",
+                    buffers: &[ModelVertex::desc()],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &fs_module,
+                    entry_point: "main",
+                    targets: &[wgpu::ColorTargetState {
+                        format: output_format,
+                        color_blend: wgpu::BlendState {
+                            src_factor: wgpu::BlendFactor::SrcAlpha,
+                            dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                            operation: wgpu::BlendOperation::Add,
+                        },
+                        alpha_blend: wgpu::BlendState::REPLACE,
+                        write_mask,
+                    }],
+                }),
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: Texture::DEPTH_STENCIL_FORMAT,
+                    depth_write_enabled: false,
+                    depth_compare: wgpu::CompareFunction::Always,
+                    stencil: wgpu::StencilState {
+                        front: stencil_face.clone(),
+                        back: stencil_face,
+                        read_mask: !0,
+                        write_mask: !0,
+                    },
+                    bias: wgpu::DepthBiasState::default(),
+                    clamp_depth: false,
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    strip_index_format: None,
+                    front_face: wgpu::FrontFace::Ccw,
+                    // Back faces fill the silhouette of open meshes too
+                    cull_mode: wgpu::CullMode::None,
+                    polygon_mode: wgpu::PolygonMode::Fill,
+                },
+                multisample: wgpu::MultisampleState {
+                    count: 1,
+                    mask: !0,
+                    alpha_to_coverage_enabled: false,
+                },
+            })
+        };
+
+        let stencil_pipeline = create_pipeline(
+            "Outline stencil pipeline",
+            wgpu::StencilFaceState {
+                compare: wgpu::CompareFunction::Always,
+                fail_op: wgpu::StencilOperation::Keep,
+                depth_fail_op: wgpu::StencilOperation::Keep,
+                pass_op: wgpu::StencilOperation::Replace,
+            },
+            wgpu::ColorWrite::empty(),
+        );
+        let outline_pipeline = create_pipeline(
+            "Outline pipeline",
+            wgpu::StencilFaceState {
+                compare: wgpu::CompareFunction::NotEqual,
+                fail_op: wgpu::StencilOperation::Keep,
+                depth_fail_op: wgpu::StencilOperation::Keep,
+                // Overlapping copies blend the outline color only once
+                pass_op: wgpu::StencilOperation::Replace,
+            },
+            wgpu::ColorWrite::ALL,
+        );
+
+        OutlinePass {
+            color: [1.0, 0.6, 0.0, 1.0],
+            thickness: 2,
+
+            params_buffer,
+            bind_group,
+            stencil_pipeline,
+            outline_pipeline,
+        }
+    }
+
+    /// `selected` is the instance to outline, if any, and `output_size` the size of the
+    /// frame in pixels.
+    pub fn update(
+        &self,
+        queue: &wgpu::Queue,
+        selected: Option<&Instance>,
+        view_proj: cgmath::Matrix4<f32>,
+        output_size: (u32, u32),
+    ) {
+        let selected = match selected {
+            Some(selected) => selected,
+            None => return,
+        };
+
+        // Clip space spans 2 units across the frame
+        let thickness = self.thickness as f32 * 2.0;
+        let params = OutlineParams {
+            model_view_proj: (view_proj * selected.model_matrix()).into(),
+            color: self.color,
+            offset: [
+                thickness / output_size.0 as f32,
+                thickness / output_size.1 as f32,
+            ],
+            _padding: [0; 2],
+        };
+        queue.write_buffer(&self.params_buffer, 0, bytemuck::cast_slice(&[params]));
+    }
+
+    /// Blends the outline over `output`. `stencil` must be single sampled, of
+    /// [`Texture::DEPTH_STENCIL_FORMAT`] and the size of `output`.
+    pub fn encode(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        model: &Model,
+        output: &wgpu::TextureView,
+        stencil: &wgpu::TextureView,
+    ) {
+        let mut outline_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Outline render pass"),
+            color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
+                attachment: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachmentDescriptor {
+                attachment: stencil,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: false,
+                }),
+                stencil_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(0),
+                    store: false,
+                }),
+            }),
+        });
+
+        outline_pass.set_bind_group(0, &self.bind_group, &[]);
+        outline_pass.set_stencil_reference(STENCIL_REFERENCE);
+
+        // Instance 0 is the silhouette itself, the following ones its shifted copies
+        outline_pass.set_pipeline(&self.stencil_pipeline);
+        outline_pass.draw_model_geometry_instanced(model, 0..1);
+        outline_pass.set_pipeline(&self.outline_pipeline);
+        outline_pass.draw_model_geometry_instanced(model, 1..OUTLINE_COPIES + 1);
+    }
+
+    /// Outline settings, shown inside the window of the selection.
+    pub fn build_ui(&mut self, ui: &imgui::Ui) {
+        ColorEdit::new(im_str!("Outline"), &mut self.color).build(&ui);
+        Slider::new(im_str!("Thickness"))
+            .range(1..=8)
+            .build(&ui, &mut self.thickness);
+    }
+}
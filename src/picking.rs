@@ -0,0 +1,205 @@
+use std::{future::Future, pin::Pin};
+
+use futures::FutureExt;
+
+use crate::{resource_stats::ResourceStats, texture::Texture};
+
+const OBJECT_IDS_LABEL: &str = "Object ID texture";
+
+type MapFuture = Pin<Box<dyn Future<Output = Result<(), wgpu::BufferAsyncError>>>>;
+
+/// What is under the cursor when the user clicked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PickedObject {
+    pub instance: usize,
+    pub mesh: usize,
+}
+
+/// Object-ID render target and the readback of the pixel clicked on.
+///
+/// The main pass writes the ID of the closest surface alongside its color, or an
+/// object ID pass does when the main pass is multisampled. A click copies the pixel
+/// under the cursor to a small buffer, which is mapped without blocking and polled
+/// on the following frames.
+pub struct Picking {
+    object_ids: Texture,
+    size: (u32, u32),
+
+    readback_buffer: wgpu::Buffer,
+    requested: Option<(u32, u32)>,
+    copy_encoded: bool,
+    mapping: Option<MapFuture>,
+
+    pub picked: Option<PickedObject>,
+}
+
+impl Picking {
+    pub fn new(device: &wgpu::Device, stats: &mut ResourceStats, width: u32, height: u32) -> Self {
+        let object_ids = Self::create_object_ids(device, stats, width, height);
+
+        // A single texel, but copies still need a full aligned row
+        let readback_buffer = stats.create_buffer(
+            device,
+            &wgpu::BufferDescriptor {
+                label: Some("Picking readback buffer"),
+                size: wgpu::COPY_BYTES_PER_ROW_ALIGNMENT as _,
+                usage: wgpu::BufferUsage::MAP_READ | wgpu::BufferUsage::COPY_DST,
+                mapped_at_creation: false,
+            },
+        );
+
+        Picking {
+            object_ids,
+            size: (width, height),
+
+            readback_buffer,
+            requested: None,
+            copy_encoded: false,
+            mapping: None,
+
+            picked: None,
+        }
+    }
+
+    fn object_ids_usage() -> wgpu::TextureUsage {
+        wgpu::TextureUsage::RENDER_ATTACHMENT
+            | wgpu::TextureUsage::SAMPLED
+            | wgpu::TextureUsage::COPY_SRC
+    }
+
+    fn create_object_ids(
+        device: &wgpu::Device,
+        stats: &mut ResourceStats,
+        width: u32,
+        height: u32,
+    ) -> Texture {
+        Texture::create_transient(
+            device,
+            stats,
+            width,
+            height,
+            Texture::OBJECT_ID_FORMAT,
+            1,
+            Self::object_ids_usage(),
+            OBJECT_IDS_LABEL,
+        )
+    }
+
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        stats: &mut ResourceStats,
+        width: u32,
+        height: u32,
+    ) {
+        stats.release_texture(&Texture::transient_descriptor(
+            self.size.0,
+            self.size.1,
+            Texture::OBJECT_ID_FORMAT,
+            1,
+            Self::object_ids_usage(),
+            OBJECT_IDS_LABEL,
+        ));
+        self.object_ids = Self::create_object_ids(device, stats, width, height);
+        self.size = (width, height);
+    }
+
+    /// Single sampled object IDs of the last rendered frame.
+    pub fn object_id_view(&self) -> &wgpu::TextureView {
+        &self.object_ids.view
+    }
+
+    /// Asks for the object under the pixel `(x, y)` of the next rendered frame.
+    pub fn request(&mut self, x: u32, y: u32) {
+        self.requested = Some((x, y));
+    }
+
+    /// Records the copy of the requested pixel, unless a previous readback is still pending.
+    pub fn encode_readback(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        if self.copy_encoded || self.mapping.is_some() {
+            return;
+        }
+        let (x, y) = match self.requested.take() {
+            Some(pixel) => pixel,
+            None => return,
+        };
+
+        encoder.copy_texture_to_buffer(
+            wgpu::TextureCopyView {
+                texture: &self.object_ids.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d {
+                    x: x.min(self.size.0.saturating_sub(1)),
+                    y: y.min(self.size.1.saturating_sub(1)),
+                    z: 0,
+                },
+            },
+            wgpu::BufferCopyView {
+                buffer: &self.readback_buffer,
+                layout: wgpu::TextureDataLayout {
+                    offset: 0,
+                    bytes_per_row: wgpu::COPY_BYTES_PER_ROW_ALIGNMENT,
+                    rows_per_image: 1,
+                },
+            },
+            wgpu::Extent3d {
+                width: 1,
+                height: 1,
+                depth: 1,
+            },
+        );
+        self.copy_encoded = true;
+    }
+
+    /// Starts mapping the readback buffer, once the copy has been submitted.
+    pub fn map_readback(&mut self) {
+        if !self.copy_encoded {
+            return;
+        }
+
+        self.copy_encoded = false;
+        let mapping = self
+            .readback_buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read);
+        self.mapping = Some(Box::pin(mapping));
+    }
+
+    /// Updates [`Picking::picked`] if the readback finished, without waiting for the GPU.
+    pub fn poll(&mut self, device: &wgpu::Device) {
+        let mapping = match &mut self.mapping {
+            Some(mapping) => mapping,
+            None => return,
+        };
+
+        device.poll(wgpu::Maintain::Poll);
+        let result = match mapping.as_mut().now_or_never() {
+            Some(result) => result,
+            None => return,
+        };
+        self.mapping = None;
+        result.expect("Failed to map picking readback buffer");
+
+        let ids = {
+            let data = self.readback_buffer.slice(..).get_mapped_range();
+            let read = |offset: usize| {
+                u32::from_ne_bytes([
+                    data[offset],
+                    data[offset + 1],
+                    data[offset + 2],
+                    data[offset + 3],
+                ])
+            };
+            [read(0), read(4)]
+        };
+        self.readback_buffer.unmap();
+
+        self.picked = match ids {
+            [0, _] => None,
+            [instance, mesh] => Some(PickedObject {
+                instance: instance as usize - 1,
+                mesh: mesh as usize,
+            }),
+        };
+    }
+}
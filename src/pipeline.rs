@@ -0,0 +1,249 @@
+use std::collections::HashMap;
+
+use crate::{instance::InstanceRaw, texture::Texture, vertex::ModelVertex};
+
+/// What the main pass displays.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ViewMode {
+    Lit,
+    UnlitAlbedo,
+    Normals,
+    TexCoords,
+    Depth,
+}
+
+impl ViewMode {
+    pub const ALL: [ViewMode; 5] = [
+        ViewMode::Lit,
+        ViewMode::UnlitAlbedo,
+        ViewMode::Normals,
+        ViewMode::TexCoords,
+        ViewMode::Depth,
+    ];
+
+    pub fn shader(self) -> ShaderVariant {
+        match self {
+            ViewMode::Lit => ShaderVariant::Lit,
+            ViewMode::UnlitAlbedo => ShaderVariant::UnlitAlbedo,
+            ViewMode::Normals => ShaderVariant::Normals,
+            ViewMode::TexCoords => ShaderVariant::TexCoords,
+            ViewMode::Depth => ShaderVariant::Depth,
+        }
+    }
+}
+
+/// Fragment shader of a scene pipeline, all variants share the same vertex shader.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ShaderVariant {
+    Lit,
+    UnlitAlbedo,
+    Normals,
+    TexCoords,
+    Depth,
+    Wireframe,
+    /// Only writes object IDs, used when the color pass is multisampled.
+    ObjectId,
+}
+
+impl ShaderVariant {
+    fn create_shader_module(self, device: &wgpu::Device) -> wgpu::ShaderModule {
+        match self {
+            ShaderVariant::Lit => device.create_shader_module(&wgpu::include_spirv!(concat!(
+                env!("OUT_DIR"),
+                "/shader.frag.spv"
+            ))),
+            ShaderVariant::UnlitAlbedo => device.create_shader_module(&wgpu::include_spirv!(
+                concat!(env!("OUT_DIR"), "/debug_albedo.frag.spv")
+            )),
+            ShaderVariant::Normals => device.create_shader_module(&wgpu::include_spirv!(concat!(
+                env!("OUT_DIR"),
+                "/debug_normals.frag.spv"
+            ))),
+            ShaderVariant::TexCoords => device.create_shader_module(&wgpu::include_spirv!(
+                concat!(env!("OUT_DIR"), "/debug_tex_coords.frag.spv")
+            )),
+            ShaderVariant::Depth => device.create_shader_module(&wgpu::include_spirv!(concat!(
+                env!("OUT_DIR"),
+                "/debug_depth.frag.spv"
+            ))),
+            ShaderVariant::Wireframe => device.create_shader_module(&wgpu::include_spirv!(
+                concat!(env!("OUT_DIR"), "/wireframe.frag.spv")
+            )),
+            ShaderVariant::ObjectId => device.create_shader_module(&wgpu::include_spirv!(concat!(
+                env!("OUT_DIR"),
+                "/object_id.frag.spv"
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BlendMode {
+    /// Replaces the target color and writes depth.
+    Opaque,
+    /// Blends over the target color and only tests depth.
+    Alpha,
+}
+
+/// Everything that differs between the pipelines drawing the scene.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PipelineKey {
+    pub shader: ShaderVariant,
+    pub polygon_mode: wgpu::PolygonMode,
+    pub cull_mode: wgpu::CullMode,
+    pub blend: BlendMode,
+    pub sample_count: u32,
+}
+
+/// Scene pipelines created on first use and kept for the lifetime of the device.
+pub struct PipelineCache {
+    layout: wgpu::PipelineLayout,
+    vs_module: wgpu::ShaderModule,
+    fs_modules: HashMap<ShaderVariant, wgpu::ShaderModule>,
+    pipelines: HashMap<PipelineKey, wgpu::RenderPipeline>,
+}
+
+impl PipelineCache {
+    pub fn new(device: &wgpu::Device, layout: wgpu::PipelineLayout) -> Self {
+        let vs_module = device.create_shader_module(&wgpu::include_spirv!(concat!(
+            env!("OUT_DIR"),
+            "/shader.vert.spv"
+        )));
+
+        PipelineCache {
+            layout,
+            vs_module,
+            fs_modules: HashMap::new(),
+            pipelines: HashMap::new(),
+        }
+    }
+
+    /// Creates the pipeline for `key` if it does not exist yet.
+    ///
+    /// Rendering only borrows the cache immutably, so pipelines have to be prepared
+    /// whenever the settings they depend on change.
+    pub fn prepare(&mut self, device: &wgpu::Device, key: PipelineKey) {
+        if self.pipelines.contains_key(&key) {
+            return;
+        }
+
+        let fs_module = self
+            .fs_modules
+            .entry(key.shader)
+            .or_insert_with(|| key.shader.create_shader_module(device));
+        let pipeline = Self::create_pipeline(device, &self.layout, &self.vs_module, fs_module, key);
+        self.pipelines.insert(key, pipeline);
+    }
+
+    pub fn get(&self, key: PipelineKey) -> &wgpu::RenderPipeline {
+        self.pipelines
+            .get(&key)
+            .unwrap_or_else(|| panic!("Pipeline {:?} was not prepared", key))
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        vs_module: &wgpu::ShaderModule,
+        fs_module: &wgpu::ShaderModule,
+        key: PipelineKey,
+    ) -> wgpu::RenderPipeline {
+        let (color_blend, alpha_blend, depth_write_enabled, depth_compare) = match key.blend {
+            BlendMode::Opaque => (
+                wgpu::BlendState::REPLACE,
+                wgpu::BlendState::REPLACE,
+                true,
+                wgpu::CompareFunction::Less,
+            ),
+            BlendMode::Alpha => (
+                wgpu::BlendState {
+                    src_factor: wgpu::BlendFactor::SrcAlpha,
+                    dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                wgpu::BlendState {
+                    src_factor: wgpu::BlendFactor::One,
+                    dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                false,
+                wgpu::CompareFunction::LessEqual,
+            ),
+        };
+
+        // The overlay keeps the IDs of the surfaces below it
+        let object_id_write_mask = if key.shader == ShaderVariant::Wireframe {
+            wgpu::ColorWrite::empty()
+        } else {
+            wgpu::ColorWrite::ALL
+        };
+
+        // Pull lines slightly towards the camera so they win against the filled surface
+        let bias = if key.polygon_mode == wgpu::PolygonMode::Fill {
+            wgpu::DepthBiasState::default()
+        } else {
+            wgpu::DepthBiasState {
+                constant: -2,
+                slope_scale: -1.0,
+                clamp: 0.0,
+            }
+        };
+
+        let color_target = wgpu::ColorTargetState {
+            format: Texture::HDR_FORMAT,
+            color_blend,
+            alpha_blend,
+            write_mask: wgpu::ColorWrite::ALL,
+        };
+        let object_id_target = wgpu::ColorTargetState {
+            format: Texture::OBJECT_ID_FORMAT,
+            color_blend: wgpu::BlendState::REPLACE,
+            alpha_blend: wgpu::BlendState::REPLACE,
+            write_mask: object_id_write_mask,
+        };
+        // Integer formats are only guaranteed to support a single sample, so
+        // multisampled passes leave the IDs to a separate ObjectId pass
+        let targets = if key.shader == ShaderVariant::ObjectId {
+            vec![object_id_target]
+        } else if key.sample_count > 1 {
+            vec![color_target]
+        } else {
+            vec![color_target, object_id_target]
+        };
+
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(&format!("{:?} render pipeline", key.shader)),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: vs_module,
+                entry_point: "main",
+                buffers: &[ModelVertex::desc(), InstanceRaw::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: fs_module,
+                entry_point: "main",
+                targets: &targets,
+            }),
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Texture::DEPTH_FORMAT,
+                depth_write_enabled,
+                depth_compare,
+                stencil: wgpu::StencilState::default(),
+                bias,
+                clamp_depth: false,
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: key.cull_mode,
+                polygon_mode: key.polygon_mode,
+            },
+            multisample: wgpu::MultisampleState {
+                count: key.sample_count,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+        })
+    }
+}
@@ -0,0 +1,689 @@
+use std::cell::RefCell;
+
+use imgui::{im_str, Condition, Slider};
+
+use crate::{
+    render_graph::{PoolTextureId, RenderGraph, TextureHandle, TransientTexture},
+    resource_stats::ResourceStats,
+    texture::Texture,
+};
+
+const LUT_SIZE: u32 = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PostEffectKind {
+    Fxaa,
+    Vignette,
+    ChromaticAberration,
+    ColorGrading,
+    Bloom,
+}
+
+struct ParamSpec {
+    label: &'static str,
+    min: f32,
+    max: f32,
+    default: f32,
+}
+
+impl PostEffectKind {
+    fn name(self) -> &'static str {
+        match self {
+            PostEffectKind::Fxaa => "FXAA",
+            PostEffectKind::Vignette => "Vignette",
+            PostEffectKind::ChromaticAberration => "Chromatic aberration",
+            PostEffectKind::ColorGrading => "Color grading",
+            PostEffectKind::Bloom => "Bloom",
+        }
+    }
+
+    /// Parameters stored in order in the `u_params` vector of the effect shader.
+    fn params(self) -> &'static [ParamSpec] {
+        match self {
+            PostEffectKind::Fxaa => &[
+                ParamSpec {
+                    label: "Span max",
+                    min: 1.0,
+                    max: 16.0,
+                    default: 8.0,
+                },
+                ParamSpec {
+                    label: "Reduce mul",
+                    min: 0.0,
+                    max: 0.5,
+                    default: 1.0 / 8.0,
+                },
+                ParamSpec {
+                    label: "Reduce min",
+                    min: 0.0,
+                    max: 0.05,
+                    default: 1.0 / 128.0,
+                },
+            ],
+            PostEffectKind::Vignette => &[
+                ParamSpec {
+                    label: "Intensity",
+                    min: 0.0,
+                    max: 1.0,
+                    default: 0.5,
+                },
+                ParamSpec {
+                    label: "Radius",
+                    min: 0.0,
+                    max: 1.0,
+                    default: 0.75,
+                },
+                ParamSpec {
+                    label: "Softness",
+                    min: 0.0,
+                    max: 1.0,
+                    default: 0.45,
+                },
+            ],
+            PostEffectKind::ChromaticAberration => &[ParamSpec {
+                label: "Strength",
+                min: 0.0,
+                max: 0.05,
+                default: 0.005,
+            }],
+            PostEffectKind::ColorGrading => &[
+                ParamSpec {
+                    label: "Contrast",
+                    min: 0.5,
+                    max: 2.0,
+                    default: 1.1,
+                },
+                ParamSpec {
+                    label: "Saturation",
+                    min: 0.0,
+                    max: 2.0,
+                    default: 1.1,
+                },
+                ParamSpec {
+                    label: "Temperature",
+                    min: -1.0,
+                    max: 1.0,
+                    default: 0.1,
+                },
+                ParamSpec {
+                    label: "Strength",
+                    min: 0.0,
+                    max: 1.0,
+                    default: 1.0,
+                },
+            ],
+            PostEffectKind::Bloom => &[
+                ParamSpec {
+                    label: "Threshold",
+                    min: 0.0,
+                    max: 4.0,
+                    default: 1.0,
+                },
+                ParamSpec {
+                    label: "Intensity",
+                    min: 0.0,
+                    max: 2.0,
+                    default: 0.6,
+                },
+                ParamSpec {
+                    label: "Spread",
+                    min: 0.5,
+                    max: 4.0,
+                    default: 1.5,
+                },
+            ],
+        }
+    }
+
+    /// Whether the effect runs on the HDR scene color, before tonemapping.
+    fn is_hdr(self) -> bool {
+        self == PostEffectKind::Bloom
+    }
+
+    fn default_params(self) -> [f32; 4] {
+        let mut params = [0.0; 4];
+        for (param, spec) in params.iter_mut().zip(self.params()) {
+            *param = spec.default;
+        }
+        params
+    }
+
+    fn create_shader_module(self, device: &wgpu::Device) -> wgpu::ShaderModule {
+        match self {
+            PostEffectKind::Fxaa => device.create_shader_module(&wgpu::include_spirv!(concat!(
+                env!("OUT_DIR"),
+                "/post_fxaa.frag.spv"
+            ))),
+            PostEffectKind::Vignette => device.create_shader_module(&wgpu::include_spirv!(
+                concat!(env!("OUT_DIR"), "/post_vignette.frag.spv")
+            )),
+            PostEffectKind::ChromaticAberration => {
+                device.create_shader_module(&wgpu::include_spirv!(concat!(
+                    env!("OUT_DIR"),
+                    "/post_chromatic_aberration.frag.spv"
+                )))
+            }
+            PostEffectKind::ColorGrading => device.create_shader_module(&wgpu::include_spirv!(
+                concat!(env!("OUT_DIR"), "/post_color_grading.frag.spv")
+            )),
+            PostEffectKind::Bloom => device.create_shader_module(&wgpu::include_spirv!(concat!(
+                env!("OUT_DIR"),
+                "/post_bloom.frag.spv"
+            ))),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct PostParams {
+    params: [f32; 4],
+    texel_size: [f32; 2],
+    _padding: [f32; 2],
+}
+
+pub struct PostEffect {
+    pub kind: PostEffectKind,
+    pub enabled: bool,
+    pub params: [f32; 4],
+
+    pipeline: wgpu::RenderPipeline,
+    params_buffer: wgpu::Buffer,
+    // Of the last input texture, the graph gives the same one until it is resized
+    bind_group: RefCell<Option<(PoolTextureId, wgpu::BindGroup)>>,
+}
+
+/// Ordered chain of fullscreen effects, around tonemapping.
+///
+/// HDR effects like bloom run on the scene color before tonemapping, the others on
+/// the tonemapped colors. Each effect is a render graph pass reading the previous
+/// effect output, the last enabled LDR effect writes directly into the chain output.
+pub struct PostChain {
+    pub effects: Vec<PostEffect>,
+
+    output_format: wgpu::TextureFormat,
+    bind_group_layout: wgpu::BindGroupLayout,
+    lut: Texture,
+    baked_grading: [f32; 3],
+}
+
+impl PostChain {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        stats: &mut ResourceStats,
+        sc_desc: &wgpu::SwapChainDescriptor,
+    ) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler {
+                        comparison: false,
+                        filtering: true,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D3,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler {
+                        comparison: false,
+                        filtering: true,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("Post effect bind group layout"),
+        });
+
+        let lut = Self::create_lut(device, stats);
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Post effect pipeline layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let vs_module = device.create_shader_module(&wgpu::include_spirv!(concat!(
+            env!("OUT_DIR"),
+            "/fullscreen.vert.spv"
+        )));
+
+        let kinds = [
+            PostEffectKind::Bloom,
+            PostEffectKind::ColorGrading,
+            PostEffectKind::ChromaticAberration,
+            PostEffectKind::Vignette,
+            PostEffectKind::Fxaa,
+        ];
+
+        let effects = kinds
+            .iter()
+            .map(|&kind| {
+                let params = kind.default_params();
+                let params_buffer = stats.create_buffer_init(
+                    device,
+                    &wgpu::util::BufferInitDescriptor {
+                        label: Some(&format!("{} params buffer", kind.name())),
+                        contents: bytemuck::cast_slice(&[Self::build_params(params, sc_desc)]),
+                        usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+                    },
+                );
+
+                let fs_module = kind.create_shader_module(device);
+                let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                    label: Some(&format!("{} pipeline", kind.name())),
+                    layout: Some(&pipeline_layout),
+                    vertex: wgpu::VertexState {
+                        module: &vs_module,
+                        entry_point: "main",
+                        buffers: &[],
+                    },
+                    fragment: Some(wgpu::FragmentState {
+                        module: &fs_module,
+                        entry_point: "main",
+                        targets: &[wgpu::ColorTargetState {
+                            format: if kind.is_hdr() {
+                                Texture::HDR_FORMAT
+                            } else {
+                                sc_desc.format
+                            },
+                            color_blend: wgpu::BlendState::REPLACE,
+                            alpha_blend: wgpu::BlendState::REPLACE,
+                            write_mask: wgpu::ColorWrite::ALL,
+                        }],
+                    }),
+                    depth_stencil: None,
+                    primitive: wgpu::PrimitiveState {
+                        topology: wgpu::PrimitiveTopology::TriangleList,
+                        strip_index_format: None,
+                        front_face: wgpu::FrontFace::Ccw,
+                        cull_mode: wgpu::CullMode::None,
+                        polygon_mode: wgpu::PolygonMode::Fill,
+                    },
+                    multisample: wgpu::MultisampleState {
+                        count: 1,
+                        mask: !0,
+                        alpha_to_coverage_enabled: false,
+                    },
+                });
+
+                PostEffect {
+                    kind,
+                    enabled: kind == PostEffectKind::Fxaa,
+                    params,
+
+                    pipeline,
+                    params_buffer,
+                    bind_group: RefCell::new(None),
+                }
+            })
+            .collect();
+
+        let mut chain = PostChain {
+            effects,
+
+            output_format: sc_desc.format,
+            bind_group_layout,
+            lut,
+            baked_grading: [f32::NAN; 3],
+        };
+        chain.bake_lut(queue);
+        chain
+    }
+
+    fn create_bind_group(
+        &self,
+        device: &wgpu::Device,
+        effect: &PostEffect,
+        input: &Texture,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&input.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&input.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Buffer {
+                        buffer: &effect.params_buffer,
+                        offset: 0,
+                        size: None,
+                    },
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&self.lut.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::Sampler(&self.lut.sampler),
+                },
+            ],
+            label: Some("Post effect bind group"),
+        })
+    }
+
+    fn create_lut(device: &wgpu::Device, stats: &mut ResourceStats) -> Texture {
+        let texture = stats.create_texture(
+            device,
+            &wgpu::TextureDescriptor {
+                label: Some("Color grading LUT"),
+                size: wgpu::Extent3d {
+                    width: LUT_SIZE,
+                    height: LUT_SIZE,
+                    depth: LUT_SIZE,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D3,
+                format: wgpu::TextureFormat::Rgba8Unorm,
+                usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
+            },
+        );
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Texture {
+            texture,
+            view,
+            sampler,
+        }
+    }
+
+    /// Bakes contrast, saturation and temperature into the color grading LUT.
+    fn bake_lut(&mut self, queue: &wgpu::Queue) {
+        let grading = match self
+            .effects
+            .iter()
+            .find(|effect| effect.kind == PostEffectKind::ColorGrading)
+        {
+            Some(effect) => [effect.params[0], effect.params[1], effect.params[2]],
+            None => return,
+        };
+
+        if grading == self.baked_grading {
+            return;
+        }
+        self.baked_grading = grading;
+
+        let [contrast, saturation, temperature] = grading;
+        let max = (LUT_SIZE - 1) as f32;
+
+        let mut data = Vec::with_capacity((LUT_SIZE * LUT_SIZE * LUT_SIZE * 4) as usize);
+        for b in 0..LUT_SIZE {
+            for g in 0..LUT_SIZE {
+                for r in 0..LUT_SIZE {
+                    let mut color = [r as f32 / max, g as f32 / max, b as f32 / max];
+
+                    for c in color.iter_mut() {
+                        *c = (*c - 0.5) * contrast + 0.5;
+                    }
+
+                    let luma = 0.2126 * color[0] + 0.7152 * color[1] + 0.0722 * color[2];
+                    for c in color.iter_mut() {
+                        *c = luma + (*c - luma) * saturation;
+                    }
+
+                    color[0] += temperature * 0.1;
+                    color[2] -= temperature * 0.1;
+
+                    for c in color.iter() {
+                        data.push((c.max(0.0).min(1.0) * 255.0).round() as u8);
+                    }
+                    data.push(255);
+                }
+            }
+        }
+
+        queue.write_texture(
+            wgpu::TextureCopyView {
+                texture: &self.lut.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            &data,
+            wgpu::TextureDataLayout {
+                offset: 0,
+                bytes_per_row: 4 * LUT_SIZE,
+                rows_per_image: LUT_SIZE,
+            },
+            wgpu::Extent3d {
+                width: LUT_SIZE,
+                height: LUT_SIZE,
+                depth: LUT_SIZE,
+            },
+        );
+    }
+
+    fn build_params(params: [f32; 4], sc_desc: &wgpu::SwapChainDescriptor) -> PostParams {
+        PostParams {
+            params,
+            texel_size: [1.0 / sc_desc.width as f32, 1.0 / sc_desc.height as f32],
+            _padding: [0.0; 2],
+        }
+    }
+
+    pub fn update(&mut self, queue: &wgpu::Queue, sc_desc: &wgpu::SwapChainDescriptor) {
+        for effect in &self.effects {
+            queue.write_buffer(
+                &effect.params_buffer,
+                0,
+                bytemuck::cast_slice(&[Self::build_params(effect.params, sc_desc)]),
+            );
+        }
+        self.bake_lut(queue);
+    }
+
+    /// Texture tonemapping should render into, `None` if no LDR effect is enabled.
+    pub fn create_input(&self, graph: &mut RenderGraph) -> Option<TextureHandle> {
+        if self
+            .effects
+            .iter()
+            .any(|effect| effect.enabled && !effect.kind.is_hdr())
+        {
+            Some(graph.create_texture(self.intermediate_texture()))
+        } else {
+            None
+        }
+    }
+
+    fn intermediate_texture(&self) -> TransientTexture {
+        TransientTexture {
+            label: "Post effect texture",
+            format: self.output_format,
+            sample_count: 1,
+            usage: wgpu::TextureUsage::RENDER_ATTACHMENT | wgpu::TextureUsage::SAMPLED,
+        }
+    }
+
+    /// Adds one pass per enabled HDR effect, reading the scene color `hdr`.
+    ///
+    /// Returns the texture to tonemap, `hdr` itself if no HDR effect is enabled.
+    pub fn add_hdr_passes<'a>(
+        &'a self,
+        graph: &mut RenderGraph<'a>,
+        device: &'a wgpu::Device,
+        hdr: TextureHandle,
+    ) -> TextureHandle {
+        let intermediate = TransientTexture {
+            label: "HDR post effect texture",
+            format: Texture::HDR_FORMAT,
+            sample_count: 1,
+            usage: wgpu::TextureUsage::RENDER_ATTACHMENT | wgpu::TextureUsage::SAMPLED,
+        };
+        self.add_effect_passes(graph, device, true, hdr, None, intermediate)
+    }
+
+    /// Adds one pass per enabled LDR effect, from `input` to `output`.
+    pub fn add_passes<'a>(
+        &'a self,
+        graph: &mut RenderGraph<'a>,
+        device: &'a wgpu::Device,
+        input: TextureHandle,
+        output: TextureHandle,
+    ) {
+        let intermediate = self.intermediate_texture();
+        self.add_effect_passes(graph, device, false, input, Some(output), intermediate);
+    }
+
+    /// Chains the enabled effects of one stage, the last one writing to `output` or to a
+    /// new `intermediate` texture. Returns the texture written last.
+    fn add_effect_passes<'a>(
+        &'a self,
+        graph: &mut RenderGraph<'a>,
+        device: &'a wgpu::Device,
+        hdr: bool,
+        input: TextureHandle,
+        output: Option<TextureHandle>,
+        intermediate: TransientTexture,
+    ) -> TextureHandle {
+        let enabled: Vec<&PostEffect> = self
+            .effects
+            .iter()
+            .filter(|e| e.enabled && e.kind.is_hdr() == hdr)
+            .collect();
+
+        let mut source = input;
+        for (i, &effect) in enabled.iter().enumerate() {
+            let destination = match output {
+                Some(output) if i + 1 == enabled.len() => output,
+                _ => graph.create_texture(intermediate),
+            };
+
+            graph.add_pass(
+                effect.kind.name(),
+                &[source],
+                &[destination],
+                move |encoder, resources| {
+                    let (source_id, source_texture) = resources.pool_texture(source);
+                    let mut cache = effect.bind_group.borrow_mut();
+                    if cache.as_ref().is_some_and(|(id, _)| *id != source_id) {
+                        *cache = None;
+                    }
+                    let (_, bind_group) = cache.get_or_insert_with(|| {
+                        (
+                            source_id,
+                            self.create_bind_group(device, effect, source_texture),
+                        )
+                    });
+
+                    let mut effect_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                        label: Some(effect.kind.name()),
+                        color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
+                            attachment: resources.view(destination),
+                            resolve_target: None,
+                            ops: wgpu::Operations {
+                                load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                                store: true,
+                            },
+                        }],
+                        depth_stencil_attachment: None,
+                    });
+
+                    effect_pass.set_pipeline(&effect.pipeline);
+                    effect_pass.set_bind_group(0, bind_group, &[]);
+                    effect_pass.draw(0..3, 0..1);
+                },
+            );
+
+            source = destination;
+        }
+        source
+    }
+
+    pub fn build_ui(&mut self, ui: &imgui::Ui) {
+        let effects = &mut self.effects;
+        let window = imgui::Window::new(im_str!("Post effects"));
+        window
+            .size([300.0, 400.0], Condition::FirstUseEver)
+            .position([500.0, 0.0], Condition::FirstUseEver)
+            .build(&ui, || {
+                let mut swap = None;
+                let count = effects.len();
+
+                for (index, effect) in effects.iter_mut().enumerate() {
+                    let id = ui.push_id(index as i32);
+
+                    ui.checkbox(&im_str!("{}", effect.kind.name()), &mut effect.enabled);
+                    ui.same_line(0.0);
+                    if effect.kind.is_hdr() {
+                        ui.text_disabled(im_str!("before tonemapping"));
+                    } else {
+                        if ui.small_button(im_str!("Up")) && index > 0 {
+                            swap = Some((index - 1, index));
+                        }
+                        ui.same_line(0.0);
+                        if ui.small_button(im_str!("Down")) && index + 1 < count {
+                            swap = Some((index, index + 1));
+                        }
+                    }
+
+                    if effect.enabled {
+                        for (value, spec) in effect.params.iter_mut().zip(effect.kind.params()) {
+                            Slider::new(&im_str!("{}", spec.label))
+                                .range(spec.min..=spec.max)
+                                .build(&ui, value);
+                        }
+                    }
+
+                    ui.separator();
+                    id.pop(&ui);
+                }
+
+                // HDR and LDR effects run in separate stages, their order cannot be mixed
+                if let Some((a, b)) = swap {
+                    if effects[a].kind.is_hdr() == effects[b].kind.is_hdr() {
+                        effects.swap(a, b);
+                    }
+                }
+            });
+    }
+}
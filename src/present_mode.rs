@@ -0,0 +1,51 @@
+use wgpu::{Backend, PresentMode};
+
+/// Every present mode, in the order they are shown in the UI.
+pub const ALL: [PresentMode; 3] = [
+    PresentMode::Fifo,
+    PresentMode::Mailbox,
+    PresentMode::Immediate,
+];
+
+/// Parses a present mode name, as given with `--present-mode`.
+pub fn parse(name: &str) -> Option<PresentMode> {
+    match name.to_ascii_lowercase().as_str() {
+        "fifo" | "vsync" => Some(PresentMode::Fifo),
+        "mailbox" => Some(PresentMode::Mailbox),
+        "immediate" => Some(PresentMode::Immediate),
+        _ => None,
+    }
+}
+
+pub fn label(mode: PresentMode) -> &'static str {
+    match mode {
+        PresentMode::Fifo => "Fifo (vsync)",
+        PresentMode::Mailbox => "Mailbox",
+        PresentMode::Immediate => "Immediate",
+    }
+}
+
+/// Present modes a surface created by `backend` can use.
+///
+/// wgpu cannot query the surface for them, so this follows what each backend
+/// implements. Vulkan support depends on the driver, and wgpu itself falls back to
+/// Fifo with a warning when the surface lacks the requested mode.
+pub fn supported(backend: Backend) -> Vec<PresentMode> {
+    match backend {
+        Backend::Vulkan => ALL.to_vec(),
+        Backend::Metal | Backend::Dx12 | Backend::Dx11 => {
+            vec![PresentMode::Fifo, PresentMode::Immediate]
+        }
+        // Fifo is the only mode every surface has to support
+        _ => vec![PresentMode::Fifo],
+    }
+}
+
+/// The preferred mode if it is supported, Fifo otherwise.
+pub fn choose(preferred: PresentMode, supported: &[PresentMode]) -> PresentMode {
+    if supported.contains(&preferred) {
+        preferred
+    } else {
+        PresentMode::Fifo
+    }
+}
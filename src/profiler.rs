@@ -0,0 +1,441 @@
+use std::{
+    fmt::Write as _,
+    future::Future,
+    io,
+    path::{Path, PathBuf},
+    pin::Pin,
+    time::Instant,
+};
+
+use futures::FutureExt;
+use imgui::im_str;
+
+use crate::{resource_stats::ResourceStats, screenshot};
+
+type MapFuture = Pin<Box<dyn Future<Output = Result<(), wgpu::BufferAsyncError>>>>;
+
+/// Frames kept for the graphs and statistics.
+const HISTORY_LEN: usize = 120;
+
+/// Frames kept for the Chrome trace export.
+const TRACE_FRAMES: usize = 10;
+
+// Two timestamps per pass
+const MAX_QUERIES: u32 = 64;
+
+// Frames whose timestamps can be read back at the same time
+const GPU_FRAMES_IN_FLIGHT: usize = 3;
+
+const OUTPUT_DIR: &str = "profiles";
+
+/// Rolling durations of one scope, in milliseconds.
+struct Timings {
+    name: &'static str,
+    values: Vec<f32>,
+}
+
+impl Timings {
+    fn push(&mut self, value: f32) {
+        if self.values.len() == HISTORY_LEN {
+            self.values.remove(0);
+        }
+        self.values.push(value);
+    }
+
+    fn min(&self) -> f32 {
+        self.values.iter().copied().fold(f32::INFINITY, f32::min)
+    }
+
+    fn avg(&self) -> f32 {
+        self.values.iter().sum::<f32>() / self.values.len().max(1) as f32
+    }
+
+    fn max(&self) -> f32 {
+        self.values.iter().copied().fold(0.0, f32::max)
+    }
+
+    fn build_ui(&self, ui: &imgui::Ui) {
+        if self.values.is_empty() {
+            return;
+        }
+        ui.plot_lines(&im_str!("{}", self.name), &self.values)
+            .graph_size([0.0, 30.0])
+            .scale_min(0.0)
+            .overlay_text(&im_str!(
+                "{:.2} / {:.2} / {:.2} ms",
+                self.min(),
+                self.avg(),
+                self.max()
+            ))
+            .build();
+    }
+}
+
+fn push_timing(timings: &mut Vec<Timings>, name: &'static str, value: f32) {
+    match timings.iter_mut().find(|timings| timings.name == name) {
+        Some(timings) => timings.push(value),
+        None => {
+            let mut new = Timings {
+                name,
+                values: Vec::with_capacity(HISTORY_LEN),
+            };
+            new.push(value);
+            timings.push(new);
+        }
+    }
+}
+
+/// A complete event of the Chrome trace format, in microseconds since the profiler start.
+#[derive(Debug, Clone, Copy)]
+struct TraceEvent {
+    name: &'static str,
+    gpu: bool,
+    start: f64,
+    duration: f64,
+}
+
+/// A scope started by [`Profiler::begin_scope`].
+#[must_use]
+pub struct Scope {
+    name: &'static str,
+    start: Instant,
+    query: Option<u32>,
+}
+
+/// Timestamp queries of one frame, resolved into a mappable buffer.
+struct GpuFrame {
+    buffer: wgpu::Buffer,
+    scopes: Vec<(&'static str, u32)>,
+    // CPU time of the frame start, to place the GPU events in the trace
+    frame_start: f64,
+    mapping: Option<MapFuture>,
+}
+
+struct GpuTimer {
+    query_set: wgpu::QuerySet,
+    // Nanoseconds per timestamp tick
+    period: f32,
+    next_query: u32,
+    scopes: Vec<(&'static str, u32)>,
+    free_buffers: Vec<wgpu::Buffer>,
+    // The buffer receiving the current frame, none if all of them are still mapped
+    current: Option<wgpu::Buffer>,
+    pending: Vec<GpuFrame>,
+}
+
+/// CPU and GPU timings of the render graph passes and other scopes.
+///
+/// CPU scopes measure the time spent encoding. GPU scopes use timestamp queries when
+/// the device supports them, and are read back a few frames later without waiting.
+pub struct Profiler {
+    pub paused: bool,
+
+    epoch: Instant,
+    frame_start: Option<Instant>,
+    frame_times: Timings,
+    cpu: Vec<Timings>,
+    gpu: Vec<Timings>,
+
+    frame_events: Vec<TraceEvent>,
+    trace: Vec<Vec<TraceEvent>>,
+
+    gpu_timer: Option<GpuTimer>,
+}
+
+impl Profiler {
+    pub fn new(adapter: &wgpu::Adapter, device: &wgpu::Device, stats: &mut ResourceStats) -> Self {
+        let gpu_timer = if device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+            let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+                ty: wgpu::QueryType::Timestamp,
+                count: MAX_QUERIES,
+            });
+            let free_buffers = (0..GPU_FRAMES_IN_FLIGHT)
+                .map(|_| {
+                    stats.create_buffer(
+                        device,
+                        &wgpu::BufferDescriptor {
+                            label: Some("Timestamp readback buffer"),
+                            size: (MAX_QUERIES as usize * std::mem::size_of::<u64>()) as _,
+                            usage: wgpu::BufferUsage::MAP_READ | wgpu::BufferUsage::COPY_DST,
+                            mapped_at_creation: false,
+                        },
+                    )
+                })
+                .collect();
+            Some(GpuTimer {
+                query_set,
+                period: adapter.get_timestamp_period(),
+                next_query: 0,
+                scopes: Vec::new(),
+                free_buffers,
+                current: None,
+                pending: Vec::new(),
+            })
+        } else {
+            None
+        };
+
+        Profiler {
+            paused: false,
+
+            epoch: Instant::now(),
+            frame_start: None,
+            frame_times: Timings {
+                name: "Frame",
+                values: Vec::with_capacity(HISTORY_LEN),
+            },
+            cpu: Vec::new(),
+            gpu: Vec::new(),
+
+            frame_events: Vec::new(),
+            trace: Vec::new(),
+
+            gpu_timer,
+        }
+    }
+
+    fn micros_since_epoch(&self, instant: Instant) -> f64 {
+        instant.duration_since(self.epoch).as_secs_f64() * 1e6
+    }
+
+    /// Starts a frame, closing the previous one.
+    pub fn begin_frame(&mut self) {
+        let now = Instant::now();
+        if let Some(frame_start) = self.frame_start {
+            if !self.paused {
+                self.frame_times
+                    .push(now.duration_since(frame_start).as_secs_f32() * 1000.0);
+                let events = std::mem::take(&mut self.frame_events);
+                if self.trace.len() == TRACE_FRAMES {
+                    self.trace.remove(0);
+                }
+                self.trace.push(events);
+            }
+        }
+        self.frame_start = Some(now);
+        self.frame_events.clear();
+
+        if let Some(timer) = &mut self.gpu_timer {
+            timer.next_query = 0;
+            timer.scopes.clear();
+            if timer.current.is_none() {
+                timer.current = timer.free_buffers.pop();
+            }
+        }
+    }
+
+    /// Starts timing `name` on the CPU, and on the GPU if `encoder` is given.
+    pub fn begin_scope(
+        &mut self,
+        name: &'static str,
+        encoder: Option<&mut wgpu::CommandEncoder>,
+    ) -> Scope {
+        let query = match (&mut self.gpu_timer, encoder) {
+            (Some(timer), Some(encoder))
+                if timer.current.is_some() && timer.next_query + 2 <= MAX_QUERIES =>
+            {
+                let query = timer.next_query;
+                timer.next_query += 2;
+                encoder.write_timestamp(&timer.query_set, query);
+                Some(query)
+            }
+            _ => None,
+        };
+
+        Scope {
+            name,
+            start: Instant::now(),
+            query,
+        }
+    }
+
+    /// Ends a scope, `encoder` must be the one given to [`Profiler::begin_scope`].
+    pub fn end_scope(&mut self, scope: Scope, encoder: Option<&mut wgpu::CommandEncoder>) {
+        let end = Instant::now();
+        if let (Some(timer), Some(encoder), Some(query)) =
+            (&mut self.gpu_timer, encoder, scope.query)
+        {
+            encoder.write_timestamp(&timer.query_set, query + 1);
+            timer.scopes.push((scope.name, query));
+        }
+
+        if self.paused {
+            return;
+        }
+        push_timing(
+            &mut self.cpu,
+            scope.name,
+            end.duration_since(scope.start).as_secs_f32() * 1000.0,
+        );
+        self.frame_events.push(TraceEvent {
+            name: scope.name,
+            gpu: false,
+            start: self.micros_since_epoch(scope.start),
+            duration: end.duration_since(scope.start).as_secs_f64() * 1e6,
+        });
+    }
+
+    /// Copies the timestamps of the frame to its readback buffer, before `encoder` is
+    /// finished.
+    pub fn resolve(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        if let Some(timer) = &mut self.gpu_timer {
+            if let (Some(buffer), true) = (&timer.current, timer.next_query > 0) {
+                encoder.resolve_query_set(&timer.query_set, 0..timer.next_query, buffer, 0);
+            }
+        }
+    }
+
+    /// Maps the timestamps resolved this frame and collects those of earlier frames.
+    pub fn end_frame(&mut self, device: &wgpu::Device) {
+        let frame_start = self
+            .frame_start
+            .map_or(0.0, |start| self.micros_since_epoch(start));
+        let timer = match &mut self.gpu_timer {
+            Some(timer) => timer,
+            None => return,
+        };
+
+        if !timer.scopes.is_empty() {
+            if let Some(buffer) = timer.current.take() {
+                let mapping = buffer.slice(..).map_async(wgpu::MapMode::Read);
+                timer.pending.push(GpuFrame {
+                    buffer,
+                    scopes: std::mem::take(&mut timer.scopes),
+                    frame_start,
+                    mapping: Some(Box::pin(mapping)),
+                });
+            }
+        }
+
+        device.poll(wgpu::Maintain::Poll);
+        let mut index = 0;
+        while index < timer.pending.len() {
+            let frame = &mut timer.pending[index];
+            let mapped = match &mut frame.mapping {
+                Some(mapping) => match mapping.as_mut().now_or_never() {
+                    Some(result) => result,
+                    None => {
+                        index += 1;
+                        continue;
+                    }
+                },
+                None => Ok(()),
+            };
+
+            let frame = timer.pending.remove(index);
+            if let Err(e) = mapped {
+                // The timings of this frame are lost, but the buffer can be used again
+                eprintln!("Failed to map timestamp readback buffer: {}", e);
+                timer.free_buffers.push(frame.buffer);
+                continue;
+            }
+            let timestamps: Vec<u64> = {
+                let data = frame.buffer.slice(..).get_mapped_range();
+                data.chunks_exact(8)
+                    .map(|bytes| {
+                        let mut value = [0; 8];
+                        value.copy_from_slice(bytes);
+                        u64::from_ne_bytes(value)
+                    })
+                    .collect()
+            };
+            frame.buffer.unmap();
+
+            if !self.paused {
+                let first = frame
+                    .scopes
+                    .iter()
+                    .map(|&(_, query)| timestamps[query as usize])
+                    .min()
+                    .unwrap_or(0);
+                let period = timer.period as f64;
+                let to_micros = |ticks: u64| ticks as f64 * period / 1000.0;
+                let mut events = Vec::with_capacity(frame.scopes.len());
+                for &(name, query) in &frame.scopes {
+                    let start = timestamps[query as usize];
+                    let end = timestamps[query as usize + 1].max(start);
+                    push_timing(
+                        &mut self.gpu,
+                        name,
+                        (to_micros(end - start) / 1000.0) as f32,
+                    );
+                    events.push(TraceEvent {
+                        name,
+                        gpu: true,
+                        start: frame.frame_start + to_micros(start - first),
+                        duration: to_micros(end - start),
+                    });
+                }
+                match self.trace.last_mut() {
+                    Some(last) => last.extend(events),
+                    None => self.trace.push(events),
+                }
+            }
+            timer.free_buffers.push(frame.buffer);
+        }
+    }
+
+    /// Writes the last frames to `profiles/trace-<timestamp>.json`, in the Chrome trace
+    /// format read by `chrome://tracing` and Perfetto.
+    pub fn export_chrome_trace(&self) -> io::Result<PathBuf> {
+        let mut json = String::from("{\"traceEvents\":[\n");
+        json.push_str(
+            "{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":1,\"tid\":1,\"args\":{\"name\":\"CPU\"}},\n",
+        );
+        json.push_str(
+            "{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":1,\"tid\":2,\"args\":{\"name\":\"GPU\"}}",
+        );
+        for event in self.trace.iter().flatten() {
+            let _ = write!(
+                json,
+                ",\n{{\"name\":\"{}\",\"cat\":\"{}\",\"ph\":\"X\",\"ts\":{:.3},\"dur\":{:.3},\"pid\":1,\"tid\":{}}}",
+                event.name.replace('"', "\\\""),
+                if event.gpu { "gpu" } else { "cpu" },
+                event.start,
+                event.duration,
+                if event.gpu { 2 } else { 1 },
+            );
+        }
+        json.push_str("\n]}\n");
+
+        std::fs::create_dir_all(OUTPUT_DIR)?;
+        let path = Path::new(OUTPUT_DIR).join(format!("trace-{}.json", screenshot::timestamp()));
+        std::fs::write(&path, json)?;
+        Ok(path)
+    }
+
+    pub fn build_ui(&mut self, ui: &imgui::Ui) {
+        let window = imgui::Window::new(im_str!("Profiler"));
+        window
+            .size([320.0, 400.0], imgui::Condition::FirstUseEver)
+            .position([1010.0, 0.0], imgui::Condition::FirstUseEver)
+            .collapsed(true, imgui::Condition::FirstUseEver)
+            .build(&ui, || {
+                ui.checkbox(im_str!("Pause"), &mut self.paused);
+                ui.same_line(0.0);
+                if ui.button(im_str!("Export Chrome trace"), [0.0, 0.0]) {
+                    match self.export_chrome_trace() {
+                        Ok(path) => println!("Trace saved to {}", path.display()),
+                        Err(e) => eprintln!("Failed to save trace: {}", e),
+                    }
+                }
+                ui.text_disabled(im_str!("min / avg / max"));
+                self.frame_times.build_ui(ui);
+
+                ui.separator();
+                ui.text(im_str!("CPU"));
+                for timings in &self.cpu {
+                    timings.build_ui(ui);
+                }
+
+                ui.separator();
+                ui.text(im_str!("GPU"));
+                if self.gpu_timer.is_none() {
+                    ui.text_disabled(im_str!("Timestamp queries not supported"));
+                }
+                for timings in &self.gpu {
+                    timings.build_ui(ui);
+                }
+            });
+    }
+}
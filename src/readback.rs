@@ -0,0 +1,152 @@
+use std::{future::Future, pin::Pin};
+
+use futures::{executor::block_on, FutureExt};
+use image::RgbaImage;
+
+type MapFuture = Pin<Box<dyn Future<Output = Result<(), wgpu::BufferAsyncError>>>>;
+
+/// A texture copy into a mappable buffer, waiting to be read back on the CPU.
+pub struct Readback {
+    buffer: wgpu::Buffer,
+    format: wgpu::TextureFormat,
+    width: u32,
+    height: u32,
+    padded_bytes_per_row: u32,
+    mapping: Option<MapFuture>,
+    mapped: bool,
+}
+
+impl Readback {
+    /// Records a copy of the whole `texture` into a new readback buffer.
+    ///
+    /// The copy only happens once `encoder` is submitted.
+    pub fn new(
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        texture: &wgpu::Texture,
+        format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let readback = Self::with_size(device, format, width, height);
+        readback.copy(encoder, texture);
+        readback
+    }
+
+    /// Creates a readback buffer for textures of the given size, to fill with [`Readback::copy`].
+    pub fn with_size(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        // Rows copied to a buffer must be aligned to 256 bytes
+        let unpadded_bytes_per_row = 4 * width;
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_bytes_per_row = (unpadded_bytes_per_row + align - 1) / align * align;
+
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Readback buffer"),
+            size: (padded_bytes_per_row * height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsage::MAP_READ | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Readback {
+            buffer,
+            format,
+            width,
+            height,
+            padded_bytes_per_row,
+            mapping: None,
+            mapped: false,
+        }
+    }
+
+    /// Records a copy of the whole `texture`, which must have the size of the buffer.
+    ///
+    /// The buffer must not be mapped, as after [`Readback::read`].
+    pub fn copy(&self, encoder: &mut wgpu::CommandEncoder, texture: &wgpu::Texture) {
+        encoder.copy_texture_to_buffer(
+            wgpu::TextureCopyView {
+                texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            wgpu::BufferCopyView {
+                buffer: &self.buffer,
+                layout: wgpu::TextureDataLayout {
+                    offset: 0,
+                    bytes_per_row: self.padded_bytes_per_row,
+                    rows_per_image: self.height,
+                },
+            },
+            wgpu::Extent3d {
+                width: self.width,
+                height: self.height,
+                depth: 1,
+            },
+        );
+    }
+
+    /// Starts mapping the buffer without waiting, once `encoder` has been submitted.
+    pub fn map(&mut self) {
+        if self.mapping.is_none() && !self.mapped {
+            let mapping = self.buffer.slice(..).map_async(wgpu::MapMode::Read);
+            self.mapping = Some(Box::pin(mapping));
+        }
+    }
+
+    /// Whether the mapping started by [`Readback::map`] finished.
+    ///
+    /// Only makes progress when the device is polled.
+    pub fn is_ready(&mut self) -> bool {
+        if let Some(mapping) = &mut self.mapping {
+            if let Some(result) = mapping.as_mut().now_or_never() {
+                result.expect("Failed to map readback buffer");
+                self.mapping = None;
+                self.mapped = true;
+            }
+        }
+        self.mapped
+    }
+
+    /// Blocks until the copy is done and returns the texture content as RGBA.
+    pub fn into_image(mut self, device: &wgpu::Device) -> RgbaImage {
+        self.read(device)
+    }
+
+    /// Like [`Readback::into_image`], but unmaps the buffer so it can be copied to again.
+    pub fn read(&mut self, device: &wgpu::Device) -> RgbaImage {
+        self.map();
+        if let Some(mapping) = self.mapping.take() {
+            device.poll(wgpu::Maintain::Wait);
+            block_on(mapping).expect("Failed to map readback buffer");
+        }
+
+        let buffer_slice = self.buffer.slice(..);
+
+        let unpadded_bytes_per_row = (4 * self.width) as usize;
+        let mut pixels = Vec::with_capacity(unpadded_bytes_per_row * self.height as usize);
+        {
+            let data = buffer_slice.get_mapped_range();
+            for row in data.chunks(self.padded_bytes_per_row as usize) {
+                pixels.extend_from_slice(&row[..unpadded_bytes_per_row]);
+            }
+        }
+        self.buffer.unmap();
+        self.mapped = false;
+
+        match self.format {
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb => {
+                for pixel in pixels.chunks_mut(4) {
+                    pixel.swap(0, 2);
+                }
+            }
+            _ => {}
+        }
+
+        RgbaImage::from_raw(self.width, self.height, pixels)
+            .expect("Readback buffer does not match image size")
+    }
+}
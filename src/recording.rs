@@ -0,0 +1,309 @@
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::mpsc::{self, SyncSender},
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use image::{ImageResult, RgbaImage};
+
+use crate::{
+    readback::Readback, render_graph::TexturePool, resource_stats::ResourceStats, screenshot,
+    texture::Texture,
+};
+
+/// Frame rates offered for recordings.
+pub const FRAME_RATES: [u32; 3] = [24, 30, 60];
+
+// Frames copied but not yet read back, before recording waits for the GPU, each
+// with its own capture target
+const MAX_FRAMES_IN_FLIGHT: usize = 3;
+
+// Frames read back but not yet written, before recording waits for the writer thread
+const MAX_QUEUED_FRAMES: usize = 4;
+
+const OUTPUT_DIR: &str = "recordings";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordingFormat {
+    /// One numbered PNG file per frame, in a directory.
+    PngSequence,
+    /// A single uncompressed YUV4MPEG2 stream, with 4:4:4 chroma.
+    Y4m,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RecordingSettings {
+    pub format: RecordingFormat,
+    pub seconds: f32,
+    pub frame_rate: u32,
+}
+
+impl Default for RecordingSettings {
+    fn default() -> Self {
+        RecordingSettings {
+            format: RecordingFormat::PngSequence,
+            seconds: 5.0,
+            frame_rate: 30,
+        }
+    }
+}
+
+const CAPTURE_COLOR_LABEL: &str = "Capture color texture";
+
+/// Color texture and readback buffer a frame is captured to.
+pub struct CaptureTarget {
+    pub color: Texture,
+    pub readback: Readback,
+    format: wgpu::TextureFormat,
+    size: (u32, u32),
+}
+
+impl CaptureTarget {
+    pub fn new(
+        device: &wgpu::Device,
+        stats: &mut ResourceStats,
+        format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        CaptureTarget {
+            color: Texture::create_transient(
+                device,
+                stats,
+                width,
+                height,
+                format,
+                1,
+                Self::color_usage(),
+                CAPTURE_COLOR_LABEL,
+            ),
+            readback: Readback::with_size(device, format, width, height),
+            format,
+            size: (width, height),
+        }
+    }
+
+    fn color_usage() -> wgpu::TextureUsage {
+        wgpu::TextureUsage::RENDER_ATTACHMENT | wgpu::TextureUsage::COPY_SRC
+    }
+
+    /// Removes the color texture from `stats`, before dropping the target.
+    pub fn release(&self, stats: &mut ResourceStats) {
+        stats.release_texture(&Texture::transient_descriptor(
+            self.size.0,
+            self.size.1,
+            self.format,
+            1,
+            Self::color_usage(),
+            CAPTURE_COLOR_LABEL,
+        ));
+    }
+}
+
+/// Records a fixed number of frames of a fixed size to disk.
+///
+/// Frames are captured to a ring of targets and mapped asynchronously, so the GPU only
+/// waits when every target is still being copied. Images are encoded and written on a
+/// separate thread, which slows recording down when it falls behind.
+pub struct Recorder {
+    path: PathBuf,
+    size: (u32, u32),
+    frame_rate: u32,
+    frame_count: u32,
+    frames_submitted: u32,
+    targets: Vec<CaptureTarget>,
+    /// Textures of the capture render graph, at the recording size.
+    pool: TexturePool,
+    /// Indices in `targets` of the frames not read back yet, oldest first.
+    in_flight: VecDeque<usize>,
+    sender: SyncSender<RgbaImage>,
+    writer: JoinHandle<ImageResult<()>>,
+}
+
+impl Recorder {
+    /// Creates the output file or directory, the capture targets and starts the writer
+    /// thread.
+    pub fn start(
+        settings: &RecordingSettings,
+        device: &wgpu::Device,
+        stats: &mut ResourceStats,
+        format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+    ) -> ImageResult<Self> {
+        let frame_rate = settings.frame_rate.max(1);
+        let frame_count = ((settings.seconds * frame_rate as f32).round() as u32).max(1);
+        let name = format!("recording-{}", screenshot::timestamp());
+        std::fs::create_dir_all(OUTPUT_DIR)?;
+
+        let (sender, receiver) = mpsc::sync_channel::<RgbaImage>(MAX_QUEUED_FRAMES);
+        let (path, writer) = match settings.format {
+            RecordingFormat::PngSequence => {
+                let directory = Path::new(OUTPUT_DIR).join(name);
+                std::fs::create_dir(&directory)?;
+                let frames = directory.clone();
+                let writer: JoinHandle<ImageResult<()>> = std::thread::spawn(move || {
+                    for (index, image) in receiver.into_iter().enumerate() {
+                        image.save(frames.join(format!("frame-{:05}.png", index)))?;
+                    }
+                    Ok(())
+                });
+                (directory, writer)
+            }
+            RecordingFormat::Y4m => {
+                let path = Path::new(OUTPUT_DIR).join(name + ".y4m");
+                let mut file = BufWriter::new(File::create(&path)?);
+                writeln!(
+                    file,
+                    "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444",
+                    width, height, frame_rate
+                )?;
+                let writer: JoinHandle<ImageResult<()>> = std::thread::spawn(move || {
+                    for image in receiver {
+                        write_y4m_frame(&mut file, &image)?;
+                    }
+                    file.flush()?;
+                    Ok(())
+                });
+                (path, writer)
+            }
+        };
+
+        let targets = (0..MAX_FRAMES_IN_FLIGHT)
+            .map(|_| CaptureTarget::new(device, stats, format, width, height))
+            .collect();
+
+        Ok(Recorder {
+            path,
+            size: (width, height),
+            frame_rate,
+            frame_count,
+            frames_submitted: 0,
+            targets,
+            pool: TexturePool::default(),
+            in_flight: VecDeque::new(),
+            sender,
+            writer,
+        })
+    }
+
+    /// Simulated time between two frames, to pass to `State::update` while recording.
+    pub fn dt(&self) -> Duration {
+        Duration::from_secs_f64(1.0 / self.frame_rate as f64)
+    }
+
+    /// Size of every recorded frame, fixed when the recording started.
+    pub fn size(&self) -> (u32, u32) {
+        self.size
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Frames submitted so far, out of the total.
+    pub fn progress(&self) -> (u32, u32) {
+        (self.frames_submitted, self.frame_count)
+    }
+
+    /// Whether more frames must be pushed.
+    pub fn is_capturing(&self) -> bool {
+        self.frames_submitted < self.frame_count
+    }
+
+    /// Target to capture the next frame to, with the pool for its render graph.
+    ///
+    /// Waits for the oldest frame to be read back if every target is in flight.
+    pub fn next_target(&mut self, device: &wgpu::Device) -> (&CaptureTarget, &mut TexturePool) {
+        if self.in_flight.len() == self.targets.len() {
+            self.read_oldest(device);
+        }
+        let index = self.frames_submitted as usize % self.targets.len();
+        (&self.targets[index], &mut self.pool)
+    }
+
+    /// Queues the readback of the frame captured to [`Recorder::next_target`], once its
+    /// copy has been submitted.
+    pub fn push(&mut self, device: &wgpu::Device) {
+        let index = self.frames_submitted as usize % self.targets.len();
+        self.targets[index].readback.map();
+        self.in_flight.push_back(index);
+        self.frames_submitted += 1;
+
+        device.poll(wgpu::Maintain::Poll);
+        while let Some(&oldest) = self.in_flight.front() {
+            if !self.targets[oldest].readback.is_ready() {
+                break;
+            }
+            self.read_oldest(device);
+        }
+    }
+
+    fn read_oldest(&mut self, device: &wgpu::Device) {
+        let oldest = self.in_flight.pop_front().expect("Empty readback queue");
+        let image = self.targets[oldest].readback.read(device);
+        self.send(image);
+    }
+
+    fn send(&self, image: RgbaImage) {
+        // The writer only hangs up after an error, which finish reports
+        let _ = self.sender.send(image);
+    }
+
+    /// Waits for every pending frame to be written and releases the capture resources.
+    pub fn finish(
+        mut self,
+        device: &wgpu::Device,
+        stats: &mut ResourceStats,
+    ) -> ImageResult<PathBuf> {
+        while !self.in_flight.is_empty() {
+            self.read_oldest(device);
+        }
+        for target in &self.targets {
+            target.release(stats);
+        }
+        self.pool.clear(stats);
+        self.close()
+    }
+
+    /// Writes the frames already read back and drops the others, for when their device
+    /// was lost.
+    pub fn abort(mut self) -> ImageResult<PathBuf> {
+        self.in_flight.clear();
+        self.close()
+    }
+
+    fn close(self) -> ImageResult<PathBuf> {
+        drop(self.sender);
+        self.writer
+            .join()
+            .expect("Recording writer thread panicked")?;
+        Ok(self.path)
+    }
+}
+
+/// Writes an RGBA frame as full resolution BT.601 limited range Y, Cb and Cr planes.
+fn write_y4m_frame(writer: &mut impl Write, image: &RgbaImage) -> std::io::Result<()> {
+    let pixel_count = (image.width() * image.height()) as usize;
+    let mut planes = vec![0u8; 3 * pixel_count];
+    for (index, pixel) in image.pixels().enumerate() {
+        let [r, g, b] = [
+            pixel[0] as f32 / 255.0,
+            pixel[1] as f32 / 255.0,
+            pixel[2] as f32 / 255.0,
+        ];
+        let y = 16.0 + 65.481 * r + 128.553 * g + 24.966 * b;
+        let cb = 128.0 - 37.797 * r - 74.203 * g + 112.0 * b;
+        let cr = 128.0 + 112.0 * r - 93.786 * g - 18.214 * b;
+        planes[index] = y.round() as u8;
+        planes[pixel_count + index] = cb.round() as u8;
+        planes[2 * pixel_count + index] = cr.round() as u8;
+    }
+
+    writer.write_all(b"FRAME\n")?;
+    writer.write_all(&planes)
+}
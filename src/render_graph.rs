@@ -0,0 +1,529 @@
+use std::{
+    collections::HashSet,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::{profiler::Profiler, resource_stats::ResourceStats, texture::Texture};
+
+/// Handle to a texture declared in a [`RenderGraph`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TextureHandle(usize);
+
+/// Handle to a pass added to a [`RenderGraph`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PassHandle(usize);
+
+/// Identifies a pool texture as long as it lives, to cache the bind groups using it.
+///
+/// Unique across pools, a texture created again after a resize gets a new one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PoolTextureId(u64);
+
+impl PoolTextureId {
+    fn next() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        PoolTextureId(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// Description of a texture allocated by the graph, sized like the swap chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransientTexture {
+    pub label: &'static str,
+    pub format: wgpu::TextureFormat,
+    pub sample_count: u32,
+    pub usage: wgpu::TextureUsage,
+}
+
+impl TransientTexture {
+    fn is_compatible(&self, other: &TransientTexture) -> bool {
+        self.format == other.format
+            && self.sample_count == other.sample_count
+            && self.usage == other.usage
+    }
+}
+
+enum TextureDecl<'a> {
+    Imported(&'a wgpu::TextureView),
+    Transient(TransientTexture),
+}
+
+impl TextureDecl<'_> {
+    fn transient(&self) -> Option<TransientTexture> {
+        match self {
+            TextureDecl::Imported(_) => None,
+            TextureDecl::Transient(desc) => Some(*desc),
+        }
+    }
+}
+
+type PassFn<'a> = Box<dyn FnOnce(&mut wgpu::CommandEncoder, &GraphResources) + 'a>;
+
+struct PassNode<'a> {
+    name: &'static str,
+    reads: Vec<TextureHandle>,
+    writes: Vec<TextureHandle>,
+    /// Passes producing resources the graph does not track, like buffers.
+    dependencies: Vec<PassHandle>,
+    exec: PassFn<'a>,
+}
+
+/// Textures a pass can access while it is executed.
+pub struct GraphResources<'r> {
+    views: Vec<&'r wgpu::TextureView>,
+    textures: Vec<Option<(PoolTextureId, &'r Texture)>>,
+}
+
+impl<'r> GraphResources<'r> {
+    pub fn view(&self, handle: TextureHandle) -> &'r wgpu::TextureView {
+        self.views[handle.0]
+    }
+
+    /// Full texture (with its sampler) of a transient resource, and the ID of the pool
+    /// texture backing it this frame.
+    pub fn pool_texture(&self, handle: TextureHandle) -> (PoolTextureId, &'r Texture) {
+        self.textures[handle.0].expect("Imported textures only expose their view")
+    }
+}
+
+struct PoolEntry {
+    desc: TransientTexture,
+    id: PoolTextureId,
+    texture: Texture,
+    /// Whether the current or last execution of a graph was given this texture.
+    used: bool,
+}
+
+/// Textures allocated by the graph, kept alive between frames so they can be reused.
+///
+/// Textures no graph used during a whole execution are dropped at the start of the
+/// next one, so targets of a previous sample count or format do not pile up.
+#[derive(Default)]
+pub struct TexturePool {
+    size: (u32, u32),
+    entries: Vec<PoolEntry>,
+}
+
+impl TexturePool {
+    /// Drops every texture if the swap chain size changed.
+    fn set_size(&mut self, stats: &mut ResourceStats, width: u32, height: u32) {
+        if self.size != (width, height) {
+            self.clear(stats);
+            self.size = (width, height);
+        }
+    }
+
+    /// Drops every texture, for pools that are not kept between frames.
+    pub fn clear(&mut self, stats: &mut ResourceStats) {
+        for entry in self.entries.drain(..) {
+            stats.release_texture(&Self::descriptor(self.size, &entry.desc));
+        }
+    }
+
+    /// Drops the textures unused since the previous call, and starts tracking again.
+    fn begin_frame(&mut self, stats: &mut ResourceStats) {
+        let size = self.size;
+        self.entries.retain(|entry| {
+            if !entry.used {
+                stats.release_texture(&Self::descriptor(size, &entry.desc));
+            }
+            entry.used
+        });
+        for entry in &mut self.entries {
+            entry.used = false;
+        }
+    }
+
+    fn descriptor(size: (u32, u32), desc: &TransientTexture) -> wgpu::TextureDescriptor<'_> {
+        Texture::transient_descriptor(
+            size.0,
+            size.1,
+            desc.format,
+            desc.sample_count,
+            desc.usage,
+            desc.label,
+        )
+    }
+
+    /// Gives each transient texture an entry, see [`assign_entries`], creating the
+    /// textures missing from the pool.
+    fn assign(
+        &mut self,
+        device: &wgpu::Device,
+        stats: &mut ResourceStats,
+        order: &[usize],
+        passes: &[PassNode],
+        transients: &[Option<TransientTexture>],
+    ) -> Vec<Option<usize>> {
+        let pool: Vec<_> = self.entries.iter().map(|entry| entry.desc).collect();
+        let (assignments, added) = assign_entries(order, passes, transients, &pool);
+
+        for desc in added {
+            let texture = Texture::create_transient(
+                device,
+                stats,
+                self.size.0,
+                self.size.1,
+                desc.format,
+                desc.sample_count,
+                desc.usage,
+                desc.label,
+            );
+            self.entries.push(PoolEntry {
+                desc,
+                id: PoolTextureId::next(),
+                texture,
+                used: false,
+            });
+        }
+        for &entry in assignments.iter().flatten() {
+            self.entries[entry].used = true;
+        }
+        assignments
+    }
+}
+
+/// A frame's worth of passes, ordered by the textures they read and write.
+///
+/// Transient textures are taken from a [`TexturePool`] and shared between passes
+/// whose lifetimes do not overlap.
+pub struct RenderGraph<'a> {
+    textures: Vec<TextureDecl<'a>>,
+    passes: Vec<PassNode<'a>>,
+}
+
+impl<'a> RenderGraph<'a> {
+    pub fn new() -> Self {
+        RenderGraph {
+            textures: Vec::new(),
+            passes: Vec::new(),
+        }
+    }
+
+    /// Declares a texture owned outside of the graph, like the swap chain frame.
+    pub fn import_texture(&mut self, view: &'a wgpu::TextureView) -> TextureHandle {
+        self.textures.push(TextureDecl::Imported(view));
+        TextureHandle(self.textures.len() - 1)
+    }
+
+    pub fn create_texture(&mut self, desc: TransientTexture) -> TextureHandle {
+        self.textures.push(TextureDecl::Transient(desc));
+        TextureHandle(self.textures.len() - 1)
+    }
+
+    pub fn add_pass(
+        &mut self,
+        name: &'static str,
+        reads: &[TextureHandle],
+        writes: &[TextureHandle],
+        exec: impl FnOnce(&mut wgpu::CommandEncoder, &GraphResources) + 'a,
+    ) -> PassHandle {
+        self.passes.push(PassNode {
+            name,
+            reads: reads.to_vec(),
+            writes: writes.to_vec(),
+            dependencies: Vec::new(),
+            exec: Box::new(exec),
+        });
+        PassHandle(self.passes.len() - 1)
+    }
+
+    /// Runs `pass` after `dependency`, for resources other than textures.
+    ///
+    /// `dependency` is only executed if `pass` is.
+    pub fn add_dependency(&mut self, pass: PassHandle, dependency: PassHandle) {
+        self.passes[pass.0].dependencies.push(dependency);
+    }
+
+    /// Runs the passes in dependency order, timing each of them if `profiler` is given.
+    pub fn execute(
+        self,
+        device: &wgpu::Device,
+        pool: &mut TexturePool,
+        stats: &mut ResourceStats,
+        encoder: &mut wgpu::CommandEncoder,
+        size: (u32, u32),
+        mut profiler: Option<&mut Profiler>,
+    ) {
+        let transients: Vec<_> = self.textures.iter().map(TextureDecl::transient).collect();
+        let order = schedule(&self.passes, &transients);
+        pool.set_size(stats, size.0, size.1);
+        pool.begin_frame(stats);
+        let assignments = pool.assign(device, stats, &order, &self.passes, &transients);
+
+        let mut views = Vec::with_capacity(self.textures.len());
+        let mut textures = Vec::with_capacity(self.textures.len());
+        for (index, decl) in self.textures.iter().enumerate() {
+            match decl {
+                TextureDecl::Imported(view) => {
+                    views.push(*view);
+                    textures.push(None);
+                }
+                TextureDecl::Transient(_) => {
+                    let entry = assignments[index].expect("Transient texture not allocated");
+                    let PoolEntry { id, texture, .. } = &pool.entries[entry];
+                    views.push(&texture.view);
+                    textures.push(Some((*id, texture)));
+                }
+            }
+        }
+        let resources = GraphResources { views, textures };
+
+        let mut passes: Vec<Option<PassNode>> = self.passes.into_iter().map(Some).collect();
+        for pass in order {
+            let node = passes[pass].take().expect("Pass scheduled twice");
+            let scope = profiler
+                .as_deref_mut()
+                .map(|profiler| profiler.begin_scope(node.name, Some(&mut *encoder)));
+            encoder.push_debug_group(node.name);
+            (node.exec)(encoder, &resources);
+            encoder.pop_debug_group();
+            if let (Some(profiler), Some(scope)) = (profiler.as_deref_mut(), scope) {
+                profiler.end_scope(scope, Some(&mut *encoder));
+            }
+        }
+    }
+}
+
+/// Order of execution of the passes contributing to an imported texture.
+///
+/// `transients` describes each texture of the graph, `None` for imported ones. Writers
+/// of a texture run in declaration order, and before any of its readers.
+fn schedule(passes: &[PassNode], transients: &[Option<TransientTexture>]) -> Vec<usize> {
+    let pass_count = passes.len();
+    let mut dependencies = vec![HashSet::new(); pass_count];
+
+    for texture in 0..transients.len() {
+        let handle = TextureHandle(texture);
+        let writers: Vec<usize> = (0..pass_count)
+            .filter(|&p| passes[p].writes.contains(&handle))
+            .collect();
+
+        for pair in writers.windows(2) {
+            dependencies[pair[1]].insert(pair[0]);
+        }
+        for reader in (0..pass_count).filter(|&p| passes[p].reads.contains(&handle)) {
+            for &writer in writers.iter().filter(|&&w| w != reader) {
+                dependencies[reader].insert(writer);
+            }
+        }
+    }
+
+    for (pass, node) in passes.iter().enumerate() {
+        dependencies[pass].extend(node.dependencies.iter().map(|dependency| dependency.0));
+    }
+
+    // Only keep passes that end up in an imported texture
+    let mut needed = vec![false; pass_count];
+    let mut stack: Vec<usize> = (0..pass_count)
+        .filter(|&p| passes[p].writes.iter().any(|h| transients[h.0].is_none()))
+        .collect();
+    while let Some(pass) = stack.pop() {
+        if !needed[pass] {
+            needed[pass] = true;
+            stack.extend(dependencies[pass].iter().copied());
+        }
+    }
+
+    let mut order = Vec::with_capacity(pass_count);
+    let mut scheduled = vec![false; pass_count];
+    while order.len() < needed.iter().filter(|&&n| n).count() {
+        let next = (0..pass_count)
+            .find(|&p| needed[p] && !scheduled[p] && dependencies[p].iter().all(|&d| scheduled[d]))
+            .unwrap_or_else(|| panic!("Render graph contains a cycle"));
+        scheduled[next] = true;
+        order.push(next);
+    }
+
+    order
+}
+
+/// Pool entry of each transient texture, `None` for imported ones.
+///
+/// Compatible textures share an entry when the passes using them, run in `order`, do
+/// not overlap. `pool` describes the existing entries, and the entries to add after
+/// them are returned with the assignments.
+fn assign_entries(
+    order: &[usize],
+    passes: &[PassNode],
+    transients: &[Option<TransientTexture>],
+    pool: &[TransientTexture],
+) -> (Vec<Option<usize>>, Vec<TransientTexture>) {
+    let mut entries = pool.to_vec();
+
+    // Step at which each transient texture is used for the last time
+    let mut last_use = vec![None; transients.len()];
+    for (step, &pass) in order.iter().enumerate() {
+        let node = &passes[pass];
+        for handle in node.reads.iter().chain(node.writes.iter()) {
+            last_use[handle.0] = Some(step);
+        }
+    }
+
+    let mut assignments = vec![None; transients.len()];
+    let mut busy = vec![false; entries.len()];
+    for (step, &pass) in order.iter().enumerate() {
+        let node = &passes[pass];
+        for handle in node.reads.iter().chain(node.writes.iter()) {
+            if let Some(desc) = &transients[handle.0] {
+                if assignments[handle.0].is_none() {
+                    let entry = find_or_add(&mut entries, desc, &busy);
+                    busy.resize(entries.len(), false);
+                    busy[entry] = true;
+                    assignments[handle.0] = Some(entry);
+                }
+            }
+        }
+
+        for (texture, last) in last_use.iter().enumerate() {
+            if *last == Some(step) {
+                if let Some(entry) = assignments[texture] {
+                    busy[entry] = false;
+                }
+            }
+        }
+    }
+
+    // Textures only used by culled passes still need a view, it is never accessed
+    for (index, desc) in transients.iter().enumerate() {
+        if let (Some(desc), None) = (desc, assignments[index]) {
+            assignments[index] = Some(find_or_add(&mut entries, desc, &[]));
+        }
+    }
+
+    let added = entries.split_off(pool.len());
+    (assignments, added)
+}
+
+/// First compatible entry that is not `busy`, added to `entries` if there is none.
+fn find_or_add(
+    entries: &mut Vec<TransientTexture>,
+    desc: &TransientTexture,
+    busy: &[bool],
+) -> usize {
+    let free = entries.iter().enumerate().position(|(index, entry)| {
+        !busy.get(index).copied().unwrap_or(false) && entry.is_compatible(desc)
+    });
+    free.unwrap_or_else(|| {
+        entries.push(*desc);
+        entries.len() - 1
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HDR: TransientTexture = TransientTexture {
+        label: "HDR",
+        format: wgpu::TextureFormat::Rgba16Float,
+        sample_count: 1,
+        usage: wgpu::TextureUsage::RENDER_ATTACHMENT,
+    };
+    const DEPTH: TransientTexture = TransientTexture {
+        label: "Depth",
+        format: wgpu::TextureFormat::Depth32Float,
+        sample_count: 1,
+        usage: wgpu::TextureUsage::RENDER_ATTACHMENT,
+    };
+
+    fn pass(reads: &[usize], writes: &[usize]) -> PassNode<'static> {
+        PassNode {
+            name: "Pass",
+            reads: reads.iter().map(|&t| TextureHandle(t)).collect(),
+            writes: writes.iter().map(|&t| TextureHandle(t)).collect(),
+            dependencies: Vec::new(),
+            exec: Box::new(|_, _| {}),
+        }
+    }
+
+    #[test]
+    fn readers_run_after_writers() {
+        // 0: imported output, 1: transient
+        let transients = [None, Some(HDR)];
+        let passes = [pass(&[1], &[0]), pass(&[], &[1])];
+        assert_eq!(schedule(&passes, &transients), vec![1, 0]);
+    }
+
+    #[test]
+    fn writers_of_a_texture_run_in_declaration_order() {
+        let transients = [None, Some(HDR)];
+        let passes = [pass(&[], &[0]), pass(&[1], &[0]), pass(&[], &[1])];
+        assert_eq!(schedule(&passes, &transients), vec![0, 2, 1]);
+    }
+
+    #[test]
+    fn passes_not_reaching_an_imported_texture_are_culled() {
+        let transients = [None, Some(HDR), Some(HDR)];
+        let passes = [pass(&[], &[1]), pass(&[], &[2]), pass(&[1], &[0])];
+        assert_eq!(schedule(&passes, &transients), vec![0, 2]);
+    }
+
+    #[test]
+    fn dependencies_only_run_before_passes_that_are_kept() {
+        let transients = [None, Some(HDR)];
+        let mut passes = vec![
+            pass(&[], &[]),
+            pass(&[], &[0]),
+            pass(&[], &[]),
+            pass(&[], &[1]),
+        ];
+        passes[1].dependencies.push(PassHandle(0));
+        passes[3].dependencies.push(PassHandle(2));
+        assert_eq!(schedule(&passes, &transients), vec![0, 1]);
+    }
+
+    #[test]
+    #[should_panic(expected = "cycle")]
+    fn cycles_are_rejected() {
+        let transients = [None, Some(HDR), Some(HDR)];
+        let passes = [pass(&[2], &[1]), pass(&[1], &[2, 0])];
+        schedule(&passes, &transients);
+    }
+
+    #[test]
+    fn textures_with_disjoint_uses_share_an_entry() {
+        let transients = [None, Some(HDR), Some(HDR), Some(HDR)];
+        let passes = [
+            pass(&[], &[1]),
+            pass(&[1], &[2]),
+            pass(&[2], &[3]),
+            pass(&[3], &[0]),
+        ];
+        let order = schedule(&passes, &transients);
+        let (assignments, added) = assign_entries(&order, &passes, &transients, &[]);
+        assert_eq!(assignments, vec![None, Some(0), Some(1), Some(0)]);
+        assert_eq!(added, vec![HDR, HDR]);
+    }
+
+    #[test]
+    fn incompatible_textures_do_not_share_an_entry() {
+        let transients = [None, Some(HDR), Some(DEPTH)];
+        let passes = [pass(&[], &[1]), pass(&[1], &[0]), pass(&[], &[2, 0])];
+        let order = schedule(&passes, &transients);
+        let (assignments, added) = assign_entries(&order, &passes, &transients, &[]);
+        assert_eq!(assignments, vec![None, Some(0), Some(1)]);
+        assert_eq!(added, vec![HDR, DEPTH]);
+    }
+
+    #[test]
+    fn textures_of_the_pool_are_reused() {
+        let transients = [None, Some(HDR), Some(DEPTH)];
+        let passes = [pass(&[], &[1, 2]), pass(&[1], &[0])];
+        let order = schedule(&passes, &transients);
+        let (assignments, added) = assign_entries(&order, &passes, &transients, &[DEPTH, HDR]);
+        assert_eq!(assignments, vec![None, Some(1), Some(0)]);
+        assert!(added.is_empty());
+    }
+
+    #[test]
+    fn textures_of_culled_passes_still_get_an_entry() {
+        let transients = [None, Some(HDR), Some(DEPTH)];
+        let passes = [pass(&[], &[1]), pass(&[1], &[0]), pass(&[], &[2])];
+        let order = schedule(&passes, &transients);
+        assert_eq!(order, vec![0, 1]);
+        let (assignments, added) = assign_entries(&order, &passes, &transients, &[]);
+        assert_eq!(assignments, vec![None, Some(0), Some(1)]);
+        assert_eq!(added, vec![HDR, DEPTH]);
+    }
+}
@@ -0,0 +1,223 @@
+use std::cell::Cell;
+
+use imgui::{im_str, CollapsingHeader, Condition};
+use wgpu::util::DeviceExt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResourceKind {
+    Buffer,
+    Texture,
+    BindGroup,
+}
+
+impl ResourceKind {
+    const ALL: [ResourceKind; 3] = [
+        ResourceKind::Buffer,
+        ResourceKind::Texture,
+        ResourceKind::BindGroup,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            ResourceKind::Buffer => "Buffers",
+            ResourceKind::Texture => "Textures",
+            ResourceKind::BindGroup => "Bind groups",
+        }
+    }
+}
+
+struct ResourceRecord {
+    kind: ResourceKind,
+    label: String,
+    /// Bytes of GPU memory, 0 for bind groups.
+    size: u64,
+}
+
+/// Resources created through it and not released since.
+///
+/// Long-lived resources and render graph targets are tracked. Bind groups and uniform
+/// buffers created while encoding a single frame are not.
+#[derive(Default)]
+pub struct ResourceStats {
+    records: Vec<ResourceRecord>,
+}
+
+impl ResourceStats {
+    fn record(&mut self, kind: ResourceKind, label: Option<&str>, size: u64) {
+        self.records.push(ResourceRecord {
+            kind,
+            label: label.unwrap_or("Unlabeled").to_string(),
+            size,
+        });
+    }
+
+    pub fn create_buffer(
+        &mut self,
+        device: &wgpu::Device,
+        desc: &wgpu::BufferDescriptor,
+    ) -> wgpu::Buffer {
+        self.record(ResourceKind::Buffer, desc.label, desc.size);
+        device.create_buffer(desc)
+    }
+
+    pub fn create_buffer_init(
+        &mut self,
+        device: &wgpu::Device,
+        desc: &wgpu::util::BufferInitDescriptor,
+    ) -> wgpu::Buffer {
+        self.record(ResourceKind::Buffer, desc.label, desc.contents.len() as u64);
+        device.create_buffer_init(desc)
+    }
+
+    pub fn create_texture(
+        &mut self,
+        device: &wgpu::Device,
+        desc: &wgpu::TextureDescriptor,
+    ) -> wgpu::Texture {
+        self.record(ResourceKind::Texture, desc.label, texture_size(desc));
+        device.create_texture(desc)
+    }
+
+    pub fn create_bind_group(
+        &mut self,
+        device: &wgpu::Device,
+        desc: &wgpu::BindGroupDescriptor,
+    ) -> wgpu::BindGroup {
+        self.record(ResourceKind::BindGroup, desc.label, 0);
+        device.create_bind_group(desc)
+    }
+
+    /// Forgets a resource created with `label`, once it has been dropped.
+    pub fn release(&mut self, kind: ResourceKind, label: &str, size: u64) {
+        if let Some(index) = self
+            .records
+            .iter()
+            .position(|r| r.kind == kind && r.label == label && r.size == size)
+        {
+            self.records.remove(index);
+        }
+    }
+
+    pub fn release_texture(&mut self, desc: &wgpu::TextureDescriptor) {
+        self.release(
+            ResourceKind::Texture,
+            desc.label.unwrap_or("Unlabeled"),
+            texture_size(desc),
+        );
+    }
+
+    pub fn build_ui(&self, ui: &imgui::Ui, draws: &DrawStats) {
+        let window = imgui::Window::new(im_str!("Resources"));
+        window
+            .size([300.0, 250.0], Condition::FirstUseEver)
+            .position([1010.0, 410.0], Condition::FirstUseEver)
+            .collapsed(true, Condition::FirstUseEver)
+            .build(&ui, || {
+                let (draw_calls, triangles) = draws.last_frame;
+                ui.text(im_str!("Scene draw calls: {}", draw_calls));
+                match triangles {
+                    Some(triangles) => ui.text(im_str!("Triangles: {}", triangles)),
+                    // Indirect draws get their instance count on the GPU
+                    None => ui.text(im_str!("Triangles: n/a (GPU culling)")),
+                }
+                ui.separator();
+
+                for &kind in ResourceKind::ALL.iter() {
+                    let records = || self.records.iter().filter(move |r| r.kind == kind);
+                    let count = records().count();
+                    let total = records().map(|r| r.size).sum::<u64>();
+                    let label = if kind == ResourceKind::BindGroup {
+                        im_str!("{}: {}###{}", kind.label(), count, kind.label())
+                    } else {
+                        im_str!(
+                            "{}: {} ({})###{}",
+                            kind.label(),
+                            count,
+                            format_bytes(total),
+                            kind.label()
+                        )
+                    };
+                    if CollapsingHeader::new(&label).build(&ui) {
+                        for record in records() {
+                            if kind == ResourceKind::BindGroup {
+                                ui.text(im_str!("{}", record.label));
+                            } else {
+                                ui.text(im_str!("{}: {}", record.label, format_bytes(record.size)));
+                            }
+                        }
+                    }
+                }
+            });
+    }
+}
+
+/// Scene draws submitted each frame.
+///
+/// Kept apart from [`ResourceStats`], as draws are counted while the render graph
+/// allocates its targets.
+#[derive(Default)]
+pub struct DrawStats {
+    draw_calls: Cell<u32>,
+    triangles: Cell<u64>,
+    indirect: Cell<bool>,
+    // Draws and triangles of the last finished frame
+    last_frame: (u32, Option<u64>),
+}
+
+impl DrawStats {
+    /// Counts draws submitted during the current frame.
+    pub fn record(&self, draw_calls: u32, triangles: u64) {
+        self.draw_calls.set(self.draw_calls.get() + draw_calls);
+        self.triangles.set(self.triangles.get() + triangles);
+    }
+
+    /// Counts indirect draws, whose triangles are unknown on the CPU.
+    pub fn record_indirect(&self, draw_calls: u32) {
+        self.draw_calls.set(self.draw_calls.get() + draw_calls);
+        self.indirect.set(true);
+    }
+
+    /// Keeps the draws of the frame for the UI and starts counting the next one.
+    pub fn end_frame(&mut self) {
+        let triangles = self.triangles.replace(0);
+        self.last_frame = (
+            self.draw_calls.replace(0),
+            if self.indirect.replace(false) {
+                None
+            } else {
+                Some(triangles)
+            },
+        );
+    }
+}
+
+/// Bytes used by every mip level and sample of a texture.
+fn texture_size(desc: &wgpu::TextureDescriptor) -> u64 {
+    let info = desc.format.describe();
+    let (block_width, block_height) = (
+        info.block_dimensions.0 as u32,
+        info.block_dimensions.1 as u32,
+    );
+    (0..desc.mip_level_count)
+        .map(|level| {
+            let width = (desc.size.width >> level).max(1);
+            let height = (desc.size.height >> level).max(1);
+            let blocks = ((width + block_width - 1) / block_width) as u64
+                * ((height + block_height - 1) / block_height) as u64;
+            blocks * info.block_size as u64 * desc.size.depth as u64
+        })
+        .sum::<u64>()
+        * desc.sample_count as u64
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const KIB: u64 = 1024;
+    const MIB: u64 = 1024 * KIB;
+    if bytes >= MIB {
+        format!("{:.1} MiB", bytes as f64 / MIB as f64)
+    } else if bytes >= KIB {
+        format!("{:.1} KiB", bytes as f64 / KIB as f64)
+    } else {
+        format!("{} B", bytes)
+    }
+}
@@ -0,0 +1,66 @@
+use std::{
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Resolution multipliers offered for screenshots.
+pub const SUPERSAMPLING_FACTORS: [u32; 3] = [1, 2, 4];
+
+// Largest texture size every wgpu backend supports
+const MAX_DIMENSION: u32 = 8192;
+
+const OUTPUT_DIR: &str = "screenshots";
+
+/// Size of a capture of a `width` x `height` frame, reducing `factor` until it fits.
+pub fn capture_size(width: u32, height: u32, factor: u32) -> (u32, u32) {
+    let mut factor = factor.max(1);
+    while factor > 1 && (width * factor > MAX_DIMENSION || height * factor > MAX_DIMENSION) {
+        factor -= 1;
+    }
+    (width * factor, height * factor)
+}
+
+/// `screenshots/screenshot-YYYYMMDD-HHMMSS-mmm.png`, using the current UTC time.
+pub fn timestamped_path() -> PathBuf {
+    PathBuf::from(OUTPUT_DIR).join(format!("screenshot-{}.png", timestamp()))
+}
+
+/// The current UTC time as `YYYYMMDD-HHMMSS-mmm`, which sorts chronologically.
+pub fn timestamp() -> String {
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let seconds = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days((seconds / 86400) as i64);
+    let time_of_day = seconds % 86400;
+
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}-{:03}",
+        year,
+        month,
+        day,
+        time_of_day / 3600,
+        time_of_day / 60 % 60,
+        time_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// Gregorian date of a number of days since 1970-01-01 (Howard Hinnant's algorithm).
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
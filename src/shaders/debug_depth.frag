@@ -0,0 +1,27 @@
+#version 450
+
+layout(location=0) in vec2 v_tex_coords;
+layout(location=1) in vec3 v_normal;
+layout(location=2) in vec3 v_position;
+layout(location=3) flat in uvec2 v_object_id;
+layout(location=0) out vec4 f_color;
+layout(location=1) out uvec2 f_object_id;
+
+layout(set=1, binding=0)
+uniform Uniforms {
+    vec4 u_view_position;
+    mat4 u_view_proj;
+    vec4 u_camera_planes; // x: near, y: far
+};
+
+void main() {
+    float near = u_camera_planes.x;
+    float far = u_camera_planes.y;
+
+    // Depth is in [0, 1] with wgpu conventions
+    float view_depth = near * far / (far - gl_FragCoord.z * (far - near));
+    float linear_depth = (view_depth - near) / (far - near);
+
+    f_color = vec4(vec3(linear_depth), 1.0);
+    f_object_id = v_object_id;
+}
@@ -0,0 +1,14 @@
+#version 450
+
+layout(location=0) in vec2 v_tex_coords;
+layout(location=1) in vec3 v_normal;
+layout(location=2) in vec3 v_position;
+layout(location=3) flat in uvec2 v_object_id;
+layout(location=0) out vec4 f_color;
+layout(location=1) out uvec2 f_object_id;
+
+void main() {
+    // World space normal remapped from [-1, 1] to [0, 1]
+    f_color = vec4(normalize(v_normal) * 0.5 + 0.5, 1.0);
+    f_object_id = v_object_id;
+}
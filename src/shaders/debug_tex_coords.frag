@@ -0,0 +1,13 @@
+#version 450
+
+layout(location=0) in vec2 v_tex_coords;
+layout(location=1) in vec3 v_normal;
+layout(location=2) in vec3 v_position;
+layout(location=3) flat in uvec2 v_object_id;
+layout(location=0) out vec4 f_color;
+layout(location=1) out uvec2 f_object_id;
+
+void main() {
+    f_color = vec4(fract(v_tex_coords), 0.0, 1.0);
+    f_object_id = v_object_id;
+}
@@ -0,0 +1,28 @@
+#version 450
+
+layout(location=0) in vec2 v_tex_coords;
+layout(location=3) flat in uvec2 v_object_id;
+layout(location=0) out uvec2 f_object_id;
+
+layout(set=0, binding=0) uniform texture2D t_diffuse;
+layout(set=0, binding=1) uniform sampler s_diffuse;
+
+layout(set=0, binding=2)
+uniform MaterialUniforms {
+    vec4 u_base_color;
+    vec3 u_emissive;
+    float u_opacity;
+    float u_metallic;
+    float u_shininess;
+    float u_alpha_cutoff;
+};
+
+// Single sampled object IDs, for when the color pass is multisampled.
+// Cut out fragments are discarded as in the color pass.
+void main() {
+    float alpha = texture(sampler2D(t_diffuse, s_diffuse), v_tex_coords).a * u_base_color.a;
+    if (alpha < u_alpha_cutoff) {
+        discard;
+    }
+    f_object_id = v_object_id;
+}
@@ -0,0 +1,14 @@
+#version 450
+
+layout(location=0) out vec4 f_color;
+
+layout(set=0, binding=0)
+uniform OutlineParams {
+    mat4 u_model_view_proj;
+    vec4 u_color;
+    vec2 u_offset;
+};
+
+void main() {
+    f_color = u_color;
+}
@@ -0,0 +1,24 @@
+#version 450
+
+layout(location=0) in vec3 a_position;
+
+layout(set=0, binding=0)
+uniform OutlineParams {
+    mat4 u_model_view_proj;
+    vec4 u_color;
+    vec2 u_offset; // thickness in clip space units
+};
+
+const float PI = 3.14159265;
+
+void main() {
+    vec4 position = u_model_view_proj * vec4(a_position, 1.0);
+
+    // Instance 0 is the silhouette itself, the 8 others are copies shifted around it.
+    // Shifts are multiplied by w to keep their size on screen after the divide.
+    if (gl_InstanceIndex > 0) {
+        float angle = float(gl_InstanceIndex - 1) * PI / 4.0;
+        position.xy += vec2(cos(angle), sin(angle)) * u_offset * position.w;
+    }
+    gl_Position = position;
+}
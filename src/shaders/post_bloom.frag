@@ -0,0 +1,43 @@
+#version 450
+
+layout(location=0) in vec2 v_tex_coords;
+layout(location=0) out vec4 f_color;
+
+layout(set=0, binding=0) uniform texture2D t_input;
+layout(set=0, binding=1) uniform sampler s_input;
+
+layout(set=0, binding=2)
+uniform PostParams {
+    vec4 u_params;
+    vec2 u_texel_size;
+};
+
+const int KERNEL_RADIUS = 4;
+
+// Single pass bloom: bright parts of the neighbourhood are blurred and added back.
+// Runs on HDR colors, so the threshold and the result are not limited to 1.
+void main() {
+    float threshold = u_params.x;
+    float intensity = u_params.y;
+    float spread = u_params.z;
+
+    vec3 color = texture(sampler2D(t_input, s_input), v_tex_coords).rgb;
+
+    vec3 bloom = vec3(0.0);
+    float total_weight = 0.0;
+    for (int x = -KERNEL_RADIUS; x <= KERNEL_RADIUS; x++) {
+        for (int y = -KERNEL_RADIUS; y <= KERNEL_RADIUS; y++) {
+            vec2 offset = vec2(x, y) * u_texel_size * spread;
+            vec3 neighbour = texture(sampler2D(t_input, s_input), v_tex_coords + offset).rgb;
+
+            float brightness = max(neighbour.r, max(neighbour.g, neighbour.b));
+            float contribution = max(brightness - threshold, 0.0) / max(brightness, 0.0001);
+
+            float weight = exp(-float(x * x + y * y) / float(KERNEL_RADIUS * KERNEL_RADIUS));
+            bloom += neighbour * contribution * weight;
+            total_weight += weight;
+        }
+    }
+
+    f_color = vec4(color + bloom / total_weight * intensity, 1.0);
+}
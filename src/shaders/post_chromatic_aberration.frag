@@ -0,0 +1,26 @@
+#version 450
+
+layout(location=0) in vec2 v_tex_coords;
+layout(location=0) out vec4 f_color;
+
+layout(set=0, binding=0) uniform texture2D t_input;
+layout(set=0, binding=1) uniform sampler s_input;
+
+layout(set=0, binding=2)
+uniform PostParams {
+    vec4 u_params;
+    vec2 u_texel_size;
+};
+
+void main() {
+    float strength = u_params.x;
+
+    // Channels are pushed apart radially from the screen center
+    vec2 offset = (v_tex_coords - vec2(0.5)) * strength;
+
+    float r = texture(sampler2D(t_input, s_input), v_tex_coords + offset).r;
+    float g = texture(sampler2D(t_input, s_input), v_tex_coords).g;
+    float b = texture(sampler2D(t_input, s_input), v_tex_coords - offset).b;
+
+    f_color = vec4(r, g, b, 1.0);
+}
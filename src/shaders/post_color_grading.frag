@@ -0,0 +1,29 @@
+#version 450
+
+layout(location=0) in vec2 v_tex_coords;
+layout(location=0) out vec4 f_color;
+
+layout(set=0, binding=0) uniform texture2D t_input;
+layout(set=0, binding=1) uniform sampler s_input;
+
+layout(set=0, binding=2)
+uniform PostParams {
+    vec4 u_params;
+    vec2 u_texel_size;
+};
+
+layout(set=0, binding=3) uniform texture3D t_lut;
+layout(set=0, binding=4) uniform sampler s_lut;
+
+void main() {
+    float strength = u_params.w;
+    float lut_size = float(textureSize(sampler3D(t_lut, s_lut), 0).x);
+
+    vec3 color = clamp(texture(sampler2D(t_input, s_input), v_tex_coords).rgb, 0.0, 1.0);
+
+    // Sample at texel centers so the LUT edges are not blended with the border
+    vec3 lut_coords = color * ((lut_size - 1.0) / lut_size) + 0.5 / lut_size;
+    vec3 graded = texture(sampler3D(t_lut, s_lut), lut_coords).rgb;
+
+    f_color = vec4(mix(color, graded, strength), 1.0);
+}
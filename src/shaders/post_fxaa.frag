@@ -0,0 +1,67 @@
+#version 450
+
+layout(location=0) in vec2 v_tex_coords;
+layout(location=0) out vec4 f_color;
+
+layout(set=0, binding=0) uniform texture2D t_input;
+layout(set=0, binding=1) uniform sampler s_input;
+
+layout(set=0, binding=2)
+uniform PostParams {
+    vec4 u_params;
+    vec2 u_texel_size;
+};
+
+const vec3 LUMA = vec3(0.299, 0.587, 0.114);
+
+vec3 sample_input(vec2 uv) {
+    return texture(sampler2D(t_input, s_input), uv).rgb;
+}
+
+// FXAA "console" variant: a single blur along the local edge direction
+void main() {
+    float span_max = u_params.x;
+    float reduce_mul = u_params.y;
+    float reduce_min = u_params.z;
+
+    vec2 uv = v_tex_coords;
+    vec3 rgb_nw = sample_input(uv + vec2(-1.0, -1.0) * u_texel_size);
+    vec3 rgb_ne = sample_input(uv + vec2(1.0, -1.0) * u_texel_size);
+    vec3 rgb_sw = sample_input(uv + vec2(-1.0, 1.0) * u_texel_size);
+    vec3 rgb_se = sample_input(uv + vec2(1.0, 1.0) * u_texel_size);
+    vec3 rgb_m = sample_input(uv);
+
+    float luma_nw = dot(rgb_nw, LUMA);
+    float luma_ne = dot(rgb_ne, LUMA);
+    float luma_sw = dot(rgb_sw, LUMA);
+    float luma_se = dot(rgb_se, LUMA);
+    float luma_m = dot(rgb_m, LUMA);
+
+    float luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    float luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+    vec2 dir = vec2(
+        -((luma_nw + luma_ne) - (luma_sw + luma_se)),
+        (luma_nw + luma_sw) - (luma_ne + luma_se)
+    );
+
+    float dir_reduce = max((luma_nw + luma_ne + luma_sw + luma_se) * 0.25 * reduce_mul, reduce_min);
+    float rcp_dir_min = 1.0 / (min(abs(dir.x), abs(dir.y)) + dir_reduce);
+    dir = clamp(dir * rcp_dir_min, vec2(-span_max), vec2(span_max)) * u_texel_size;
+
+    vec3 rgb_a = 0.5 * (
+        sample_input(uv + dir * (1.0 / 3.0 - 0.5)) +
+        sample_input(uv + dir * (2.0 / 3.0 - 0.5))
+    );
+    vec3 rgb_b = rgb_a * 0.5 + 0.25 * (
+        sample_input(uv + dir * -0.5) +
+        sample_input(uv + dir * 0.5)
+    );
+
+    float luma_b = dot(rgb_b, LUMA);
+    if (luma_b < luma_min || luma_b > luma_max) {
+        f_color = vec4(rgb_a, 1.0);
+    } else {
+        f_color = vec4(rgb_b, 1.0);
+    }
+}
@@ -0,0 +1,26 @@
+#version 450
+
+layout(location=0) in vec2 v_tex_coords;
+layout(location=0) out vec4 f_color;
+
+layout(set=0, binding=0) uniform texture2D t_input;
+layout(set=0, binding=1) uniform sampler s_input;
+
+layout(set=0, binding=2)
+uniform PostParams {
+    vec4 u_params;
+    vec2 u_texel_size;
+};
+
+void main() {
+    float intensity = u_params.x;
+    float radius = u_params.y;
+    float softness = u_params.z;
+
+    vec3 color = texture(sampler2D(t_input, s_input), v_tex_coords).rgb;
+
+    float dist = distance(v_tex_coords, vec2(0.5));
+    float vignette = smoothstep(radius, radius - softness, dist);
+
+    f_color = vec4(color * mix(1.0, vignette, intensity), 1.0);
+}
@@ -1,11 +1,89 @@
 #version 450
 
 layout(location=0) in vec2 v_tex_coords;
+layout(location=1) in vec3 v_normal;
+layout(location=2) in vec3 v_position;
+layout(location=3) flat in uvec2 v_object_id;
 layout(location=0) out vec4 f_color;
+layout(location=1) out uvec2 f_object_id;
 
 layout(set=0, binding=0) uniform texture2D t_diffuse;
 layout(set=0, binding=1) uniform sampler s_diffuse;
 
+layout(set=0, binding=2)
+uniform MaterialUniforms {
+    vec4 u_base_color;
+    vec3 u_emissive;
+    float u_opacity;
+    float u_metallic;
+    float u_shininess;
+    float u_alpha_cutoff;
+};
+
+layout(set=1, binding=0)
+uniform Uniforms {
+    vec4 u_view_position;
+    mat4 u_view_proj;
+    vec4 u_camera_planes;
+};
+
+layout(set=1, binding=1)
+uniform Light {
+    vec3 light_position;
+    float light_ambient_strength;
+    vec3 light_color;
+    float light_specular_strength;
+};
+
+layout(set=2, binding=0) uniform texture2D t_shadow;
+layout(set=2, binding=1) uniform samplerShadow s_shadow;
+
+layout(set=2, binding=2)
+uniform ShadowUniforms {
+    mat4 u_light_view_proj;
+    vec4 u_shadow_params;
+};
+
+// 3x3 PCF lookup, returns 1.0 when fully lit
+float shadow_factor(vec3 world_position) {
+    vec4 light_space = u_light_view_proj * vec4(world_position, 1.0);
+    vec3 projected = light_space.xyz / light_space.w;
+    if (projected.z > 1.0) {
+        return 1.0;
+    }
+
+    vec2 uv = projected.xy * vec2(0.5, -0.5) + 0.5;
+    float texel_size = u_shadow_params.x;
+
+    float lit = 0.0;
+    for (int x = -1; x <= 1; x++) {
+        for (int y = -1; y <= 1; y++) {
+            vec2 offset = vec2(x, y) * texel_size;
+            lit += texture(sampler2DShadow(t_shadow, s_shadow), vec3(uv + offset, projected.z));
+        }
+    }
+    return lit / 9.0;
+}
+
 void main() {
-    f_color = texture(sampler2D(t_diffuse, s_diffuse), v_tex_coords);
-}
\ No newline at end of file
+    vec4 object_color = texture(sampler2D(t_diffuse, s_diffuse), v_tex_coords) * u_base_color;
+    if (object_color.a < u_alpha_cutoff) {
+        discard;
+    }
+
+    vec3 normal = normalize(v_normal);
+    vec3 light_dir = normalize(light_position - v_position);
+    vec3 view_dir = normalize(u_view_position.xyz - v_position);
+    vec3 half_dir = normalize(view_dir + light_dir);
+
+    vec3 ambient = light_color * light_ambient_strength;
+    // Metals have no diffuse reflection, their specular keeps the object color below
+    vec3 diffuse = light_color * max(dot(normal, light_dir), 0.0) * (1.0 - u_metallic);
+    vec3 specular = light_color * light_specular_strength * pow(max(dot(normal, half_dir), 0.0), u_shininess);
+
+    float shadow = shadow_factor(v_position);
+
+    vec3 result = (ambient + shadow * (diffuse + specular)) * object_color.rgb + u_emissive;
+    f_color = vec4(result, object_color.a * u_opacity);
+    f_object_id = v_object_id;
+}
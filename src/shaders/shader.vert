@@ -2,21 +2,43 @@
 
 layout(location=0) in vec3 a_position;
 layout(location=1) in vec2 a_tex_coords;
+layout(location=2) in vec3 a_normal;
 layout(location=5) in vec4 model_matrix_c0;
 layout(location=6) in vec4 model_matrix_c1;
 layout(location=7) in vec4 model_matrix_c2;
 layout(location=8) in vec4 model_matrix_c3;
+layout(location=9) in vec3 normal_matrix_c0;
+layout(location=10) in vec3 normal_matrix_c1;
+layout(location=11) in vec3 normal_matrix_c2;
+layout(location=12) in uint a_instance_id;
 
 layout(location=0) out vec2 v_tex_coords;
+layout(location=1) out vec3 v_normal;
+layout(location=2) out vec3 v_position;
+// x: instance index + 1 (0 is the background), y: mesh index
+layout(location=3) flat out uvec2 v_object_id;
 
 layout(set=1, binding=0)
 uniform Uniforms {
+    vec4 u_view_position;
     mat4 u_view_proj;
+    vec4 u_camera_planes;
+};
+
+layout(set=3, binding=0)
+uniform MeshUniforms {
+    uint u_mesh_index;
 };
 
 void main() {
     mat4 model_matrix = mat4(model_matrix_c0, model_matrix_c1, model_matrix_c2, model_matrix_c3);
+    mat3 normal_matrix = mat3(normal_matrix_c0, normal_matrix_c1, normal_matrix_c2);
+
+    vec4 world_position = model_matrix * vec4(a_position, 1.0);
 
     v_tex_coords = a_tex_coords;
-    gl_Position = u_view_proj * model_matrix * vec4(a_position, 1.0);
-}
\ No newline at end of file
+    v_normal = normal_matrix * a_normal;
+    v_position = world_position.xyz;
+    v_object_id = uvec2(a_instance_id + 1, u_mesh_index);
+    gl_Position = u_view_proj * world_position;
+}
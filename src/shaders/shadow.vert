@@ -0,0 +1,19 @@
+#version 450
+
+layout(location=0) in vec3 a_position;
+layout(location=5) in vec4 model_matrix_c0;
+layout(location=6) in vec4 model_matrix_c1;
+layout(location=7) in vec4 model_matrix_c2;
+layout(location=8) in vec4 model_matrix_c3;
+
+layout(set=0, binding=0)
+uniform ShadowUniforms {
+    mat4 u_light_view_proj;
+    vec4 u_shadow_params;
+};
+
+void main() {
+    mat4 model_matrix = mat4(model_matrix_c0, model_matrix_c1, model_matrix_c2, model_matrix_c3);
+
+    gl_Position = u_light_view_proj * model_matrix * vec4(a_position, 1.0);
+}
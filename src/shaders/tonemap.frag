@@ -0,0 +1,44 @@
+#version 450
+
+layout(location=0) in vec2 v_tex_coords;
+layout(location=0) out vec4 f_color;
+
+layout(set=0, binding=0) uniform texture2D t_hdr;
+layout(set=0, binding=1) uniform sampler s_hdr;
+
+layout(set=0, binding=2)
+uniform TonemapParams {
+    uint u_operator;
+    float u_exposure;
+};
+
+const uint OPERATOR_REINHARD = 0u;
+const uint OPERATOR_ACES = 1u;
+const uint OPERATOR_EXPOSURE = 2u;
+
+// Krzysztof Narkowicz's fit of the ACES filmic curve
+vec3 aces(vec3 x) {
+    const float a = 2.51;
+    const float b = 0.03;
+    const float c = 2.43;
+    const float d = 0.59;
+    const float e = 0.14;
+    return clamp((x * (a * x + b)) / (x * (c * x + d) + e), 0.0, 1.0);
+}
+
+void main() {
+    vec3 hdr = texture(sampler2D(t_hdr, s_hdr), v_tex_coords).rgb * u_exposure;
+
+    vec3 mapped;
+    if (u_operator == OPERATOR_REINHARD) {
+        mapped = hdr / (hdr + vec3(1.0));
+    } else if (u_operator == OPERATOR_ACES) {
+        mapped = aces(hdr);
+    } else if (u_operator == OPERATOR_EXPOSURE) {
+        mapped = vec3(1.0) - exp(-hdr);
+    } else {
+        mapped = clamp(hdr, 0.0, 1.0);
+    }
+
+    f_color = vec4(mapped, 1.0);
+}
@@ -0,0 +1,11 @@
+#version 450
+
+layout(location=3) flat in uvec2 v_object_id;
+layout(location=0) out vec4 f_color;
+// Masked out by the pipeline, lines must not hide the surface they outline
+layout(location=1) out uvec2 f_object_id;
+
+void main() {
+    f_color = vec4(1.0, 0.6, 0.1, 0.8);
+    f_object_id = v_object_id;
+}
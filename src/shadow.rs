@@ -0,0 +1,425 @@
+use cgmath::{EuclideanSpace, InnerSpace};
+use imgui::{im_str, ComboBox, Condition, Slider};
+
+use crate::{
+    camera::OPENGL_TO_WGPU_MATRIX,
+    instance::InstanceRaw,
+    light::Light,
+    model::{DrawModel, Model},
+    resource_stats::{ResourceKind, ResourceStats},
+    texture::Texture,
+    vertex::ModelVertex,
+};
+
+const RESOLUTIONS: [u32; 4] = [512, 1024, 2048, 4096];
+
+const SHADOW_MAP_LABEL: &str = "Shadow map";
+const SAMPLE_BIND_GROUP_LABEL: &str = "Shadow sample bind group";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ShadowSettings {
+    pub resolution: u32,
+    pub constant_bias: i32,
+    pub slope_bias: f32,
+    /// Half size of the orthographic volume rendered from the light.
+    pub extent: f32,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        ShadowSettings {
+            resolution: 2048,
+            constant_bias: 2,
+            slope_bias: 2.0,
+            extent: 20.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ShadowUniforms {
+    light_view_proj: [[f32; 4]; 4],
+    // x: texel size of the shadow map
+    params: [f32; 4],
+}
+
+pub struct ShadowPass {
+    pub settings: ShadowSettings,
+    applied_settings: ShadowSettings,
+
+    shadow_map: Texture,
+    uniform_buffer: wgpu::Buffer,
+
+    light_bind_group: wgpu::BindGroup,
+    sample_bind_group_layout: wgpu::BindGroupLayout,
+    sample_bind_group: wgpu::BindGroup,
+
+    pipeline_layout: wgpu::PipelineLayout,
+    vs_module: wgpu::ShaderModule,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl ShadowPass {
+    pub fn new(
+        device: &wgpu::Device,
+        stats: &mut ResourceStats,
+        light: &Light,
+        center: cgmath::Point3<f32>,
+    ) -> Self {
+        let settings = ShadowSettings::default();
+
+        let shadow_map =
+            Texture::create_shadow_map(device, stats, settings.resolution, SHADOW_MAP_LABEL);
+
+        let uniforms = Self::build_uniforms(&settings, light, center);
+        let uniform_buffer = stats.create_buffer_init(
+            device,
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Shadow uniform buffer"),
+                contents: bytemuck::cast_slice(&[uniforms]),
+                usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+            },
+        );
+
+        let light_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStage::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+                label: Some("Shadow light bind group layout"),
+            });
+
+        let light_bind_group = stats.create_bind_group(
+            device,
+            &wgpu::BindGroupDescriptor {
+                layout: &light_bind_group_layout,
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Buffer {
+                        buffer: &uniform_buffer,
+                        offset: 0,
+                        size: None,
+                    },
+                }],
+                label: Some("Shadow light bind group"),
+            },
+        );
+
+        let sample_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStage::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            sample_type: wgpu::TextureSampleType::Depth,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStage::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler {
+                            comparison: true,
+                            filtering: true,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStage::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+                label: Some("Shadow sample bind group layout"),
+            });
+
+        let sample_bind_group = Self::create_sample_bind_group(
+            device,
+            stats,
+            &sample_bind_group_layout,
+            &shadow_map,
+            &uniform_buffer,
+        );
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Shadow pipeline layout"),
+            bind_group_layouts: &[&light_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let vs_module = device.create_shader_module(&wgpu::include_spirv!(concat!(
+            env!("OUT_DIR"),
+            "/shadow.vert.spv"
+        )));
+
+        let pipeline = Self::create_pipeline(device, &pipeline_layout, &vs_module, &settings);
+
+        ShadowPass {
+            settings,
+            applied_settings: settings,
+
+            shadow_map,
+            uniform_buffer,
+
+            light_bind_group,
+            sample_bind_group_layout,
+            sample_bind_group,
+
+            pipeline_layout,
+            vs_module,
+            pipeline,
+        }
+    }
+
+    /// Layout of the bind group used by the main pass to sample the shadow map.
+    pub fn sample_bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.sample_bind_group_layout
+    }
+
+    pub fn sample_bind_group(&self) -> &wgpu::BindGroup {
+        &self.sample_bind_group
+    }
+
+    pub fn shadow_map_view(&self) -> &wgpu::TextureView {
+        &self.shadow_map.view
+    }
+
+    fn create_sample_bind_group(
+        device: &wgpu::Device,
+        stats: &mut ResourceStats,
+        layout: &wgpu::BindGroupLayout,
+        shadow_map: &Texture,
+        uniform_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        stats.create_bind_group(
+            device,
+            &wgpu::BindGroupDescriptor {
+                layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&shadow_map.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&shadow_map.sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::Buffer {
+                            buffer: uniform_buffer,
+                            offset: 0,
+                            size: None,
+                        },
+                    },
+                ],
+                label: Some(SAMPLE_BIND_GROUP_LABEL),
+            },
+        )
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        vs_module: &wgpu::ShaderModule,
+        settings: &ShadowSettings,
+    ) -> wgpu::RenderPipeline {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Shadow pipeline"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: vs_module,
+                entry_point: "main",
+                buffers: &[ModelVertex::desc(), InstanceRaw::desc()],
+            },
+            fragment: None,
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Texture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState {
+                    constant: settings.constant_bias,
+                    slope_scale: settings.slope_bias,
+                    clamp: 0.0,
+                },
+                clamp_depth: false,
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: wgpu::CullMode::Back,
+                polygon_mode: wgpu::PolygonMode::Fill,
+            },
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+        })
+    }
+
+    fn build_uniforms(
+        settings: &ShadowSettings,
+        light: &Light,
+        center: cgmath::Point3<f32>,
+    ) -> ShadowUniforms {
+        let eye = cgmath::Point3::from(light.position);
+        let direction = center - eye;
+        let distance = direction.magnitude().max(0.1);
+
+        // look_at is degenerate when looking straight down
+        let up = if direction.normalize().y.abs() > 0.99 {
+            cgmath::Vector3::unit_z()
+        } else {
+            cgmath::Vector3::unit_y()
+        };
+
+        let view = cgmath::Matrix4::look_at_rh(eye, center, up);
+        let extent = settings.extent;
+        let proj = cgmath::ortho(-extent, extent, -extent, extent, 0.1, distance + extent);
+
+        ShadowUniforms {
+            light_view_proj: (OPENGL_TO_WGPU_MATRIX * proj * view).into(),
+            params: [1.0 / settings.resolution as f32, 0.0, 0.0, 0.0],
+        }
+    }
+
+    /// Uploads the light matrix and applies settings changed since the last frame.
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        stats: &mut ResourceStats,
+        light: &Light,
+        center: cgmath::Point3<f32>,
+    ) {
+        if self.settings.resolution != self.applied_settings.resolution {
+            stats.release_texture(&Texture::shadow_map_descriptor(
+                self.applied_settings.resolution,
+                SHADOW_MAP_LABEL,
+            ));
+            stats.release(ResourceKind::BindGroup, SAMPLE_BIND_GROUP_LABEL, 0);
+            self.shadow_map = Texture::create_shadow_map(
+                device,
+                stats,
+                self.settings.resolution,
+                SHADOW_MAP_LABEL,
+            );
+            self.sample_bind_group = Self::create_sample_bind_group(
+                device,
+                stats,
+                &self.sample_bind_group_layout,
+                &self.shadow_map,
+                &self.uniform_buffer,
+            );
+        }
+
+        if self.settings.constant_bias != self.applied_settings.constant_bias
+            || self.settings.slope_bias != self.applied_settings.slope_bias
+        {
+            self.pipeline = Self::create_pipeline(
+                device,
+                &self.pipeline_layout,
+                &self.vs_module,
+                &self.settings,
+            );
+        }
+
+        self.applied_settings = self.settings;
+
+        let uniforms = Self::build_uniforms(&self.settings, light, center);
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniforms]));
+    }
+
+    pub fn encode(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        model: &Model,
+        instance_buffer: &wgpu::Buffer,
+        instance_count: u32,
+    ) {
+        let mut shadow_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Shadow render pass"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachmentDescriptor {
+                attachment: &self.shadow_map.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+
+        shadow_pass.set_pipeline(&self.pipeline);
+        shadow_pass.set_bind_group(0, &self.light_bind_group, &[]);
+        shadow_pass.set_vertex_buffer(1, instance_buffer.slice(..));
+        shadow_pass.draw_model_geometry_instanced(model, 0..instance_count);
+    }
+
+    pub fn build_ui(&mut self, ui: &imgui::Ui) {
+        let settings = &mut self.settings;
+        let window = imgui::Window::new(im_str!("Shadows"));
+        window
+            .size([250.0, 130.0], Condition::FirstUseEver)
+            .position([0.0, 400.0], Condition::FirstUseEver)
+            .build(&ui, || {
+                let mut resolution_index = RESOLUTIONS
+                    .iter()
+                    .position(|&r| r == settings.resolution)
+                    .unwrap_or(0);
+                if ComboBox::new(im_str!("Resolution")).build_simple_string(
+                    &ui,
+                    &mut resolution_index,
+                    &[
+                        im_str!("512"),
+                        im_str!("1024"),
+                        im_str!("2048"),
+                        im_str!("4096"),
+                    ],
+                ) {
+                    settings.resolution = RESOLUTIONS[resolution_index];
+                }
+                Slider::new(im_str!("Constant bias"))
+                    .range(0..=16)
+                    .build(&ui, &mut settings.constant_bias);
+                Slider::new(im_str!("Slope bias"))
+                    .range(0.0..=8.0)
+                    .build(&ui, &mut settings.slope_bias);
+                Slider::new(im_str!("Extent"))
+                    .range(5.0..=50.0)
+                    .build(&ui, &mut settings.extent);
+            });
+    }
+}
+
+/// Center of the instance positions, used as the target of the shadow camera.
+pub fn scene_center(positions: impl Iterator<Item = cgmath::Vector3<f32>>) -> cgmath::Point3<f32> {
+    let (sum, count) = positions.fold(
+        (cgmath::Vector3::new(0.0, 0.0, 0.0), 0),
+        |(sum, count), p| (sum + p, count + 1),
+    );
+    if count == 0 {
+        cgmath::Point3::origin()
+    } else {
+        cgmath::Point3::from_vec(sum / count as f32)
+    }
+}
@@ -0,0 +1,527 @@
+use std::collections::HashMap;
+
+use crate::vertex::ModelVertex;
+
+type Vec3 = [f64; 3];
+
+// Makes moving a border vertex off its border much costlier than bending a surface
+const BORDER_WEIGHT: f64 = 10.0;
+
+fn sub(a: Vec3, b: Vec3) -> Vec3 {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn cross(a: Vec3, b: Vec3) -> Vec3 {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn dot(a: Vec3, b: Vec3) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+/// Sum of squared distances to a set of planes, as a symmetric 4x4 matrix.
+///
+/// Only the upper triangle is stored: a00 a01 a02 a03 a11 a12 a13 a22 a23 a33.
+#[derive(Debug, Clone, Copy, Default)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    fn from_plane(normal: Vec3, distance: f64) -> Self {
+        let [a, b, c] = normal;
+        let d = distance;
+        Quadric([
+            a * a,
+            a * b,
+            a * c,
+            a * d,
+            b * b,
+            b * c,
+            b * d,
+            c * c,
+            c * d,
+            d * d,
+        ])
+    }
+
+    fn add(&mut self, other: &Quadric) {
+        for (value, other) in self.0.iter_mut().zip(other.0.iter()) {
+            *value += other;
+        }
+    }
+
+    fn scale(&mut self, factor: f64) {
+        for value in self.0.iter_mut() {
+            *value *= factor;
+        }
+    }
+
+    fn error(&self, point: Vec3) -> f64 {
+        let q = &self.0;
+        let [x, y, z] = point;
+        q[0] * x * x
+            + 2.0 * q[1] * x * y
+            + 2.0 * q[2] * x * z
+            + 2.0 * q[3] * x
+            + q[4] * y * y
+            + 2.0 * q[5] * y * z
+            + 2.0 * q[6] * y
+            + q[7] * z * z
+            + 2.0 * q[8] * z
+            + q[9]
+    }
+}
+
+fn position(vertex: &ModelVertex) -> Vec3 {
+    let [x, y, z] = vertex.position;
+    [x as f64, y as f64, z as f64]
+}
+
+fn triangle_normal(p0: Vec3, p1: Vec3, p2: Vec3) -> Vec3 {
+    cross(sub(p1, p0), sub(p2, p0))
+}
+
+/// Distance between the attributes of two vertices sharing a position.
+fn attribute_distance(a: &ModelVertex, b: &ModelVertex) -> f32 {
+    let uv = (a.tex_coords[0] - b.tex_coords[0]).abs() + (a.tex_coords[1] - b.tex_coords[1]).abs();
+    let normal = (0..3)
+        .map(|i| (a.normal[i] - b.normal[i]).abs())
+        .sum::<f32>();
+    uv + normal
+}
+
+/// Reduces a triangle list with quadric error metrics edge collapses.
+///
+/// Vertices are collapsed onto existing vertices, so the returned index buffer still
+/// refers to `vertices`. Simplification stops once `target_index_count` is reached or
+/// when every remaining collapse would move the surface by more than `max_error`.
+pub fn simplify(
+    vertices: &[ModelVertex],
+    indices: &[u32],
+    target_index_count: usize,
+    max_error: f32,
+) -> Vec<u32> {
+    // Vertices split by UV or normal seams share a position and collapse together
+    let mut welded: HashMap<[u32; 3], usize> = HashMap::new();
+    let groups: Vec<usize> = vertices
+        .iter()
+        .enumerate()
+        .map(|(index, vertex)| {
+            let key = [
+                vertex.position[0].to_bits(),
+                vertex.position[1].to_bits(),
+                vertex.position[2].to_bits(),
+            ];
+            *welded.entry(key).or_insert(index)
+        })
+        .collect();
+
+    let mut members = vec![Vec::new(); vertices.len()];
+    for (vertex, &group) in groups.iter().enumerate() {
+        members[group].push(vertex as u32);
+    }
+
+    let mut quadrics = vec![Quadric::default(); vertices.len()];
+    for triangle in indices.chunks_exact(3) {
+        let [p0, p1, p2] = [
+            position(&vertices[triangle[0] as usize]),
+            position(&vertices[triangle[1] as usize]),
+            position(&vertices[triangle[2] as usize]),
+        ];
+        let normal = triangle_normal(p0, p1, p2);
+        let length = dot(normal, normal).sqrt();
+        if length == 0.0 {
+            continue;
+        }
+
+        let normal = [normal[0] / length, normal[1] / length, normal[2] / length];
+        let quadric = Quadric::from_plane(normal, -dot(normal, p0));
+        for &vertex in triangle {
+            quadrics[groups[vertex as usize]].add(&quadric);
+        }
+    }
+
+    // Open borders get a plane perpendicular to their face, so they do not shrink inwards
+    let mut edge_uses: HashMap<(usize, usize), u32> = HashMap::new();
+    for triangle in indices.chunks_exact(3) {
+        for corner in 0..3 {
+            let a = groups[triangle[corner] as usize];
+            let b = groups[triangle[(corner + 1) % 3] as usize];
+            *edge_uses.entry((a.min(b), a.max(b))).or_insert(0) += 1;
+        }
+    }
+    for triangle in indices.chunks_exact(3) {
+        let corners = [
+            groups[triangle[0] as usize],
+            groups[triangle[1] as usize],
+            groups[triangle[2] as usize],
+        ];
+        let face_normal = triangle_normal(
+            position(&vertices[corners[0]]),
+            position(&vertices[corners[1]]),
+            position(&vertices[corners[2]]),
+        );
+        for corner in 0..3 {
+            let (a, b) = (corners[corner], corners[(corner + 1) % 3]);
+            if edge_uses.get(&(a.min(b), a.max(b))) != Some(&1) {
+                continue;
+            }
+
+            let edge = sub(position(&vertices[b]), position(&vertices[a]));
+            let normal = cross(edge, face_normal);
+            let length = dot(normal, normal).sqrt();
+            if length == 0.0 {
+                continue;
+            }
+
+            let normal = [normal[0] / length, normal[1] / length, normal[2] / length];
+            let mut quadric = Quadric::from_plane(normal, -dot(normal, position(&vertices[a])));
+            quadric.scale(BORDER_WEIGHT);
+            quadrics[a].add(&quadric);
+            quadrics[b].add(&quadric);
+        }
+    }
+
+    let max_cost = (max_error as f64) * (max_error as f64);
+    let mut indices = indices.to_vec();
+
+    // Each pass collapses independent edges, then rewrites the index buffer
+    while indices.len() > target_index_count {
+        let mut adjacency = vec![Vec::new(); vertices.len()];
+        let mut collapses = Vec::new();
+        for (triangle_index, triangle) in indices.chunks_exact(3).enumerate() {
+            for corner in 0..3 {
+                let from = groups[triangle[corner] as usize];
+                let to = groups[triangle[(corner + 1) % 3] as usize];
+                adjacency[from].push(triangle_index);
+                if from == to {
+                    continue;
+                }
+
+                let mut quadric = quadrics[from];
+                quadric.add(&quadrics[to]);
+                collapses.push((quadric.error(position(&vertices[to])), from, to));
+            }
+        }
+        collapses.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+
+        let mut remap: Vec<u32> = (0..vertices.len() as u32).collect();
+        let mut locked = vec![false; vertices.len()];
+        let mut removed_indices = 0;
+        for (cost, from, to) in collapses {
+            if cost > max_cost || locked[from] || locked[to] {
+                continue;
+            }
+            if indices.len() - removed_indices <= target_index_count {
+                break;
+            }
+            if flips_triangles(vertices, &indices, &groups, &adjacency[from], from, to) {
+                continue;
+            }
+
+            for &triangle in &adjacency[from] {
+                let triangle = &indices[triangle * 3..triangle * 3 + 3];
+                for &vertex in triangle {
+                    locked[groups[vertex as usize]] = true;
+                }
+                if triangle.iter().any(|&vertex| groups[vertex as usize] == to) {
+                    removed_indices += 3;
+                }
+            }
+
+            for &vertex in &members[from] {
+                let closest = members[to]
+                    .iter()
+                    .copied()
+                    .min_by(|&a, &b| {
+                        let distance_a =
+                            attribute_distance(&vertices[vertex as usize], &vertices[a as usize]);
+                        let distance_b =
+                            attribute_distance(&vertices[vertex as usize], &vertices[b as usize]);
+                        distance_a
+                            .partial_cmp(&distance_b)
+                            .unwrap_or(std::cmp::Ordering::Equal)
+                    })
+                    .expect("Empty vertex group");
+                remap[vertex as usize] = closest;
+            }
+            let from_quadric = quadrics[from];
+            quadrics[to].add(&from_quadric);
+        }
+
+        if removed_indices == 0 {
+            break;
+        }
+
+        let previous_len = indices.len();
+        indices = indices
+            .chunks_exact(3)
+            .map(|triangle| {
+                [
+                    remap[triangle[0] as usize],
+                    remap[triangle[1] as usize],
+                    remap[triangle[2] as usize],
+                ]
+            })
+            .filter(|triangle| {
+                let [a, b, c] = [
+                    groups[triangle[0] as usize],
+                    groups[triangle[1] as usize],
+                    groups[triangle[2] as usize],
+                ];
+                a != b && b != c && a != c
+            })
+            .flat_map(|triangle| triangle.to_vec())
+            .collect();
+
+        if indices.len() == previous_len {
+            break;
+        }
+    }
+
+    indices
+}
+
+/// Whether moving group `from` onto group `to` turns any remaining triangle over.
+fn flips_triangles(
+    vertices: &[ModelVertex],
+    indices: &[u32],
+    groups: &[usize],
+    triangles: &[usize],
+    from: usize,
+    to: usize,
+) -> bool {
+    let target = position(&vertices[to]);
+    triangles.iter().any(|&triangle| {
+        let corners = &indices[triangle * 3..triangle * 3 + 3];
+        if corners.iter().any(|&vertex| groups[vertex as usize] == to) {
+            // Collapsed into a degenerate triangle
+            return false;
+        }
+
+        let before: Vec<Vec3> = corners
+            .iter()
+            .map(|&vertex| position(&vertices[vertex as usize]))
+            .collect();
+        let after: Vec<Vec3> = corners
+            .iter()
+            .zip(before.iter())
+            .map(|(&vertex, &p)| {
+                if groups[vertex as usize] == from {
+                    target
+                } else {
+                    p
+                }
+            })
+            .collect();
+
+        let normal_before = triangle_normal(before[0], before[1], before[2]);
+        let normal_after = triangle_normal(after[0], after[1], after[2]);
+        dot(normal_before, normal_after) <= 0.0
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vertex(position: [f32; 3], tex_coords: [f32; 2], normal: [f32; 3]) -> ModelVertex {
+        ModelVertex {
+            position,
+            tex_coords,
+            normal,
+        }
+    }
+
+    /// Open unit square in the XZ plane, facing up, split in `n` by `n` quads.
+    fn grid(n: u32) -> (Vec<ModelVertex>, Vec<u32>) {
+        let mut vertices = Vec::new();
+        for z in 0..=n {
+            for x in 0..=n {
+                let (u, v) = (x as f32 / n as f32, z as f32 / n as f32);
+                vertices.push(vertex([u, 0.0, v], [u, v], [0.0, 1.0, 0.0]));
+            }
+        }
+
+        let mut indices = Vec::new();
+        for z in 0..n {
+            for x in 0..n {
+                let a = z * (n + 1) + x;
+                let (b, c) = (a + 1, a + n + 1);
+                indices.extend_from_slice(&[a, c, b, b, c, c + 1]);
+            }
+        }
+        (vertices, indices)
+    }
+
+    /// Closed unit sphere, with duplicated vertices along its UV seam and at its poles.
+    ///
+    /// The degenerate triangles touching the poles are left out.
+    fn sphere(n: u32) -> (Vec<ModelVertex>, Vec<u32>) {
+        use std::f32::consts::PI;
+
+        let mut vertices = Vec::new();
+        for i in 0..=n {
+            for j in 0..=n {
+                let (u, v) = (j as f32 / n as f32, i as f32 / n as f32);
+                let (theta, phi) = (v * PI, u * 2.0 * PI);
+                let position = [
+                    theta.sin() * phi.cos(),
+                    theta.cos(),
+                    -theta.sin() * phi.sin(),
+                ];
+                vertices.push(vertex(position, [u, v], position));
+            }
+        }
+
+        let mut indices = Vec::new();
+        for i in 0..n {
+            for j in 0..n {
+                let a = i * (n + 1) + j;
+                let (b, c) = (a + 1, a + n + 1);
+                if i > 0 {
+                    indices.extend_from_slice(&[a, c, b]);
+                }
+                if i < n - 1 {
+                    indices.extend_from_slice(&[b, c, c + 1]);
+                }
+            }
+        }
+        (vertices, indices)
+    }
+
+    /// Closed cube of side 2 with a vertex per face corner, like a flat shaded model.
+    fn cube() -> (Vec<ModelVertex>, Vec<u32>) {
+        let faces = [
+            ([0.0, 0.0, 1.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+            ([0.0, 0.0, -1.0], [-1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+            ([1.0, 0.0, 0.0], [0.0, 0.0, -1.0], [0.0, 1.0, 0.0]),
+            ([-1.0, 0.0, 0.0], [0.0, 0.0, 1.0], [0.0, 1.0, 0.0]),
+            ([0.0, 1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, -1.0]),
+            ([0.0, -1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0]),
+        ];
+
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        for (normal, right, up) in faces.iter() {
+            let base = vertices.len() as u32;
+            for &(x, y) in [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)].iter() {
+                let position = [
+                    normal[0] + right[0] * x + up[0] * y,
+                    normal[1] + right[1] * x + up[1] * y,
+                    normal[2] + right[2] * x + up[2] * y,
+                ];
+                vertices.push(vertex(position, [x, y], *normal));
+            }
+            indices.extend_from_slice(&[base, base + 1, base + 2, base, base + 2, base + 3]);
+        }
+        (vertices, indices)
+    }
+
+    fn triangle_positions(vertices: &[ModelVertex], triangle: &[u32]) -> [Vec3; 3] {
+        [
+            position(&vertices[triangle[0] as usize]),
+            position(&vertices[triangle[1] as usize]),
+            position(&vertices[triangle[2] as usize]),
+        ]
+    }
+
+    /// Checks the output is a triangle list of `vertices`, whose triangles all face
+    /// the same side as `outwards` says they should.
+    fn assert_valid(vertices: &[ModelVertex], indices: &[u32], outwards: impl Fn(Vec3) -> Vec3) {
+        assert_eq!(indices.len() % 3, 0);
+        assert!(indices
+            .iter()
+            .all(|&index| (index as usize) < vertices.len()));
+
+        for triangle in indices.chunks_exact(3) {
+            let [p0, p1, p2] = triangle_positions(vertices, triangle);
+            let centroid = [
+                (p0[0] + p1[0] + p2[0]) / 3.0,
+                (p0[1] + p1[1] + p2[1]) / 3.0,
+                (p0[2] + p1[2] + p2[2]) / 3.0,
+            ];
+            assert!(
+                dot(triangle_normal(p0, p1, p2), outwards(centroid)) > 0.0,
+                "triangle {:?} is flipped",
+                triangle
+            );
+        }
+    }
+
+    fn area(vertices: &[ModelVertex], indices: &[u32]) -> f64 {
+        indices
+            .chunks_exact(3)
+            .map(|triangle| {
+                let [p0, p1, p2] = triangle_positions(vertices, triangle);
+                let normal = triangle_normal(p0, p1, p2);
+                dot(normal, normal).sqrt() / 2.0
+            })
+            .sum()
+    }
+
+    #[test]
+    fn flat_grid_reaches_target_and_keeps_its_border() {
+        let (vertices, indices) = grid(16);
+        let target = indices.len() / 4;
+        let simplified = simplify(&vertices, &indices, target, 0.001);
+
+        assert!(simplified.len() <= target);
+        assert_valid(&vertices, &simplified, |_| [0.0, 1.0, 0.0]);
+
+        // Vertices are never moved, so a shrinking border shows up as a smaller area
+        assert!((area(&vertices, &simplified) - 1.0).abs() < 1e-6);
+        for corner in [
+            [0.0, 0.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0],
+            [1.0, 0.0, 1.0],
+        ]
+        .iter()
+        {
+            assert!(
+                simplified
+                    .iter()
+                    .any(|&index| vertices[index as usize].position == *corner),
+                "corner {:?} was collapsed",
+                corner
+            );
+        }
+    }
+
+    #[test]
+    fn sphere_reaches_target_without_flipping() {
+        let (vertices, indices) = sphere(24);
+        let target = indices.len() / 4;
+        let simplified = simplify(&vertices, &indices, target, 0.2);
+
+        assert!(simplified.len() <= target);
+        assert!(simplified.len() >= target / 2);
+        assert_valid(&vertices, &simplified, |centroid| centroid);
+    }
+
+    #[test]
+    fn sphere_stops_at_max_error() {
+        let (vertices, indices) = sphere(24);
+        let loose = simplify(&vertices, &indices, 0, 0.1);
+        let tight = simplify(&vertices, &indices, 0, 0.001);
+
+        assert!(tight.len() > loose.len());
+        assert!(!loose.is_empty());
+        assert_valid(&vertices, &loose, |centroid| centroid);
+        assert_valid(&vertices, &tight, |centroid| centroid);
+    }
+
+    #[test]
+    fn cube_keeps_its_corners() {
+        let (vertices, indices) = cube();
+        let simplified = simplify(&vertices, &indices, indices.len() / 2, 0.01);
+
+        assert_eq!(simplified.len(), indices.len());
+        assert_valid(&vertices, &simplified, |centroid| centroid);
+        assert!((area(&vertices, &simplified) - 24.0).abs() < 1e-6);
+    }
+}
@@ -1,87 +1,306 @@
-use cgmath::{InnerSpace, SquareMatrix, Zero};
-use imgui::{im_str, Condition, Context};
-use imgui_wgpu::{Renderer, RendererConfig};
-use wgpu::{
-    util::DeviceExt, ColorTargetState, DepthBiasState, DepthStencilState, FragmentState,
-    MultisampleState, PrimitiveState, PrimitiveTopology, StencilState, VertexState,
+use std::{
+    cell::RefCell,
+    ops::Range,
+    panic::{self, AssertUnwindSafe},
+    rc::Rc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
 };
+
+use cgmath::{EuclideanSpace, InnerSpace, MetricSpace, SquareMatrix, Zero};
+use imgui::{im_str, ColorEdit, ComboBox, Condition, Context, Drag, Slider};
+use imgui_wgpu::{Renderer, RendererConfig};
 use winit::{
     dpi::PhysicalSize,
-    event::{DeviceEvent, WindowEvent},
+    event::{DeviceEvent, ElementState, MouseButton, WindowEvent},
     window::Window,
 };
 
+use crate::model::{DrawModel, MeshFilter};
 use crate::{
     camera::{Camera, CameraController},
+    config::Config,
+    culling::{Aabb, CullingMode, Frustum},
+    debug_draw::{DebugDraw, DebugOverlays},
+    gpu_culling::GpuCulling,
+    light::Light,
+    lod,
+    outline::OutlinePass,
+    picking::Picking,
+    pipeline::{BlendMode, PipelineCache, PipelineKey, ShaderVariant, ViewMode},
+    post::PostChain,
+    present_mode,
+    profiler::Profiler,
+    readback::Readback,
+    recording::{self, CaptureTarget, Recorder, RecordingFormat, RecordingSettings},
+    render_graph::{RenderGraph, TextureHandle, TexturePool, TransientTexture},
+    resource_stats::{DrawStats, ResourceStats},
+    screenshot,
+    shadow::{self, ShadowPass},
     texture::Texture,
+    tonemap::TonemapPass,
 };
 use crate::{
-    instance::{Instance, InstanceRaw},
-    model::Model,
+    instance::Instance,
+    model::{Model, ModelData},
 };
-use crate::{model::DrawModel, vertex::ModelVertex};
+
+/// MSAA sample counts offered in the UI when the adapter supports them, see
+/// [`supported_sample_counts`]. Object IDs are integers, which only guarantee a single
+/// sample, so they get their own pass.
+const SAMPLE_COUNTS: [u32; 4] = [1, 2, 4, 8];
+
+/// What outlives a device, needed to create another one when it is lost.
+struct DeviceSource {
+    instance: Rc<wgpu::Instance>,
+    /// The window surface, `None` when rendering offscreen.
+    surface: Option<wgpu::Surface>,
+    config: Config,
+}
+
+enum RenderTarget {
+    Window {
+        swapchain: wgpu::SwapChain,
+        // Borrowed mutably from inside the render graph
+        imgui_renderer: RefCell<Renderer>,
+    },
+    Offscreen {
+        color_texture: Texture,
+    },
+}
 
 pub struct State {
-    surface: wgpu::Surface,
+    source: DeviceSource,
+    device_lost: Arc<AtomicBool>,
+    adapter_info: wgpu::AdapterInfo,
+    adapter_limits: wgpu::Limits,
     device: wgpu::Device,
     queue: wgpu::Queue,
     swapchain_desc: wgpu::SwapChainDescriptor,
-    swapchain: wgpu::SwapChain,
+    supported_present_modes: Vec<wgpu::PresentMode>,
+    supported_sample_counts: Vec<u32>,
+    target: RenderTarget,
     pub window_size: PhysicalSize<u32>,
 
-    render_pipeline: wgpu::RenderPipeline,
+    pipelines: PipelineCache,
+    view_mode: ViewMode,
+    wireframe_overlay: bool,
+    wireframe_supported: bool,
     uniform_bind_group: wgpu::BindGroup,
-    depth_texture: Texture,
+    sample_count: u32,
+    texture_pool: RefCell<TexturePool>,
+    // Borrowed mutably while the render graph executes
+    profiler: RefCell<Profiler>,
+    resource_stats: RefCell<ResourceStats>,
+    draw_stats: DrawStats,
+    tonemap: TonemapPass,
+    post: PostChain,
+    shadow: ShadowPass,
+    scene_center: cgmath::Point3<f32>,
     uniform_buffer: wgpu::Buffer,
+    light_buffer: wgpu::Buffer,
 
     model: Model,
+    model_data: ModelData,
 
     uniforms: Uniforms,
+    light: Light,
     camera: Camera,
     camera_controller: CameraController,
 
     instances: Vec<Instance>,
+    // Instances inside the view frustum, compacted at the start of the buffer
     instance_buffer: wgpu::Buffer,
+    // Visible instances sorted back-to-front, used by the transparent pass
+    sorted_instance_buffer: wgpu::Buffer,
+    // Every instance, as casters outside of the view can still shadow visible ones
+    shadow_instance_buffer: wgpu::Buffer,
+    visible_instance_count: u32,
+    // Visible instances are grouped by level of detail in the instance buffer
+    lod_ranges: Vec<Range<u32>>,
+    lod_bias: f32,
+    culling_mode: CullingMode,
+    gpu_culling: GpuCulling,
 
-    imgui_renderer: Renderer,
+    debug_draw: DebugDraw,
+    debug_overlays: DebugOverlays,
+
+    picking: Picking,
+    cursor_position: (u32, u32),
+    // The cursor does not move while grabbed, picking uses the window center instead
+    cursor_grabbed: bool,
+    outline: OutlinePass,
+
+    screenshot_supersampling: u32,
+    screenshot_requested: bool,
+    recording_settings: RecordingSettings,
+    recorder: Option<Recorder>,
 }
 
 impl State {
-    pub async fn new(window: &Window, imgui_context: &mut Context) -> Self {
+    /// Creates a state rendering into `window`, with the adapter and present mode chosen
+    /// by `config`.
+    pub async fn new(window: &Window, imgui_context: &mut Context, config: &Config) -> Self {
         let size = window.inner_size();
 
-        let instance = wgpu::Instance::new(wgpu::BackendBit::PRIMARY);
+        let instance = Rc::new(wgpu::Instance::new(config.backends));
         let surface = unsafe { instance.create_surface(window) };
+        let source = DeviceSource {
+            instance,
+            surface: Some(surface),
+            config: config.clone(),
+        };
 
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::HighPerformance,
-                compatible_surface: Some(&surface),
-            })
+        let swapchain_desc = wgpu::SwapChainDescriptor {
+            usage: wgpu::TextureUsage::RENDER_ATTACHMENT,
+            format: wgpu::TextureFormat::Bgra8UnormSrgb,
+            width: size.width,
+            height: size.height,
+            present_mode: config.present_mode,
+        };
+
+        Self::create(
+            source,
+            swapchain_desc,
+            load_model(config),
+            Some(imgui_context),
+        )
+        .await
+        .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Creates a state rendering into an offscreen color texture instead of a window.
+    pub async fn new_headless(size: PhysicalSize<u32>, config: &Config) -> Self {
+        let source = DeviceSource {
+            instance: Rc::new(wgpu::Instance::new(config.backends)),
+            surface: None,
+            config: config.clone(),
+        };
+
+        let swapchain_desc = wgpu::SwapChainDescriptor {
+            usage: wgpu::TextureUsage::RENDER_ATTACHMENT,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            width: size.width,
+            height: size.height,
+            present_mode: wgpu::PresentMode::Fifo,
+        };
+
+        Self::create(source, swapchain_desc, load_model(config), None)
+            .await
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Requests an adapter and a device, then creates every GPU resource.
+    ///
+    /// `imgui_context` is only needed when `source` has a surface.
+    async fn create(
+        source: DeviceSource,
+        mut swapchain_desc: wgpu::SwapChainDescriptor,
+        model_data: ModelData,
+        imgui_context: Option<&mut Context>,
+    ) -> Result<Self, String> {
+        let adapter = source
+            .config
+            .request_adapter(&source.instance, source.surface.as_ref())
+            .await
+            .ok_or_else(|| {
+                format!(
+                    "Cannot find a suitable adapter for {:?}, see --list-adapters",
+                    source.config
+                )
+            })?;
+
+        let (device, queue) = Self::request_device(&adapter)
             .await
-            .expect("Cannot find a suitable adapter");
+            .map_err(|e| format!("Failed to request a device and a queue: {}", e))?;
+
+        let target = match &source.surface {
+            Some(surface) => {
+                swapchain_desc.present_mode = present_mode::choose(
+                    swapchain_desc.present_mode,
+                    &present_mode::supported(adapter.get_info().backend),
+                );
+                let swapchain = device.create_swap_chain(surface, &swapchain_desc);
+
+                let imgui_renderer = Renderer::new(
+                    imgui_context.expect("Window target without an imgui context"),
+                    &device,
+                    &queue,
+                    RendererConfig {
+                        texture_format: swapchain_desc.format,
+                        ..Default::default()
+                    },
+                );
+
+                RenderTarget::Window {
+                    swapchain,
+                    imgui_renderer: RefCell::new(imgui_renderer),
+                }
+            }
+            None => RenderTarget::Offscreen {
+                color_texture: Texture::create_render_target(
+                    &device,
+                    &swapchain_desc,
+                    swapchain_desc.format,
+                    "Offscreen color texture",
+                ),
+            },
+        };
+
+        Ok(Self::from_device(
+            source,
+            &adapter,
+            device,
+            queue,
+            swapchain_desc,
+            target,
+            model_data,
+        ))
+    }
 
-        let (device, queue) = adapter
+    async fn request_device(
+        adapter: &wgpu::Adapter,
+    ) -> Result<(wgpu::Device, wgpu::Queue), wgpu::RequestDeviceError> {
+        adapter
             .request_device(
                 &wgpu::DeviceDescriptor {
                     label: Some("Main device"),
-                    features: wgpu::Features::empty(),
+                    // Line polygon mode is only needed by the wireframe overlay, timestamp
+                    // queries by the profiler
+                    features: adapter.features()
+                        & (wgpu::Features::NON_FILL_POLYGON_MODE | wgpu::Features::TIMESTAMP_QUERY),
                     limits: wgpu::Limits::default(),
                 },
                 None,
             )
             .await
-            .expect("Failed to request a device and a queue");
+    }
 
-        let swapchain_desc = wgpu::SwapChainDescriptor {
-            usage: wgpu::TextureUsage::RENDER_ATTACHMENT,
-            format: wgpu::TextureFormat::Bgra8UnormSrgb,
-            width: size.width,
-            height: size.height,
-            present_mode: wgpu::PresentMode::Mailbox,
-        };
+    fn from_device(
+        source: DeviceSource,
+        adapter: &wgpu::Adapter,
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        swapchain_desc: wgpu::SwapChainDescriptor,
+        target: RenderTarget,
+        model_data: ModelData,
+    ) -> Self {
+        let size = PhysicalSize::new(swapchain_desc.width, swapchain_desc.height);
 
-        let swapchain = device.create_swap_chain(&surface, &swapchain_desc);
+        // Replaces the error handler, so it runs before the one below is set
+        let supported_sample_counts = supported_sample_counts(&device);
+
+        let device_lost = Arc::new(AtomicBool::new(false));
+        let lost = Arc::clone(&device_lost);
+        device.on_uncaptured_error(move |error| {
+            if is_device_loss(&error) {
+                lost.store(true, Ordering::Relaxed);
+            } else {
+                panic!("{}", error);
+            }
+        });
 
         let texture_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
@@ -105,6 +324,16 @@ impl State {
                         },
                         count: None,
                     },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStage::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
                 ],
                 label: Some("Texture bind group layout"),
             });
@@ -124,13 +353,81 @@ impl State {
         let mut uniforms = Uniforms::new();
         uniforms.update_view_proj(&camera);
 
-        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Uniform buffer"),
-            contents: bytemuck::cast_slice(&[uniforms]),
-            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
-        });
+        let mut resource_stats = ResourceStats::default();
+        let uniform_buffer = resource_stats.create_buffer_init(
+            &device,
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Uniform buffer"),
+                contents: bytemuck::cast_slice(&[uniforms]),
+                usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+            },
+        );
+
+        let light = Light::new([2.0, 4.0, 2.0], [1.0, 1.0, 1.0]);
+
+        let light_buffer = resource_stats.create_buffer_init(
+            &device,
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Light buffer"),
+                contents: bytemuck::cast_slice(&[light]),
+                usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+            },
+        );
 
         let uniform_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStage::VERTEX | wgpu::ShaderStage::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStage::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+                label: Some("Uniform bind group layout"),
+            });
+
+        let uniform_bind_group = resource_stats.create_bind_group(
+            &device,
+            &wgpu::BindGroupDescriptor {
+                layout: &uniform_bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::Buffer {
+                            buffer: &uniform_buffer,
+                            offset: 0,
+                            size: None,
+                        },
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Buffer {
+                            buffer: &light_buffer,
+                            offset: 0,
+                            size: None,
+                        },
+                    },
+                ],
+                label: Some("Uniform bind group"),
+            },
+        );
+
+        let mesh_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 entries: &[wgpu::BindGroupLayoutEntry {
                     binding: 0,
@@ -142,130 +439,215 @@ impl State {
                     },
                     count: None,
                 }],
-                label: Some("Uniform bind group layout"),
+                label: Some("Mesh bind group layout"),
             });
 
-        let uniform_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &uniform_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: wgpu::BindingResource::Buffer {
-                    buffer: &uniform_buffer,
-                    offset: 0,
-                    size: None,
-                },
-            }],
-            label: Some("Uniform bind group"),
-        });
-
-        let model = Model::open(
-            "res/cube/cube.obj",
+        let model = Model::from_data(
+            &model_data,
             &device,
             &queue,
+            &mut resource_stats,
             &texture_bind_group_layout,
-        )
-        .expect("Failed to open model");
+            &mesh_bind_group_layout,
+        );
 
-        let depth_texture =
-            Texture::create_depth_texture(&device, &swapchain_desc, "Depth texture");
+        let supported_present_modes = present_mode::supported(adapter.get_info().backend);
+        let sample_count = 1;
 
-        let vs_module = device.create_shader_module(&wgpu::include_spirv!(concat!(
-            env!("OUT_DIR"),
-            "/shader.vert.spv"
-        )));
-        let fs_module = device.create_shader_module(&wgpu::include_spirv!(concat!(
-            env!("OUT_DIR"),
-            "/shader.frag.spv"
-        )));
+        let instances = Self::build_instances();
+        let scene_center = shadow::scene_center(instances.iter().map(|i| i.position));
+        let shadow = ShadowPass::new(&device, &mut resource_stats, &light, scene_center);
 
         let render_pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                 label: Some("Render pipeline layout"),
-                bind_group_layouts: &[&texture_bind_group_layout, &uniform_bind_group_layout],
+                bind_group_layouts: &[
+                    &texture_bind_group_layout,
+                    &uniform_bind_group_layout,
+                    shadow.sample_bind_group_layout(),
+                    &mesh_bind_group_layout,
+                ],
                 push_constant_ranges: &[],
             });
 
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: VertexState {
-                module: &vs_module,
-                entry_point: "main",
-                buffers: &[ModelVertex::desc(), InstanceRaw::desc()],
+        let pipelines = PipelineCache::new(&device, render_pipeline_layout);
+        let wireframe_supported = device
+            .features()
+            .contains(wgpu::Features::NON_FILL_POLYGON_MODE);
+
+        let tonemap = TonemapPass::new(&device, &mut resource_stats, swapchain_desc.format);
+        let post = PostChain::new(&device, &queue, &mut resource_stats, &swapchain_desc);
+
+        let instance_count = instances.len() as u32;
+        let raw_instances: Vec<_> = instances
+            .iter()
+            .enumerate()
+            .map(|(id, instance)| instance.to_raw(id as u32))
+            .collect();
+        let instance_buffer = resource_stats.create_buffer_init(
+            &device,
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Instance buffer"),
+                contents: bytemuck::cast_slice(&raw_instances),
+                usage: wgpu::BufferUsage::VERTEX | wgpu::BufferUsage::COPY_DST,
             },
-            fragment: Some(FragmentState {
-                module: &fs_module,
-                entry_point: "main",
-                targets: &[ColorTargetState {
-                    format: swapchain_desc.format,
-                    color_blend: wgpu::BlendState::REPLACE,
-                    alpha_blend: wgpu::BlendState::REPLACE,
-                    write_mask: wgpu::ColorWrite::ALL,
-                }],
-            }),
-            depth_stencil: Some(DepthStencilState {
-                format: Texture::DEPTH_FORMAT,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less,
-                stencil: StencilState::default(),
-                bias: DepthBiasState::default(),
-                clamp_depth: false,
-            }),
-            primitive: PrimitiveState {
-                topology: PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: wgpu::CullMode::Back,
-                polygon_mode: wgpu::PolygonMode::Fill,
+        );
+        let shadow_instance_buffer = resource_stats.create_buffer_init(
+            &device,
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Shadow instance buffer"),
+                contents: bytemuck::cast_slice(&raw_instances),
+                usage: wgpu::BufferUsage::VERTEX,
             },
-            multisample: MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
+        );
+        let gpu_culling = GpuCulling::new(&device, &mut resource_stats, &model, &raw_instances);
+        let sorted_instance_buffer = resource_stats.create_buffer_init(
+            &device,
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Sorted instance buffer"),
+                contents: bytemuck::cast_slice(&raw_instances),
+                usage: wgpu::BufferUsage::VERTEX | wgpu::BufferUsage::COPY_DST,
             },
-        });
-
-        let instances = Self::build_instances();
-        let raw_instances: Vec<_> = instances.iter().map(Instance::to_raw).collect();
-        let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Instance buffer"),
-            contents: bytemuck::cast_slice(&raw_instances),
-            usage: wgpu::BufferUsage::VERTEX,
-        });
+        );
 
-        let imgui_renderer = Renderer::new(
-            imgui_context,
+        let debug_draw = DebugDraw::new(&device, sample_count);
+        let profiler = Profiler::new(&adapter, &device, &mut resource_stats);
+        let picking = Picking::new(
             &device,
-            &queue,
-            RendererConfig {
-                texture_format: swapchain_desc.format,
-                ..Default::default()
-            },
+            &mut resource_stats,
+            swapchain_desc.width,
+            swapchain_desc.height,
         );
+        let outline = OutlinePass::new(&device, &mut resource_stats, swapchain_desc.format);
 
-        State {
-            surface,
+        let mut state = State {
+            source,
+            device_lost,
+            adapter_info: adapter.get_info(),
+            adapter_limits: adapter.limits(),
             device,
             queue,
             swapchain_desc,
-            swapchain,
+            supported_present_modes,
+            supported_sample_counts,
+            target,
             window_size: size,
 
-            render_pipeline,
+            pipelines,
+            view_mode: ViewMode::Lit,
+            wireframe_overlay: false,
+            wireframe_supported,
             uniform_bind_group,
             uniform_buffer,
-            depth_texture,
+            light_buffer,
+            sample_count,
+            texture_pool: RefCell::new(TexturePool::default()),
+            profiler: RefCell::new(profiler),
+            resource_stats: RefCell::new(resource_stats),
+            draw_stats: DrawStats::default(),
+            tonemap,
+            post,
+            shadow,
+            scene_center,
 
             model,
+            model_data,
 
             uniforms,
+            light,
             camera,
             camera_controller,
 
             instances,
             instance_buffer,
+            sorted_instance_buffer,
+            shadow_instance_buffer,
+            visible_instance_count: instance_count,
+            lod_ranges: vec![0..instance_count],
+            lod_bias: 1.0,
+            culling_mode: CullingMode::Cpu,
+            gpu_culling,
+
+            debug_draw,
+            debug_overlays: DebugOverlays::default(),
+
+            picking,
+            cursor_position: (0, 0),
+            cursor_grabbed: false,
+            outline,
+
+            screenshot_supersampling: 1,
+            screenshot_requested: false,
+            recording_settings: RecordingSettings::default(),
+            recorder: None,
+        };
+        state.prepare_pipelines();
+        state.update_visible_instances();
+        state
+    }
+
+    pub fn set_sample_count(&mut self, sample_count: u32) {
+        if sample_count == self.sample_count
+            || !self.supported_sample_counts.contains(&sample_count)
+        {
+            return;
+        }
+
+        self.sample_count = sample_count;
+        self.prepare_pipelines();
+        self.debug_draw.set_sample_count(&self.device, sample_count);
+    }
+
+    fn scene_pipeline_key(&self) -> PipelineKey {
+        PipelineKey {
+            shader: self.view_mode.shader(),
+            polygon_mode: wgpu::PolygonMode::Fill,
+            cull_mode: wgpu::CullMode::Back,
+            blend: BlendMode::Opaque,
+            sample_count: self.sample_count,
+        }
+    }
 
-            imgui_renderer,
+    fn wireframe_pipeline_key(&self) -> PipelineKey {
+        PipelineKey {
+            shader: ShaderVariant::Wireframe,
+            polygon_mode: wgpu::PolygonMode::Line,
+            cull_mode: wgpu::CullMode::Back,
+            blend: BlendMode::Alpha,
+            sample_count: self.sample_count,
+        }
+    }
+
+    fn transparent_pipeline_key(&self) -> PipelineKey {
+        PipelineKey {
+            blend: BlendMode::Alpha,
+            ..self.scene_pipeline_key()
+        }
+    }
+
+    fn object_id_pipeline_key(&self) -> PipelineKey {
+        PipelineKey {
+            shader: ShaderVariant::ObjectId,
+            sample_count: 1,
+            ..self.scene_pipeline_key()
+        }
+    }
+
+    /// Makes sure the pipelines used by the current view settings exist.
+    fn prepare_pipelines(&mut self) {
+        let scene_key = self.scene_pipeline_key();
+        self.pipelines.prepare(&self.device, scene_key);
+        if self.model.has_transparent_meshes() {
+            let transparent_key = self.transparent_pipeline_key();
+            self.pipelines.prepare(&self.device, transparent_key);
+        }
+        if self.wireframe_overlay {
+            let wireframe_key = self.wireframe_pipeline_key();
+            self.pipelines.prepare(&self.device, wireframe_key);
+        }
+        if self.sample_count > 1 {
+            let object_id_key = self.object_id_pipeline_key();
+            self.pipelines.prepare(&self.device, object_id_key);
         }
     }
 
@@ -303,16 +685,175 @@ impl State {
         self.window_size = new_size;
         self.swapchain_desc.width = new_size.width;
         self.swapchain_desc.height = new_size.height;
-        self.swapchain = self
-            .device
-            .create_swap_chain(&self.surface, &self.swapchain_desc);
+        self.picking.resize(
+            &self.device,
+            self.resource_stats.get_mut(),
+            new_size.width,
+            new_size.height,
+        );
+        self.recreate_target();
+    }
+
+    /// Switches to `present_mode` if the surface supports it, recreating the swap chain.
+    pub fn set_present_mode(&mut self, present_mode: wgpu::PresentMode) {
+        if present_mode == self.swapchain_desc.present_mode
+            || !self.supported_present_modes.contains(&present_mode)
+        {
+            return;
+        }
+
+        self.swapchain_desc.present_mode = present_mode;
+        self.recreate_target();
+    }
+
+    /// Recreates the swap chain or offscreen texture after `swapchain_desc` changed.
+    fn recreate_target(&mut self) {
+        match &mut self.target {
+            RenderTarget::Window { swapchain, .. } => {
+                let surface = self
+                    .source
+                    .surface
+                    .as_ref()
+                    .expect("Window target without a surface");
+                *swapchain = self.device.create_swap_chain(surface, &self.swapchain_desc);
+            }
+            RenderTarget::Offscreen { color_texture } => {
+                *color_texture = Texture::create_render_target(
+                    &self.device,
+                    &self.swapchain_desc,
+                    self.swapchain_desc.format,
+                    "Offscreen color texture",
+                );
+            }
+        }
+    }
+
+    /// Whether the device was lost or ran out of memory, see [`State::recover`].
+    ///
+    /// Only errors wgpu reports to the uncaptured error handler are detected: out of
+    /// memory errors, and lost or invalid device errors returned when creating encoders,
+    /// buffers, textures and bind groups. wgpu 0.7 treats errors of `Queue::submit`,
+    /// `Queue::write_buffer`, `Device::poll` and `SwapChain::present` as fatal and
+    /// panics, so a loss first seen by one of those cannot be recovered from.
+    pub fn is_device_lost(&self) -> bool {
+        self.device_lost.load(Ordering::Relaxed)
+    }
+
+    /// Schedules a recovery, for errors reported outside of the device like swap chain
+    /// out of memory errors.
+    pub fn mark_device_lost(&self) {
+        self.device_lost.store(true, Ordering::Relaxed);
+    }
+
+    /// Recreates the device and every GPU resource, keeping the scene and the settings.
+    ///
+    /// Models are uploaded again from their retained [`ModelData`]. On failure the
+    /// state is left without a usable surface and should be dropped.
+    ///
+    /// `imgui_context` is needed to recreate the UI renderer of a window target.
+    pub async fn recover(&mut self, imgui_context: Option<&mut Context>) -> Result<(), String> {
+        // Frames still being read back belong to the lost device
+        if let Some(recorder) = self.recorder.take() {
+            match recorder.abort() {
+                Ok(path) => println!("Recording interrupted, saved to {}", path.display()),
+                Err(e) => eprintln!("Failed to save recording: {}", e),
+            }
+        }
 
-        self.depth_texture =
-            Texture::create_depth_texture(&self.device, &self.swapchain_desc, "Depth texture");
+        let source = DeviceSource {
+            instance: Rc::clone(&self.source.instance),
+            surface: self.source.surface.take(),
+            config: self.source.config.clone(),
+        };
+        let mut recovered = Self::create(
+            source,
+            self.swapchain_desc.clone(),
+            self.model_data.clone(),
+            imgui_context,
+        )
+        .await?;
+        recovered.restore_settings(self);
+        *self = recovered;
+        Ok(())
+    }
+
+    /// Takes over the CPU-side state of `lost`: camera, light, selection and settings.
+    fn restore_settings(&mut self, lost: &mut State) {
+        self.camera = lost.camera.clone();
+        std::mem::swap(&mut self.camera_controller, &mut lost.camera_controller);
+        self.uniforms.update_view_proj(&self.camera);
+        self.light = lost.light;
+
+        self.view_mode = lost.view_mode;
+        self.wireframe_overlay = lost.wireframe_overlay && self.wireframe_supported;
+        self.culling_mode = lost.culling_mode;
+        self.lod_bias = lost.lod_bias;
+        self.debug_overlays = lost.debug_overlays;
+        self.set_sample_count(lost.sample_count);
+
+        self.tonemap.operator = lost.tonemap.operator;
+        self.tonemap.exposure = lost.tonemap.exposure;
+        self.shadow.settings = lost.shadow.settings;
+        for (effect, lost_effect) in self.post.effects.iter_mut().zip(&lost.post.effects) {
+            effect.enabled = lost_effect.enabled;
+            effect.params = lost_effect.params;
+        }
+
+        self.profiler.get_mut().paused = lost.profiler.get_mut().paused;
+        self.picking.picked = lost.picking.picked;
+        self.cursor_position = lost.cursor_position;
+        self.cursor_grabbed = lost.cursor_grabbed;
+        self.outline.color = lost.outline.color;
+        self.outline.thickness = lost.outline.thickness;
+
+        self.screenshot_supersampling = lost.screenshot_supersampling;
+        self.recording_settings = lost.recording_settings;
+        self.prepare_pipelines();
+    }
+
+    /// Moves the camera to a fixed pose, overriding the controller orientation.
+    pub fn set_camera(&mut self, camera: Camera) {
+        self.camera_controller.look_along(camera.front);
+        self.camera = camera;
+        self.uniforms.update_view_proj(&self.camera);
+        self.queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[self.uniforms]),
+        );
+        self.update_visible_instances();
     }
 
     pub fn handle_window_event(&mut self, event: &WindowEvent) -> bool {
-        self.camera_controller.process_window_event(event)
+        match event {
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor_position = (position.x.max(0.0) as u32, position.y.max(0.0) as u32);
+                false
+            }
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Left,
+                ..
+            } => {
+                let (x, y) = self.pick_position();
+                self.picking.request(x, y);
+                true
+            }
+            _ => self.camera_controller.process_window_event(event),
+        }
+    }
+
+    /// Tells whether the window grabbed and hid the cursor, to pick at the crosshair.
+    pub fn set_cursor_grabbed(&mut self, grabbed: bool) {
+        self.cursor_grabbed = grabbed;
+    }
+
+    fn pick_position(&self) -> (u32, u32) {
+        if self.cursor_grabbed {
+            (self.window_size.width / 2, self.window_size.height / 2)
+        } else {
+            self.cursor_position
+        }
     }
 
     pub fn handle_device_event(&mut self, event: &DeviceEvent) -> bool {
@@ -320,6 +861,12 @@ impl State {
     }
 
     pub fn update(&mut self, dt: std::time::Duration) {
+        let profiler = self.profiler.get_mut();
+        profiler.begin_frame();
+        let scope = profiler.begin_scope("Update", None);
+
+        self.debug_draw.clear();
+        self.picking.poll(&self.device);
         self.camera_controller.update_camera(&mut self.camera, dt);
         self.uniforms.update_view_proj(&self.camera);
         self.queue.write_buffer(
@@ -327,10 +874,196 @@ impl State {
             0,
             bytemuck::cast_slice(&[self.uniforms]),
         );
+        self.queue
+            .write_buffer(&self.light_buffer, 0, bytemuck::cast_slice(&[self.light]));
+        self.update_visible_instances();
+        self.shadow.update(
+            &self.device,
+            &self.queue,
+            self.resource_stats.get_mut(),
+            &self.light,
+            self.scene_center,
+        );
+        self.tonemap.update(&self.queue);
+        self.post.update(&self.queue, &self.swapchain_desc);
+        self.outline.update(
+            &self.queue,
+            self.picking
+                .picked
+                .map(|picked| &self.instances[picked.instance]),
+            self.camera.build_view_projection_matrix(),
+            (self.swapchain_desc.width, self.swapchain_desc.height),
+        );
+        self.add_debug_overlays();
+
+        self.profiler.get_mut().end_scope(scope, None);
+    }
+
+    /// Lines drawn over the scene until the next update, see [`DebugDraw`].
+    pub fn debug_draw(&mut self) -> &mut DebugDraw {
+        &mut self.debug_draw
+    }
+
+    fn add_debug_overlays(&mut self) {
+        let overlays = self.debug_overlays;
+        let debug_draw = &mut self.debug_draw;
+
+        if overlays.grid {
+            debug_draw.grid(
+                cgmath::Point3::new(0.0, 0.0, 0.0),
+                40.0,
+                40,
+                [0.5, 0.5, 0.5, 0.5],
+                true,
+            );
+        }
+        if overlays.axes {
+            debug_draw.axes(cgmath::Point3::new(0.0, 0.0, 0.0), 2.0, false);
+        }
+        if overlays.bounds {
+            let corners = self.model.bounds.corners();
+            for instance in &self.instances {
+                let world_bounds = Aabb::from_points(
+                    corners
+                        .iter()
+                        .map(|&corner| instance.transform_point(corner)),
+                );
+                debug_draw.aabb(&world_bounds, [1.0, 1.0, 0.0, 1.0], true);
+            }
+        }
+        if overlays.light {
+            let position = cgmath::Point3::from(self.light.position);
+            let color = [
+                self.light.color[0],
+                self.light.color[1],
+                self.light.color[2],
+                1.0,
+            ];
+            debug_draw.sphere(position, 0.25, color, false);
+            debug_draw.arrow(position, self.scene_center, color, false);
+        }
+        if let Some(view_proj) = overlays.frozen_frustum {
+            debug_draw.frustum(view_proj, [1.0, 0.0, 1.0, 1.0], true);
+        }
+    }
+
+    /// Compacts the instances inside the view frustum at the start of the instance buffer,
+    /// grouped by the level of detail matching their size on screen.
+    ///
+    /// The transparent pass gets a copy ordered by decreasing distance to the eye. With GPU
+    /// culling, only transparent instances still need to be culled and sorted here.
+    fn update_visible_instances(&mut self) {
+        let frustum = Frustum::from_matrix(self.camera.build_view_projection_matrix());
+        if self.culling_mode == CullingMode::Gpu {
+            self.gpu_culling.update(
+                &self.queue,
+                &frustum,
+                &self.model,
+                &self.camera,
+                self.lod_bias,
+            );
+            if !self.model.has_transparent_meshes() {
+                return;
+            }
+        }
+
+        let center = self.model.bounds.center();
+        let radius = self.model.bounds.radius();
+        let frustum_culling = self.culling_mode != CullingMode::Disabled;
+
+        let mut visible: Vec<(u32, &Instance)> = self
+            .instances
+            .iter()
+            .enumerate()
+            .map(|(id, instance)| (id as u32, instance))
+            .filter(|(_, instance)| {
+                !frustum_culling
+                    || frustum.intersects_sphere(instance.transform_point(center), radius)
+            })
+            .collect();
+        self.visible_instance_count = visible.len() as u32;
+        if visible.is_empty() {
+            self.lod_ranges.clear();
+            return;
+        }
+
+        let eye = self.camera.eye;
+        let fovy = cgmath::Deg(self.camera.fovy);
+        let lod_bias = self.lod_bias;
+        let lod_count = self.model.lod_count();
+        let mut by_lod: Vec<(usize, u32, &Instance)> = visible
+            .iter()
+            .map(|&(id, instance)| {
+                let distance = instance.transform_point(center).distance(eye);
+                let screen_size = lod::screen_size(radius, distance, fovy);
+                (
+                    lod::select_lod(screen_size, lod_bias, lod_count),
+                    id,
+                    instance,
+                )
+            })
+            .collect();
+        by_lod.sort_by_key(|&(lod, _, _)| lod);
+
+        let mut lod_ranges = vec![0..0; lod_count];
+        for (index, &(lod, _, _)) in by_lod.iter().enumerate() {
+            if lod_ranges[lod].is_empty() {
+                lod_ranges[lod].start = index as u32;
+            }
+            lod_ranges[lod].end = index as u32 + 1;
+        }
+        self.lod_ranges = lod_ranges;
+
+        let raw_instances: Vec<_> = by_lod
+            .iter()
+            .map(|(_, id, instance)| instance.to_raw(*id))
+            .collect();
+        self.queue.write_buffer(
+            &self.instance_buffer,
+            0,
+            bytemuck::cast_slice(&raw_instances),
+        );
+
+        if self.model.has_transparent_meshes() {
+            let eye = self.camera.eye.to_vec();
+            visible.sort_by(|(_, a), (_, b)| {
+                let distance_a = (a.position - eye).magnitude2();
+                let distance_b = (b.position - eye).magnitude2();
+                distance_b
+                    .partial_cmp(&distance_a)
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            });
+
+            let raw_instances: Vec<_> = visible
+                .iter()
+                .map(|(id, instance)| instance.to_raw(*id))
+                .collect();
+            self.queue.write_buffer(
+                &self.sorted_instance_buffer,
+                0,
+                bytemuck::cast_slice(&raw_instances),
+            );
+        }
     }
 
     pub fn render(&mut self, imgui_ui: imgui::Ui) -> Result<(), wgpu::SwapChainError> {
-        let frame = self.swapchain.get_current_frame()?.output;
+        self.upload_debug_lines();
+        if self.screenshot_requested {
+            self.screenshot_requested = false;
+            self.save_screenshot();
+        }
+        self.record_frame();
+
+        let (frame, imgui_renderer) = match &self.target {
+            RenderTarget::Window {
+                swapchain,
+                imgui_renderer,
+            } => (swapchain.get_current_frame()?.output, imgui_renderer),
+            RenderTarget::Offscreen { .. } => {
+                self.render_offscreen();
+                return Ok(());
+            }
+        };
 
         let mut encoder = self
             .device
@@ -338,71 +1071,694 @@ impl State {
                 label: Some("Render encoder"),
             });
 
-        {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Main render pass"),
+        let mut graph = RenderGraph::new();
+        let output = graph.import_texture(&frame.view);
+        let object_ids = graph.import_texture(self.picking.object_id_view());
+        self.add_scene_passes(&mut graph, output, object_ids);
+
+        let (queue, device) = (&self.queue, &self.device);
+        graph.add_pass("Imgui pass", &[], &[output], move |encoder, resources| {
+            let mut imgui_renderer = imgui_renderer.borrow_mut();
+            let mut imgui_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Imgui render pass"),
                 color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
-                    attachment: &frame.view,
+                    attachment: resources.view(output),
                     resolve_target: None,
                     ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.1,
-                            g: 0.2,
-                            b: 0.3,
-                            a: 1.0,
-                        }),
+                        load: wgpu::LoadOp::Load,
                         store: true,
                     },
                 }],
-                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachmentDescriptor {
-                    attachment: &self.depth_texture.view,
-                    depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
-                        store: true,
-                    }),
-                    stencil_ops: None,
-                }),
+                depth_stencil_attachment: None,
             });
+            imgui_renderer
+                .render(imgui_ui.render(), queue, device, &mut imgui_pass)
+                .expect("Failed to render UI!");
+        });
 
-            render_pass.set_pipeline(&self.render_pipeline);
+        self.execute_graph(graph, &mut encoder);
+        self.picking.encode_readback(&mut encoder);
+        self.profiler.get_mut().resolve(&mut encoder);
+        self.queue.submit(Some(encoder.finish()));
+        self.picking.map_readback();
+        self.profiler.get_mut().end_frame(&self.device);
+        self.draw_stats.end_frame();
 
-            render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
-            render_pass.draw_model_instanced(
+        Ok(())
+    }
+
+    /// Renders the scene into the offscreen color texture, without any UI.
+    pub fn render_offscreen(&mut self) {
+        self.upload_debug_lines();
+
+        let color_texture = match &self.target {
+            RenderTarget::Offscreen { color_texture } => color_texture,
+            RenderTarget::Window { .. } => panic!("State does not have an offscreen target"),
+        };
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Offscreen render encoder"),
+            });
+
+        let mut graph = RenderGraph::new();
+        let output = graph.import_texture(&color_texture.view);
+        let object_ids = graph.import_texture(self.picking.object_id_view());
+        self.add_scene_passes(&mut graph, output, object_ids);
+
+        self.execute_graph(graph, &mut encoder);
+        self.profiler.get_mut().resolve(&mut encoder);
+        self.queue.submit(Some(encoder.finish()));
+        self.profiler.get_mut().end_frame(&self.device);
+        self.draw_stats.end_frame();
+    }
+
+    fn upload_debug_lines(&mut self) {
+        self.debug_draw.upload(
+            &self.device,
+            &self.queue,
+            self.camera.build_view_projection_matrix(),
+        );
+    }
+
+    /// Reads the offscreen color texture back to CPU memory.
+    ///
+    /// Returns `None` when rendering to a window, as swap chain frames cannot be copied.
+    pub fn read_frame(&self) -> Option<image::RgbaImage> {
+        let color_texture = match &self.target {
+            RenderTarget::Offscreen { color_texture } => color_texture,
+            RenderTarget::Window { .. } => return None,
+        };
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Readback encoder"),
+            });
+
+        let readback = Readback::new(
+            &self.device,
+            &mut encoder,
+            &color_texture.texture,
+            self.swapchain_desc.format,
+            self.swapchain_desc.width,
+            self.swapchain_desc.height,
+        );
+
+        self.queue.submit(Some(encoder.finish()));
+
+        Some(readback.into_image(&self.device))
+    }
+
+    /// Saves the next rendered frame, without the UI, see [`State::save_screenshot`].
+    pub fn request_screenshot(&mut self) {
+        self.screenshot_requested = true;
+    }
+
+    /// Renders the scene at the screenshot resolution and writes it to a timestamped PNG.
+    pub fn save_screenshot(&mut self) {
+        let image = self.capture(self.screenshot_supersampling);
+        let path = screenshot::timestamped_path();
+        let result = path
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .map_err(image::ImageError::IoError)
+            .and_then(|_| image.save(&path));
+        match result {
+            Ok(()) => println!("Screenshot saved to {}", path.display()),
+            Err(e) => eprintln!("Failed to save screenshot {}: {}", path.display(), e),
+        }
+    }
+
+    /// Renders the scene to a new texture `factor` times larger than the frame and reads
+    /// it back. Swap chain frames cannot be copied, so this works with any target.
+    pub fn capture(&mut self, factor: u32) -> image::RgbaImage {
+        let (width, height) = screenshot::capture_size(
+            self.swapchain_desc.width,
+            self.swapchain_desc.height,
+            factor,
+        );
+        let target = CaptureTarget::new(
+            &self.device,
+            self.resource_stats.get_mut(),
+            self.swapchain_desc.format,
+            width,
+            height,
+        );
+        let mut pool = TexturePool::default();
+        self.encode_capture(&target, &mut pool, (width, height));
+
+        let stats = self.resource_stats.get_mut();
+        pool.clear(stats);
+        target.release(stats);
+        target.readback.into_image(&self.device)
+    }
+
+    /// Renders the scene to the `width` x `height` color texture of `target` and submits
+    /// its readback.
+    ///
+    /// `pool` provides the other targets, it is separate from the pool of the window
+    /// sized textures so they stay alive.
+    fn encode_capture(
+        &mut self,
+        target: &CaptureTarget,
+        pool: &mut TexturePool,
+        (width, height): (u32, u32),
+    ) {
+        let capture_desc = wgpu::SwapChainDescriptor {
+            width,
+            height,
+            ..self.swapchain_desc.clone()
+        };
+        self.post.update(&self.queue, &capture_desc);
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Capture encoder"),
+            });
+
+        let mut graph = RenderGraph::new();
+        let output = graph.import_texture(&target.color.view);
+        // The picking texture has the size of the window
+        let object_ids = graph.create_texture(TransientTexture {
+            label: "Capture object IDs",
+            format: Texture::OBJECT_ID_FORMAT,
+            sample_count: 1,
+            usage: wgpu::TextureUsage::RENDER_ATTACHMENT | wgpu::TextureUsage::SAMPLED,
+        });
+        self.add_scene_passes(&mut graph, output, object_ids);
+        graph.execute(
+            &self.device,
+            pool,
+            &mut self.resource_stats.borrow_mut(),
+            &mut encoder,
+            (width, height),
+            None,
+        );
+
+        target.readback.copy(&mut encoder, &target.color.texture);
+        self.queue.submit(Some(encoder.finish()));
+
+        // Applied before the next frame is submitted
+        self.post.update(&self.queue, &self.swapchain_desc);
+    }
+
+    /// Starts recording frames with the current [`RecordingSettings`], at the window size.
+    pub fn start_recording(&mut self) {
+        self.stop_recording();
+        match Recorder::start(
+            &self.recording_settings,
+            &self.device,
+            self.resource_stats.get_mut(),
+            self.swapchain_desc.format,
+            self.swapchain_desc.width,
+            self.swapchain_desc.height,
+        ) {
+            Ok(recorder) => {
+                println!("Recording to {}", recorder.path().display());
+                self.recorder = Some(recorder);
+            }
+            Err(e) => eprintln!("Failed to start recording: {}", e),
+        }
+    }
+
+    /// Writes the frames recorded so far and ends the recording, if any.
+    pub fn stop_recording(&mut self) {
+        if let Some(recorder) = self.recorder.take() {
+            match recorder.finish(&self.device, self.resource_stats.get_mut()) {
+                Ok(path) => println!("Recording saved to {}", path.display()),
+                Err(e) => eprintln!("Failed to save recording: {}", e),
+            }
+        }
+    }
+
+    /// The simulated time step to pass to [`State::update`] while recording.
+    ///
+    /// A fixed step makes the recording play back smoothly, whatever the frame rate.
+    pub fn fixed_dt(&self) -> Option<std::time::Duration> {
+        self.recorder.as_ref().map(Recorder::dt)
+    }
+
+    fn record_frame(&mut self) {
+        // Taken out while capturing, as the frame is drawn to its targets
+        let mut recorder = match self.recorder.take() {
+            Some(recorder) => recorder,
+            None => return,
+        };
+
+        // Recorded at the size it started with, even if the window was resized since
+        let size = recorder.size();
+        let (target, pool) = recorder.next_target(&self.device);
+        self.encode_capture(target, pool, size);
+        recorder.push(&self.device);
+
+        let capturing = recorder.is_capturing();
+        self.recorder = Some(recorder);
+        if !capturing {
+            self.stop_recording();
+        }
+    }
+
+    fn execute_graph(&self, graph: RenderGraph, encoder: &mut wgpu::CommandEncoder) {
+        graph.execute(
+            &self.device,
+            &mut self.texture_pool.borrow_mut(),
+            &mut self.resource_stats.borrow_mut(),
+            encoder,
+            (self.swapchain_desc.width, self.swapchain_desc.height),
+            Some(&mut *self.profiler.borrow_mut()),
+        );
+    }
+
+    /// Declares the passes drawing the scene, from the shadow map to `output`.
+    ///
+    /// `object_ids` receives the single sampled object IDs and must be the size of `output`.
+    fn add_scene_passes<'a>(
+        &'a self,
+        graph: &mut RenderGraph<'a>,
+        output: TextureHandle,
+        object_ids: TextureHandle,
+    ) {
+        let shadow_map = graph.import_texture(self.shadow.shadow_map_view());
+        graph.add_pass("Shadow pass", &[], &[shadow_map], move |encoder, _| {
+            self.shadow.encode(
+                encoder,
                 &self.model,
-                &self.uniform_bind_group,
-                0..self.instances.len() as _,
+                &self.shadow_instance_buffer,
+                self.instances.len() as _,
             );
+            self.count_draws(MeshFilter::All, 0, self.instances.len() as _);
+        });
+
+        let hdr = graph.create_texture(TransientTexture {
+            label: "HDR texture",
+            format: Texture::HDR_FORMAT,
+            sample_count: 1,
+            usage: wgpu::TextureUsage::RENDER_ATTACHMENT | wgpu::TextureUsage::SAMPLED,
+        });
+        let depth = graph.create_texture(TransientTexture {
+            label: "Depth texture",
+            format: Texture::DEPTH_FORMAT,
+            sample_count: self.sample_count,
+            usage: wgpu::TextureUsage::RENDER_ATTACHMENT,
+        });
+        let multisampled = if self.sample_count > 1 {
+            Some(graph.create_texture(TransientTexture {
+                label: "Multisampled framebuffer",
+                format: Texture::HDR_FORMAT,
+                sample_count: self.sample_count,
+                usage: wgpu::TextureUsage::RENDER_ATTACHMENT,
+            }))
+        } else {
+            None
+        };
+
+        // Object IDs are only written by the main pass when it is single sampled
+        let main_object_ids = if multisampled.is_none() {
+            Some(object_ids)
+        } else {
+            None
+        };
+
+        let mut main_writes = vec![hdr, depth];
+        main_writes.extend(multisampled);
+        main_writes.extend(main_object_ids);
+
+        // Fills the instance and indirect buffers drawn by the scene passes
+        let culling_pass = if self.culling_mode == CullingMode::Gpu {
+            Some(
+                graph.add_pass("GPU culling pass", &[], &[], move |encoder, _| {
+                    self.gpu_culling.encode(encoder);
+                }),
+            )
+        } else {
+            None
+        };
+
+        let main_pass = graph.add_pass(
+            "Main pass",
+            &[shadow_map],
+            &main_writes,
+            move |encoder, resources| {
+                let (attachment, resolve_target) = match multisampled {
+                    Some(framebuffer) => (resources.view(framebuffer), Some(resources.view(hdr))),
+                    None => (resources.view(hdr), None),
+                };
+                self.encode_main_pass(
+                    encoder,
+                    attachment,
+                    resolve_target,
+                    main_object_ids.map(|ids| resources.view(ids)),
+                    resources.view(depth),
+                );
+            },
+        );
+        if let Some(culling_pass) = culling_pass {
+            graph.add_dependency(main_pass, culling_pass);
         }
 
-        {
-            let mut imgui_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Imgui render pass"),
-                color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
-                    attachment: &frame.view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Load,
-                        store: true,
-                    },
-                }],
-                depth_stencil_attachment: None,
+        if self.model.has_transparent_meshes() {
+            graph.add_pass(
+                "Transparent pass",
+                &[shadow_map],
+                &main_writes,
+                move |encoder, resources| {
+                    let (attachment, resolve_target) = match multisampled {
+                        Some(framebuffer) => {
+                            (resources.view(framebuffer), Some(resources.view(hdr)))
+                        }
+                        None => (resources.view(hdr), None),
+                    };
+                    self.encode_transparent_pass(
+                        encoder,
+                        attachment,
+                        resolve_target,
+                        main_object_ids.map(|ids| resources.view(ids)),
+                        resources.view(depth),
+                    );
+                },
+            );
+        }
+
+        if multisampled.is_some() {
+            let object_id_depth = graph.create_texture(TransientTexture {
+                label: "Object ID depth texture",
+                format: Texture::DEPTH_FORMAT,
+                sample_count: 1,
+                usage: wgpu::TextureUsage::RENDER_ATTACHMENT,
             });
-            self.imgui_renderer
-                .render(
-                    imgui_ui.render(),
-                    &self.queue,
+            let object_id_pass = graph.add_pass(
+                "Object ID pass",
+                &[],
+                &[object_ids, object_id_depth],
+                move |encoder, resources| {
+                    self.encode_object_id_pass(
+                        encoder,
+                        resources.view(object_ids),
+                        resources.view(object_id_depth),
+                    );
+                },
+            );
+            if let Some(culling_pass) = culling_pass {
+                graph.add_dependency(object_id_pass, culling_pass);
+            }
+        }
+
+        if !self.debug_draw.is_empty() {
+            graph.add_pass(
+                "Debug line pass",
+                &[],
+                &main_writes,
+                move |encoder, resources| {
+                    let (attachment, resolve_target) = match multisampled {
+                        Some(framebuffer) => {
+                            (resources.view(framebuffer), Some(resources.view(hdr)))
+                        }
+                        None => (resources.view(hdr), None),
+                    };
+                    self.debug_draw.encode(
+                        encoder,
+                        attachment,
+                        resolve_target,
+                        resources.view(depth),
+                    );
+                },
+            );
+        }
+
+        // Bloom needs the colors above 1, so it runs before tonemapping
+        let hdr = self.post.add_hdr_passes(graph, &self.device, hdr);
+        let post_input = self.post.create_input(graph);
+        let tonemap_output = post_input.unwrap_or(output);
+        graph.add_pass(
+            "Tonemap pass",
+            &[hdr],
+            &[tonemap_output],
+            move |encoder, resources| {
+                self.tonemap.encode(
                     &self.device,
-                    &mut imgui_pass,
-                )
-                .expect("Failed to render UI!");
+                    encoder,
+                    resources.pool_texture(hdr),
+                    resources.view(tonemap_output),
+                );
+            },
+        );
+
+        if let Some(input) = post_input {
+            self.post.add_passes(graph, &self.device, input, output);
         }
 
-        self.queue.submit(Some(encoder.finish()));
+        if self.picking.picked.is_some() {
+            let stencil = graph.create_texture(TransientTexture {
+                label: "Outline stencil texture",
+                format: Texture::DEPTH_STENCIL_FORMAT,
+                sample_count: 1,
+                usage: wgpu::TextureUsage::RENDER_ATTACHMENT,
+            });
+            graph.add_pass(
+                "Outline pass",
+                &[],
+                &[output, stencil],
+                move |encoder, resources| {
+                    self.outline.encode(
+                        encoder,
+                        &self.model,
+                        resources.view(output),
+                        resources.view(stencil),
+                    );
+                },
+            );
+        }
+    }
 
-        Ok(())
+    fn encode_main_pass(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        attachment: &wgpu::TextureView,
+        resolve_target: Option<&wgpu::TextureView>,
+        object_ids: Option<&wgpu::TextureView>,
+        depth: &wgpu::TextureView,
+    ) {
+        let mut color_attachments = vec![wgpu::RenderPassColorAttachmentDescriptor {
+            attachment,
+            resolve_target,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Clear(wgpu::Color {
+                    r: 0.1,
+                    g: 0.2,
+                    b: 0.3,
+                    a: 1.0,
+                }),
+                store: true,
+            },
+        }];
+        color_attachments.extend(object_ids.map(|attachment| {
+            wgpu::RenderPassColorAttachmentDescriptor {
+                attachment,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: true,
+                },
+            }
+        }));
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Main render pass"),
+            color_attachments: &color_attachments,
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachmentDescriptor {
+                attachment: depth,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+
+        render_pass.set_pipeline(self.pipelines.get(self.scene_pipeline_key()));
+        render_pass.set_bind_group(2, self.shadow.sample_bind_group(), &[]);
+
+        if self.culling_mode == CullingMode::Gpu {
+            self.draw_culled_lods(&mut render_pass, MeshFilter::Opaque);
+
+            if self.wireframe_overlay {
+                render_pass.set_pipeline(self.pipelines.get(self.wireframe_pipeline_key()));
+                self.draw_culled_lods(&mut render_pass, MeshFilter::All);
+            }
+        } else {
+            render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+            self.draw_lods(&mut render_pass, MeshFilter::Opaque);
+
+            if self.wireframe_overlay {
+                render_pass.set_pipeline(self.pipelines.get(self.wireframe_pipeline_key()));
+                self.draw_lods(&mut render_pass, MeshFilter::All);
+            }
+        }
+    }
+
+    /// Renders the IDs of the closest surfaces single sampled, for multisampled frames.
+    ///
+    /// Transparent meshes write depth here, so the closest of them is picked.
+    fn encode_object_id_pass(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        object_ids: &wgpu::TextureView,
+        depth: &wgpu::TextureView,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Object ID render pass"),
+            color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
+                attachment: object_ids,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachmentDescriptor {
+                attachment: depth,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+
+        render_pass.set_pipeline(self.pipelines.get(self.object_id_pipeline_key()));
+        render_pass.set_bind_group(2, self.shadow.sample_bind_group(), &[]);
+
+        if self.culling_mode == CullingMode::Gpu {
+            // Reuses the draws culled for the main pass
+            self.draw_culled_lods(&mut render_pass, MeshFilter::All);
+        } else {
+            render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+            self.draw_lods(&mut render_pass, MeshFilter::All);
+        }
+    }
+
+    /// Draws each group of instances of the instance buffer with its level of detail.
+    fn draw_lods<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, filter: MeshFilter) {
+        for (lod, instances) in self.lod_ranges.iter().enumerate() {
+            if !instances.is_empty() {
+                render_pass.draw_model_lod_instanced(
+                    &self.model,
+                    &self.uniform_bind_group,
+                    lod,
+                    instances.clone(),
+                    filter,
+                );
+                self.count_draws(filter, lod, instances.len() as _);
+            }
+        }
+    }
+
+    /// Draws every level of detail with the instances and arguments written by GPU culling.
+    fn draw_culled_lods<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, filter: MeshFilter) {
+        for lod in 0..self.gpu_culling.lod_count() {
+            render_pass.set_vertex_buffer(1, self.gpu_culling.visible_instances(lod));
+            render_pass.draw_model_lod_indirect(
+                &self.model,
+                &self.uniform_bind_group,
+                self.gpu_culling.draw_buffer(),
+                lod,
+                filter,
+            );
+            self.count_indirect_draws(filter);
+        }
+    }
+
+    /// Blends transparent meshes over the opaque scene, without writing depth.
+    fn encode_transparent_pass(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        attachment: &wgpu::TextureView,
+        resolve_target: Option<&wgpu::TextureView>,
+        object_ids: Option<&wgpu::TextureView>,
+        depth: &wgpu::TextureView,
+    ) {
+        let load = wgpu::Operations {
+            load: wgpu::LoadOp::Load,
+            store: true,
+        };
+        let mut color_attachments = vec![wgpu::RenderPassColorAttachmentDescriptor {
+            attachment,
+            resolve_target,
+            ops: load,
+        }];
+        color_attachments.extend(object_ids.map(|attachment| {
+            wgpu::RenderPassColorAttachmentDescriptor {
+                attachment,
+                resolve_target: None,
+                ops: load,
+            }
+        }));
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Transparent render pass"),
+            color_attachments: &color_attachments,
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachmentDescriptor {
+                attachment: depth,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+
+        render_pass.set_pipeline(self.pipelines.get(self.transparent_pipeline_key()));
+        render_pass.set_bind_group(2, self.shadow.sample_bind_group(), &[]);
+
+        // Instances are ordered by distance and not by level of detail, so every
+        // transparent mesh is drawn at full detail
+        render_pass.set_vertex_buffer(1, self.sorted_instance_buffer.slice(..));
+        render_pass.draw_model_lod_instanced(
+            &self.model,
+            &self.uniform_bind_group,
+            0,
+            0..self.visible_instance_count,
+            MeshFilter::Transparent,
+        );
+        self.count_draws(MeshFilter::Transparent, 0, self.visible_instance_count);
+    }
+
+    /// Adds the draws of the model to the frame statistics.
+    fn count_draws(&self, filter: MeshFilter, lod: usize, instance_count: u32) {
+        let (draw_calls, triangles) = self.model.draw_stats(filter, lod, instance_count);
+        self.draw_stats.record(draw_calls, triangles);
+    }
+
+    /// Adds indirect draws, whose instance counts are only known on the GPU.
+    fn count_indirect_draws(&self, filter: MeshFilter) {
+        let (draw_calls, _) = self.model.draw_stats(filter, 0, 0);
+        self.draw_stats.record_indirect(draw_calls);
+    }
+
+    /// Marks the window center, where clicks pick while the cursor is grabbed.
+    fn draw_crosshair(ui: &imgui::Ui) {
+        const HALF_SIZE: f32 = 8.0;
+        const COLOR: [f32; 4] = [1.0, 1.0, 1.0, 0.8];
+
+        let [width, height] = ui.io().display_size;
+        let (x, y) = (width / 2.0, height / 2.0);
+        let draw_list = ui.get_foreground_draw_list();
+        draw_list
+            .add_line([x - HALF_SIZE, y], [x + HALF_SIZE, y], COLOR)
+            .build();
+        draw_list
+            .add_line([x, y - HALF_SIZE], [x, y + HALF_SIZE], COLOR)
+            .build();
     }
 
-    pub fn build_ui(&self, ui: &imgui::Ui, framerate: f32) {
+    pub fn build_ui(&mut self, ui: &imgui::Ui, framerate: f32) {
+        if self.cursor_grabbed {
+            Self::draw_crosshair(ui);
+        }
+
         let window = imgui::Window::new(im_str!("Camera"));
         window
             .size([150.0, 250.0], Condition::FirstUseEver)
@@ -421,22 +1777,438 @@ impl State {
                 ui.separator();
                 ui.text(im_str!("FPS: {}", framerate));
             });
+
+        let light = &mut self.light;
+        let light_window = imgui::Window::new(im_str!("Light"));
+        light_window
+            .size([250.0, 150.0], Condition::FirstUseEver)
+            .position([0.0, 250.0], Condition::FirstUseEver)
+            .build(&ui, || {
+                Drag::new(im_str!("Position"))
+                    .speed(0.1)
+                    .build_array(&ui, &mut light.position);
+                ColorEdit::new(im_str!("Color"), &mut light.color).build(&ui);
+                Slider::new(im_str!("Ambient"))
+                    .range(0.0..=1.0)
+                    .build(&ui, &mut light.ambient_strength);
+                Slider::new(im_str!("Specular"))
+                    .range(0.0..=1.0)
+                    .build(&ui, &mut light.specular_strength);
+            });
+
+        self.shadow.build_ui(ui);
+        self.profiler.get_mut().build_ui(ui);
+        self.resource_stats.borrow().build_ui(ui, &self.draw_stats);
+
+        let info = &self.adapter_info;
+        let limits = &self.adapter_limits;
+        let device_lost = &self.device_lost;
+        let adapter_window = imgui::Window::new(im_str!("Adapter"));
+        adapter_window
+            .size([250.0, 240.0], Condition::FirstUseEver)
+            .position([0.0, 410.0], Condition::FirstUseEver)
+            .collapsed(true, Condition::FirstUseEver)
+            .build(&ui, || {
+                ui.text_wrapped(&im_str!("{}", info.name));
+                ui.text(im_str!("Backend: {:?}", info.backend));
+                ui.text(im_str!("Type: {:?}", info.device_type));
+                ui.text(im_str!(
+                    "Vendor: {:#06x}, device: {:#06x}",
+                    info.vendor,
+                    info.device
+                ));
+                ui.separator();
+                ui.text(im_str!("Limits:"));
+                ui.text(im_str!("Bind groups: {}", limits.max_bind_groups));
+                ui.text(im_str!(
+                    "Sampled textures: {}",
+                    limits.max_sampled_textures_per_shader_stage
+                ));
+                ui.text(im_str!(
+                    "Samplers: {}",
+                    limits.max_samplers_per_shader_stage
+                ));
+                ui.text(im_str!(
+                    "Uniform buffers: {}",
+                    limits.max_uniform_buffers_per_shader_stage
+                ));
+                ui.text(im_str!(
+                    "Storage buffers: {}",
+                    limits.max_storage_buffers_per_shader_stage
+                ));
+                ui.text(im_str!(
+                    "Storage textures: {}",
+                    limits.max_storage_textures_per_shader_stage
+                ));
+                ui.text(im_str!(
+                    "Uniform binding size: {}",
+                    limits.max_uniform_buffer_binding_size
+                ));
+                ui.text(im_str!(
+                    "Push constants: {} bytes",
+                    limits.max_push_constant_size
+                ));
+                ui.separator();
+                // Goes through the same recovery as a real loss, on the next frame
+                if ui.button(im_str!("Simulate device loss"), [0.0, 0.0]) {
+                    device_lost.store(true, Ordering::Relaxed);
+                }
+            });
+
+        let mut sample_count = self.sample_count;
+        let mut present_mode = self.swapchain_desc.present_mode;
+        let supported_present_modes = &self.supported_present_modes;
+        let supported_sample_counts = &self.supported_sample_counts;
+        let view_mode = &mut self.view_mode;
+        let wireframe_overlay = &mut self.wireframe_overlay;
+        let wireframe_supported = self.wireframe_supported;
+        let culling_mode = &mut self.culling_mode;
+        let lod_bias = &mut self.lod_bias;
+        let lod_ranges = &self.lod_ranges;
+        let instance_count = self.instances.len() as u32;
+        let culled_count = instance_count - self.visible_instance_count;
+        let rendering_window = imgui::Window::new(im_str!("Rendering"));
+        rendering_window
+            .size([250.0, 300.0], Condition::FirstUseEver)
+            .position([250.0, 0.0], Condition::FirstUseEver)
+            .build(&ui, || {
+                ui.text(im_str!("MSAA samples:"));
+                for &count in supported_sample_counts {
+                    ui.same_line(0.0);
+                    ui.radio_button(&im_str!("{}x", count), &mut sample_count, count);
+                }
+
+                ui.text(im_str!("Present mode:"));
+                for &mode in supported_present_modes {
+                    ui.radio_button(
+                        &im_str!("{}", present_mode::label(mode)),
+                        &mut present_mode,
+                        mode,
+                    );
+                }
+
+                let mut mode_index = ViewMode::ALL
+                    .iter()
+                    .position(|&mode| mode == *view_mode)
+                    .unwrap_or(0);
+                if ComboBox::new(im_str!("View")).build_simple_string(
+                    &ui,
+                    &mut mode_index,
+                    &[
+                        im_str!("Lit"),
+                        im_str!("Unlit albedo"),
+                        im_str!("Normals"),
+                        im_str!("UVs"),
+                        im_str!("Linear depth"),
+                    ],
+                ) {
+                    *view_mode = ViewMode::ALL[mode_index];
+                }
+
+                if wireframe_supported {
+                    ui.checkbox(im_str!("Wireframe overlay"), wireframe_overlay);
+                } else {
+                    ui.text_disabled(im_str!("Wireframe not supported"));
+                }
+
+                ui.separator();
+                ui.text(im_str!("Culling:"));
+                ui.same_line(0.0);
+                ui.radio_button(im_str!("Off"), culling_mode, CullingMode::Disabled);
+                ui.same_line(0.0);
+                ui.radio_button(im_str!("CPU"), culling_mode, CullingMode::Cpu);
+                ui.same_line(0.0);
+                ui.radio_button(im_str!("GPU"), culling_mode, CullingMode::Gpu);
+                if *culling_mode == CullingMode::Gpu {
+                    ui.text_disabled(im_str!("Culled instances stay on the GPU"));
+                } else {
+                    ui.text(im_str!(
+                        "Culled instances: {} / {}",
+                        culled_count,
+                        instance_count
+                    ));
+                }
+
+                ui.separator();
+                Slider::new(im_str!("LOD bias"))
+                    .range(0.25..=4.0)
+                    .build(&ui, lod_bias);
+                if *culling_mode == CullingMode::Gpu {
+                    ui.text_disabled(im_str!("Levels are selected on the GPU"));
+                } else {
+                    for (lod, instances) in lod_ranges.iter().enumerate() {
+                        ui.text(im_str!("LOD {}: {} instances", lod, instances.len()));
+                    }
+                }
+            });
+        self.set_sample_count(sample_count);
+        self.set_present_mode(present_mode);
+        self.prepare_pipelines();
+
+        let instances = &self.instances;
+        let meshes = &self.model.meshes;
+        let picked = self
+            .picking
+            .picked
+            .map(|picked| (picked, &instances[picked.instance], &meshes[picked.mesh]));
+        let outline = &mut self.outline;
+        let picking_window = imgui::Window::new(im_str!("Picking"));
+        picking_window
+            .size([250.0, 180.0], Condition::FirstUseEver)
+            .position([750.0, 170.0], Condition::FirstUseEver)
+            .build(&ui, || match picked {
+                Some((picked, instance, mesh)) => {
+                    let euler = cgmath::Euler::from(instance.rotation);
+                    ui.text(im_str!("Instance {}", picked.instance));
+                    ui.text(im_str!("Mesh {} ({})", picked.mesh, mesh.name));
+                    ui.text(im_str!(
+                        "Position: ({:.2}, {:.2}, {:.2})",
+                        instance.position.x,
+                        instance.position.y,
+                        instance.position.z
+                    ));
+                    ui.text(im_str!(
+                        "Rotation: ({:.1}, {:.1}, {:.1}) deg",
+                        cgmath::Deg::from(euler.x).0,
+                        cgmath::Deg::from(euler.y).0,
+                        cgmath::Deg::from(euler.z).0
+                    ));
+                    ui.separator();
+                    outline.build_ui(ui);
+                }
+                None => ui.text_disabled(im_str!("Click an instance to pick it")),
+            });
+
+        let mut screenshot_requested = false;
+        let supersampling = &mut self.screenshot_supersampling;
+        let screenshot_window = imgui::Window::new(im_str!("Screenshot"));
+        screenshot_window
+            .size([250.0, 80.0], Condition::FirstUseEver)
+            .position([750.0, 350.0], Condition::FirstUseEver)
+            .build(&ui, || {
+                ui.text(im_str!("Supersampling:"));
+                for &factor in screenshot::SUPERSAMPLING_FACTORS.iter() {
+                    ui.same_line(0.0);
+                    ui.radio_button(&im_str!("{}x", factor), supersampling, factor);
+                }
+                screenshot_requested = ui.button(im_str!("Save screenshot (F12)"), [0.0, 0.0]);
+            });
+        if screenshot_requested {
+            self.request_screenshot();
+        }
+
+        let mut toggle_recording = false;
+        let settings = &mut self.recording_settings;
+        let recorder = &self.recorder;
+        let recording_window = imgui::Window::new(im_str!("Recording"));
+        recording_window
+            .size([250.0, 150.0], Condition::FirstUseEver)
+            .position([750.0, 440.0], Condition::FirstUseEver)
+            .build(&ui, || match recorder {
+                Some(recorder) => {
+                    let (frame, frame_count) = recorder.progress();
+                    ui.text(im_str!("Frame {} / {}", frame, frame_count));
+                    ui.text_wrapped(&im_str!("{}", recorder.path().display()));
+                    toggle_recording = ui.button(im_str!("Stop recording"), [0.0, 0.0]);
+                }
+                None => {
+                    ui.radio_button(
+                        im_str!("PNG sequence"),
+                        &mut settings.format,
+                        RecordingFormat::PngSequence,
+                    );
+                    ui.same_line(0.0);
+                    ui.radio_button(im_str!("Y4M"), &mut settings.format, RecordingFormat::Y4m);
+                    Slider::new(im_str!("Seconds"))
+                        .range(0.5..=60.0)
+                        .build(&ui, &mut settings.seconds);
+                    ui.text(im_str!("Frame rate:"));
+                    for &frame_rate in recording::FRAME_RATES.iter() {
+                        ui.same_line(0.0);
+                        ui.radio_button(
+                            &im_str!("{}", frame_rate),
+                            &mut settings.frame_rate,
+                            frame_rate,
+                        );
+                    }
+                    toggle_recording = ui.button(im_str!("Start recording"), [0.0, 0.0]);
+                }
+            });
+        if toggle_recording {
+            if self.recorder.is_some() {
+                self.stop_recording();
+            } else {
+                self.start_recording();
+            }
+        }
+
+        let overlays = &mut self.debug_overlays;
+        let view_proj = self.camera.build_view_projection_matrix();
+        let debug_window = imgui::Window::new(im_str!("Debug draw"));
+        debug_window
+            .size([200.0, 170.0], Condition::FirstUseEver)
+            .position([750.0, 0.0], Condition::FirstUseEver)
+            .build(&ui, || {
+                ui.checkbox(im_str!("World axes"), &mut overlays.axes);
+                ui.checkbox(im_str!("Ground grid"), &mut overlays.grid);
+                ui.checkbox(im_str!("Instance bounds"), &mut overlays.bounds);
+                ui.checkbox(im_str!("Light"), &mut overlays.light);
+                if overlays.frozen_frustum.is_some() {
+                    if ui.button(im_str!("Release frustum"), [0.0, 0.0]) {
+                        overlays.frozen_frustum = None;
+                    }
+                } else if ui.button(im_str!("Freeze frustum"), [0.0, 0.0]) {
+                    overlays.frozen_frustum = Some(view_proj);
+                }
+            });
+
+        self.tonemap.build_ui(ui);
+        self.post.build_ui(ui);
+    }
+}
+
+/// Loads the configured model, reporting what was replaced while loading it.
+fn load_model(config: &Config) -> ModelData {
+    let (model_data, warnings) =
+        ModelData::load(&config.model_path, config.normal_mode).expect("Failed to open model");
+    for warning in warnings {
+        eprintln!("{:?}: {}", config.model_path, warning);
     }
+    model_data
 }
+
+/// The sample counts of [`SAMPLE_COUNTS`] the adapter can render the scene with.
+///
+/// wgpu 0.7 cannot query multisampling support, but validates the sample count of each
+/// render pass against the adapter. So a pass is recorded with each count, into HDR color
+/// and depth textures, and the counts reported as errors are dropped. This replaces the
+/// uncaptured error handler of the device.
+///
+/// wgpu-core panics instead when the backend fails to create the textures, as the GL
+/// backend does over its sample limit, so these panics are caught and kept quiet.
+fn supported_sample_counts(device: &wgpu::Device) -> Vec<u32> {
+    let rejected = Arc::new(AtomicBool::new(false));
+    let error = Arc::clone(&rejected);
+    device.on_uncaptured_error(move |_| error.store(true, Ordering::Relaxed));
+
+    let create_view = |format, sample_count| {
+        device
+            .create_texture(&Texture::transient_descriptor(
+                1,
+                1,
+                format,
+                sample_count,
+                wgpu::TextureUsage::RENDER_ATTACHMENT,
+                "Sample count probe",
+            ))
+            .create_view(&wgpu::TextureViewDescriptor::default())
+    };
+
+    let record_pass = |sample_count| {
+        let color = create_view(Texture::HDR_FORMAT, sample_count);
+        let depth = create_view(Texture::DEPTH_FORMAT, sample_count);
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Sample count probe encoder"),
+        });
+        // Validated when the pass ends, the encoder is then dropped unsubmitted
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Sample count probe pass"),
+            color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
+                attachment: &color,
+                resolve_target: None,
+                ops: wgpu::Operations::default(),
+            }],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachmentDescriptor {
+                attachment: &depth,
+                depth_ops: Some(wgpu::Operations::default()),
+                stencil_ops: None,
+            }),
+        });
+    };
+
+    let panic_hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+    let supported = SAMPLE_COUNTS
+        .iter()
+        .copied()
+        .filter(|&sample_count| {
+            rejected.store(false, Ordering::Relaxed);
+            let recorded = panic::catch_unwind(AssertUnwindSafe(|| record_pass(sample_count)));
+            recorded.is_ok() && !rejected.load(Ordering::Relaxed)
+        })
+        .collect();
+    panic::set_hook(panic_hook);
+    supported
+}
+
+/// Whether an uncaptured error means the device cannot be used anymore.
+///
+/// wgpu-core wraps its `DeviceError` transparently in the error of each call, so the
+/// error chain is checked for the wrappers of the calls made every frame.
+fn is_device_loss(error: &wgpu::Error) -> bool {
+    use wgpu_core::{
+        binding_model::CreateBindGroupError,
+        command::CommandAllocatorError,
+        device::DeviceError,
+        resource::{CreateBufferError, CreateTextureError},
+    };
+
+    if let wgpu::Error::OutOfMemoryError { .. } = error {
+        return true;
+    }
+
+    let mut source: Option<&(dyn std::error::Error + 'static)> = Some(error);
+    while let Some(error) = source {
+        let device_error = error
+            .downcast_ref::<DeviceError>()
+            .or_else(|| match error.downcast_ref::<CommandAllocatorError>() {
+                Some(CommandAllocatorError::Device(e)) => Some(e),
+                _ => None,
+            })
+            .or_else(|| match error.downcast_ref::<CreateBufferError>() {
+                Some(CreateBufferError::Device(e)) => Some(e),
+                _ => None,
+            })
+            .or_else(|| match error.downcast_ref::<CreateTextureError>() {
+                Some(CreateTextureError::Device(e)) => Some(e),
+                _ => None,
+            })
+            .or_else(|| match error.downcast_ref::<CreateBindGroupError>() {
+                Some(CreateBindGroupError::Device(e)) => Some(e),
+                _ => None,
+            });
+        if let Some(device_error) = device_error {
+            return matches!(
+                device_error,
+                DeviceError::Lost | DeviceError::Invalid | DeviceError::OutOfMemory
+            );
+        }
+        source = error.source();
+    }
+    false
+}
+
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 struct Uniforms {
+    view_position: [f32; 4],
     view_proj: [[f32; 4]; 4],
+    // x: near plane, y: far plane
+    camera_planes: [f32; 4],
 }
 
 impl Uniforms {
     fn new() -> Self {
         Self {
+            view_position: [0.0; 4],
             view_proj: cgmath::Matrix4::identity().into(),
+            camera_planes: [0.0; 4],
         }
     }
 
     fn update_view_proj(&mut self, camera: &Camera) {
+        self.view_position = camera.eye.to_homogeneous().into();
         self.view_proj = camera.build_view_projection_matrix().into();
+        self.camera_planes = [camera.znear, camera.zfar, 0.0, 0.0];
     }
 }
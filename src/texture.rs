@@ -1,8 +1,8 @@
-use std::path::Path;
-
-use image::{EncodableLayout, GenericImageView, ImageResult};
+use image::{DynamicImage, EncodableLayout, GenericImageView};
 use wgpu::{Device, Queue};
 
+use crate::resource_stats::ResourceStats;
+
 pub struct Texture {
     pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
@@ -10,8 +10,12 @@ pub struct Texture {
 }
 
 impl Texture {
-    pub fn open<P: AsRef<Path>>(path: P, device: &Device, queue: &Queue) -> ImageResult<Self> {
-        let texture_image = image::open(path)?;
+    pub fn from_image(
+        texture_image: &DynamicImage,
+        device: &Device,
+        queue: &Queue,
+        stats: &mut ResourceStats,
+    ) -> Self {
         let texture_dimensions = texture_image.dimensions();
         let texture_rgba = texture_image.to_rgba8();
 
@@ -20,15 +24,18 @@ impl Texture {
             height: texture_dimensions.1,
             depth: 1,
         };
-        let texture = device.create_texture(&wgpu::TextureDescriptor {
-            size: texture_size,
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
-            usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
-            label: Some("Texture"),
-        });
+        let texture = stats.create_texture(
+            device,
+            &wgpu::TextureDescriptor {
+                size: texture_size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rgba8UnormSrgb,
+                usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
+                label: Some("Texture"),
+            },
+        );
 
         queue.write_texture(
             wgpu::TextureCopyView {
@@ -57,18 +64,17 @@ impl Texture {
             ..Default::default()
         });
 
-        Ok(Texture {
+        Texture {
             texture,
             view: texture_view,
             sampler: texture_sampler,
-        })
+        }
     }
 
-    pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float; // 1.
-
-    pub fn create_depth_texture(
+    pub fn create_render_target(
         device: &wgpu::Device,
         sc_desc: &wgpu::SwapChainDescriptor,
+        format: wgpu::TextureFormat,
         label: &str,
     ) -> Self {
         let size = wgpu::Extent3d {
@@ -76,16 +82,108 @@ impl Texture {
             height: sc_desc.height,
             depth: 1,
         };
-        let desc = wgpu::TextureDescriptor {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
             label: Some(label),
             size,
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: Self::DEPTH_FORMAT,
-            usage: wgpu::TextureUsage::RENDER_ATTACHMENT | wgpu::TextureUsage::SAMPLED,
-        };
-        let texture = device.create_texture(&desc);
+            format,
+            usage: wgpu::TextureUsage::RENDER_ATTACHMENT
+                | wgpu::TextureUsage::SAMPLED
+                | wgpu::TextureUsage::COPY_SRC,
+        });
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Self {
+            texture,
+            view,
+            sampler,
+        }
+    }
+
+    /// Creates a render graph texture, sampled with linear filtering.
+    pub fn create_transient(
+        device: &wgpu::Device,
+        stats: &mut ResourceStats,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+        usage: wgpu::TextureUsage,
+        label: &str,
+    ) -> Self {
+        let texture = stats.create_texture(
+            device,
+            &Self::transient_descriptor(width, height, format, sample_count, usage, label),
+        );
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Self {
+            texture,
+            view,
+            sampler,
+        }
+    }
+
+    /// Descriptor of [`Texture::create_transient`], to release the texture from the stats.
+    pub fn transient_descriptor(
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+        usage: wgpu::TextureUsage,
+        label: &str,
+    ) -> wgpu::TextureDescriptor<'_> {
+        wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth: 1,
+            },
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage,
+        }
+    }
+
+    pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float; // 1.
+    /// Depth format of the passes that also need a stencil.
+    pub const DEPTH_STENCIL_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth24PlusStencil8;
+    pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+    /// Instance index + 1 and mesh index of the closest surface, 0 meaning the background.
+    pub const OBJECT_ID_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rg32Uint;
+
+    pub fn create_shadow_map(
+        device: &wgpu::Device,
+        stats: &mut ResourceStats,
+        resolution: u32,
+        label: &str,
+    ) -> Self {
+        let texture = stats.create_texture(device, &Self::shadow_map_descriptor(resolution, label));
 
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
@@ -107,4 +205,21 @@ impl Texture {
             sampler,
         }
     }
+
+    /// Descriptor of [`Texture::create_shadow_map`], to release the texture from the stats.
+    pub fn shadow_map_descriptor(resolution: u32, label: &str) -> wgpu::TextureDescriptor<'_> {
+        wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: resolution,
+                height: resolution,
+                depth: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::DEPTH_FORMAT,
+            usage: wgpu::TextureUsage::RENDER_ATTACHMENT | wgpu::TextureUsage::SAMPLED,
+        }
+    }
 }
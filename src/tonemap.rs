@@ -0,0 +1,254 @@
+use std::cell::RefCell;
+
+use imgui::{im_str, ComboBox, Condition, Slider};
+
+use crate::{render_graph::PoolTextureId, resource_stats::ResourceStats, texture::Texture};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TonemapOperator {
+    Reinhard,
+    Aces,
+    Exposure,
+}
+
+impl TonemapOperator {
+    const ALL: [TonemapOperator; 3] = [
+        TonemapOperator::Reinhard,
+        TonemapOperator::Aces,
+        TonemapOperator::Exposure,
+    ];
+
+    // Must match the OPERATOR_* constants in tonemap.frag
+    fn shader_index(self) -> u32 {
+        match self {
+            TonemapOperator::Reinhard => 0,
+            TonemapOperator::Aces => 1,
+            TonemapOperator::Exposure => 2,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct TonemapParams {
+    operator: u32,
+    exposure: f32,
+}
+
+/// Fullscreen pass mapping the HDR scene color into the displayable range.
+pub struct TonemapPass {
+    pub operator: TonemapOperator,
+    pub exposure: f32,
+
+    params_buffer: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    // Of the last HDR texture, the graph gives the same one until it is resized
+    bind_group: RefCell<Option<(PoolTextureId, wgpu::BindGroup)>>,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl TonemapPass {
+    pub fn new(
+        device: &wgpu::Device,
+        stats: &mut ResourceStats,
+        output_format: wgpu::TextureFormat,
+    ) -> Self {
+        let operator = TonemapOperator::Aces;
+        let exposure = 1.0;
+
+        let params_buffer = stats.create_buffer_init(
+            device,
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Tonemap params buffer"),
+                contents: bytemuck::cast_slice(&[TonemapParams {
+                    operator: operator.shader_index(),
+                    exposure,
+                }]),
+                usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+            },
+        );
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler {
+                        comparison: false,
+                        filtering: true,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("Tonemap bind group layout"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Tonemap pipeline layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let vs_module = device.create_shader_module(&wgpu::include_spirv!(concat!(
+            env!("OUT_DIR"),
+            "/fullscreen.vert.spv"
+        )));
+        let fs_module = device.create_shader_module(&wgpu::include_spirv!(concat!(
+            env!("OUT_DIR"),
+            "/tonemap.frag.spv"
+        )));
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Tonemap pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &vs_module,
+                entry_point: "main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &fs_module,
+                entry_point: "main",
+                targets: &[wgpu::ColorTargetState {
+                    format: output_format,
+                    color_blend: wgpu::BlendState::REPLACE,
+                    alpha_blend: wgpu::BlendState::REPLACE,
+                    write_mask: wgpu::ColorWrite::ALL,
+                }],
+            }),
+            depth_stencil: None,
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: wgpu::CullMode::None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+            },
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+        });
+
+        TonemapPass {
+            operator,
+            exposure,
+
+            params_buffer,
+            bind_group_layout,
+            bind_group: RefCell::new(None),
+            pipeline,
+        }
+    }
+
+    fn create_bind_group(&self, device: &wgpu::Device, hdr_texture: &Texture) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&hdr_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&hdr_texture.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Buffer {
+                        buffer: &self.params_buffer,
+                        offset: 0,
+                        size: None,
+                    },
+                },
+            ],
+            label: Some("Tonemap bind group"),
+        })
+    }
+
+    pub fn update(&self, queue: &wgpu::Queue) {
+        let params = TonemapParams {
+            operator: self.operator.shader_index(),
+            exposure: self.exposure,
+        };
+        queue.write_buffer(&self.params_buffer, 0, bytemuck::cast_slice(&[params]));
+    }
+
+    pub fn encode(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        (hdr_id, hdr_texture): (PoolTextureId, &Texture),
+        output: &wgpu::TextureView,
+    ) {
+        let mut cache = self.bind_group.borrow_mut();
+        if cache.as_ref().is_some_and(|(id, _)| *id != hdr_id) {
+            *cache = None;
+        }
+        let (_, bind_group) =
+            cache.get_or_insert_with(|| (hdr_id, self.create_bind_group(device, hdr_texture)));
+
+        let mut tonemap_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Tonemap render pass"),
+            color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
+                attachment: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+
+        tonemap_pass.set_pipeline(&self.pipeline);
+        tonemap_pass.set_bind_group(0, bind_group, &[]);
+        tonemap_pass.draw(0..3, 0..1);
+    }
+
+    pub fn build_ui(&mut self, ui: &imgui::Ui) {
+        let operator = &mut self.operator;
+        let exposure = &mut self.exposure;
+        let window = imgui::Window::new(im_str!("Tonemapping"));
+        window
+            .size([250.0, 80.0], Condition::FirstUseEver)
+            .position([250.0, 100.0], Condition::FirstUseEver)
+            .build(&ui, || {
+                let mut operator_index = TonemapOperator::ALL
+                    .iter()
+                    .position(|o| o == operator)
+                    .unwrap_or(0);
+                if ComboBox::new(im_str!("Operator")).build_simple_string(
+                    &ui,
+                    &mut operator_index,
+                    &[im_str!("Reinhard"), im_str!("ACES"), im_str!("Exposure")],
+                ) {
+                    *operator = TonemapOperator::ALL[operator_index];
+                }
+                Slider::new(im_str!("Exposure"))
+                    .range(0.05..=8.0)
+                    .build(&ui, exposure);
+            });
+    }
+}
@@ -0,0 +1,27 @@
+//! Runs the golden-image comparison of the renderer binary.
+//!
+//! Ignored by default: it needs a Vulkan implementation (lavapipe or SwiftShader in CI),
+//! the model assets in `res/` and the reference images in `res/golden`, which are
+//! generated with `cargo run -- --update-golden`. Run it with `cargo test -- --ignored`.
+
+use std::process::Command;
+
+#[test]
+#[ignore]
+fn golden_images_match() {
+    let manifest_dir = env!("CARGO_MANIFEST_DIR");
+    let output = Command::new(env!("CARGO_BIN_EXE_wgpu_learning"))
+        .arg("--golden")
+        .current_dir(manifest_dir)
+        .output()
+        .expect("Failed to run the renderer");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "golden comparison failed, rendered and diff images are in {}/target/golden\n{}{}",
+        manifest_dir,
+        stdout,
+        String::from_utf8_lossy(&output.stderr)
+    );
+}